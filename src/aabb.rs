@@ -2,7 +2,7 @@ use std::{cmp::Ordering, mem::swap};
 
 use serde::{Deserialize, Serialize};
 
-use crate::{util::{Interval, Point, random_int}, renderable::{Renderable, RenderableList, Object}, sphere::Sphere};
+use crate::{util::{Interval, Point}, renderable::{Renderable, Object}};
 use crate::ray::Ray;
 use crate::renderable::HitRecord;
 
@@ -89,7 +89,7 @@ impl AABB {
                 return (false, HitRecord::nothing());
             }
         }
-        return (true, HitRecord::nothing());
+        (true, HitRecord::nothing())
     }
 }
 
@@ -103,66 +103,50 @@ impl Renderable for AABB {
 pub struct BvhNode {
     pub bbox: Object,
     pub left: Option<Box<BvhNode>>,
-    pub right: Option<Box<BvhNode>>
+    pub right: Option<Box<BvhNode>>,
+    // position of a leaf's object in the list the tree was built from
+    pub object_id: usize,
 }
 
 impl BvhNode {
 
     fn root() -> BvhNode {
-        BvhNode { bbox: Object::empty(), left: None, right: None }
+        BvhNode { bbox: Object::empty(), left: None, right: None, object_id: 0 }
     }
 
-    pub fn new_from_renderables(list: &Vec<Object>) -> BvhNode {
-        let root = Self::new_from_renderables_with_index(list, 0, list.len());
+    pub fn new_from_renderables(list: &[Object]) -> BvhNode {
+        // objects are shuffled around while building, so each one carries its position in `list` as its id
+        let mut indexed: Vec<(usize, Object)> = list.iter().cloned().enumerate().collect();
+        let root = Self::new_from_indexed_renderables(&mut indexed);
         match root {
             Some(node) => *node,
             None => Self::root()
         }
     }
 
-    fn new_from_renderables_with_index(list: &Vec<Object>, start: usize, end: usize)-> Option<Box<BvhNode>>{
-        // eprintln!("start: {} end: {} len {}", start, end, list.len());
-        let axis = random_int(0, 2);
+    // a tree over objects that already have their ids, None for an empty list
+    pub fn new_from_indexed(list: &mut [(usize, Object)]) -> Option<BvhNode> {
+        Self::new_from_indexed_renderables(list).map(|node| *node)
+    }
 
-        let object_span = end - start;
-        // base case - just make two leaf nodes for the same object
-        if object_span == 1 {
-            return Some(Box::new(Self {
-                bbox: list[start],
+    fn new_from_indexed_renderables(list: &mut [(usize, Object)]) -> Option<Box<BvhNode>> {
+        // base case - a single object becomes a leaf
+        if list.len() <= 1 {
+            return list.first().map(|(object_id, object)| Box::new(Self {
+                bbox: object.clone(),
                 left: None,
                 right: None,
+                object_id: *object_id,
             }));
         }
-        // two, assign accordingly
-        else if object_span == 2 {
-            // if the first is < the second, build tree as:
-            //              root
-            //              /  \
-            //         first    second
-            let first = Some(Box::new(Self {
-                bbox: list[start],
-                left: None,
-                right: None
-            }));
-            let second = Some(Box::new(Self {
-                bbox: list[start + 1],
-                left: None,
-                right: None,
-            }));
-            let parent_bbox = AABB::new_from_bbox(list[start].bounding_box(), list[start + 1].bounding_box());
-            // TODO: since we only use bbox for checking ANY child node, no need for ordering here.
-            return Some(Box::new(Self {
-                bbox: Object::AABB(parent_bbox),
-                left: first,
-                right: second
-            }));
-        }
-        // Copies the entire list of renderables - not the best performance wise, but slicing accordingly gave 
-        // weird index OOB errors for SPECIFICALLY when the original list passed in was >= 6 objects ???
-        let rec_list = list[..].to_vec();
-        let mid = start + (f32::round(object_span as f32 / 2.0)) as usize;
-        let left = Self::new_from_renderables_with_index(&rec_list, start, mid);
-        let right = Self::new_from_renderables_with_index(&rec_list, mid, end);
+        // split in half along whichever axis the objects are spread out the most on, so the same scene
+        // always gives the same tree
+        let axis = Self::longest_axis(list);
+        list.sort_by(|a, b| Self::box_compare(&a.1, &b.1, axis));
+        let mid = (f32::round(list.len() as f32 / 2.0)) as usize;
+        let (left_list, right_list) = list.split_at_mut(mid);
+        let left = Self::new_from_indexed_renderables(left_list);
+        let right = Self::new_from_indexed_renderables(right_list);
         let bbox = match (&left, &right) {
             (Some(node_l), Some(node_r)) => AABB::new_from_bbox(node_l.bounding_box(), node_r.bounding_box()),
             (Some(node_l), None) => node_l.bounding_box(),
@@ -170,56 +154,30 @@ impl BvhNode {
             _ => AABB::empty()
         };
         Some(Box::new(Self {
-            bbox: Object::AABB(bbox), left, right
+            bbox: Object::AABB(bbox), left, right, object_id: 0
         }))
-        
     }
 
-    fn box_compare(a: &Object, b: &Object, axis_index: i32) -> Ordering {
-       let result = a.bounding_box().axis(axis_index).min <= b.bounding_box().axis(axis_index).min;
-       match result {
-        true => Ordering::Less,
-        false => Ordering::Greater
-       }
-    }
-
-    fn box_x_compare(a: &Object, b: &Object) -> Ordering {
-        Self::box_compare(a, b, 0)
-    }
-    fn box_y_compare(a: &Object, b: &Object) -> Ordering {
-        Self::box_compare(a, b, 1)
-    }
-    fn box_z_compare(a: &Object, b: &Object) -> Ordering {
-        Self::box_compare(a, b, 2)
-    }
-
-    fn hit_aabb(&self, aabb: AABB, ray: &Ray, interval: Interval) -> (bool, HitRecord) {
-        let (did_hit, hit_rec) = aabb.hit(ray, interval);
-        if !did_hit {
-            return (false, HitRecord::nothing());
-        }
-        let (did_hit_left, left_hit_rec) = match &self.left {
-            Some(node) => node.hit(ray, interval),
-            None => (did_hit, hit_rec)
-        };
-        let right_interval_max = if did_hit_left { left_hit_rec.t } else { interval.max };
-        let (did_hit_right, right_hit_rec) = match &self.right {
-            Some(node) => node.hit(ray, Interval { min: interval.min, max: right_interval_max}),
-            None => (did_hit, hit_rec.clone())
-        };
-        if did_hit_left {
-            return (did_hit_left, left_hit_rec);
+    // index (as used by `AABB::axis`) of the longest side of the box around every object in the list
+    fn longest_axis(list: &[(usize, Object)]) -> i32 {
+        let bbox = list[1..].iter().fold(list[0].1.bounding_box(), |bbox, (_, object)| {
+            AABB::new_from_bbox(bbox, object.bounding_box())
+        });
+        let extent = |axis: i32| bbox.axis(axis).max - bbox.axis(axis).min;
+        let mut longest = 1;
+        for axis in 2..=3 {
+            if extent(axis) > extent(longest) {
+                longest = axis;
+            }
         }
-        (did_hit_right, right_hit_rec)
+        longest
     }
 
-    fn hit_sphere(&self, sphere: Sphere, ray: &Ray, interval: Interval) -> (bool, HitRecord) {
-        let (did_hit_aabb, hit_rec) = sphere.bbox.hit(ray, interval);
-        if did_hit_aabb {
-            let (hit_sphere, sphere_hit_rec) = sphere.hit(ray, interval);
-            return (hit_sphere, sphere_hit_rec);
-        }
-        (did_hit_aabb, hit_rec)
+    // a total order (ties compare equal), sorting with anything else can panic
+    fn box_compare(a: &Object, b: &Object, axis_index: i32) -> Ordering {
+        let a_min = a.bounding_box().axis(axis_index).min;
+        let b_min = b.bounding_box().axis(axis_index).min;
+        a_min.total_cmp(&b_min)
     }
 }
 
@@ -230,20 +188,20 @@ impl Renderable for BvhNode {
         let mut closest_yet = interval.max;
 
         let mut to_check = vec![self];
-        while to_check.len() > 0 {
+        while !to_check.is_empty() {
             let mut new_to_check: Vec<&BvhNode> = vec![];
             for node in &to_check[..] {
                 let new_interval = Interval {min: interval.min, max: closest_yet};
-                match node.bbox {
+                match &node.bbox {
                     Object::AABB(aabb) => {
                         let (did_hit, _hit_rec) = aabb.hit(ray, new_interval);
                         if did_hit {
                             match &node.left {
-                                Some(n) => new_to_check.push(&n),
+                                Some(n) => new_to_check.push(n),
                                 None => eprint!("Error: reached AABB without Sphere child node")
                             }
                             match &node.right {
-                                Some(n) => new_to_check.push(&n),
+                                Some(n) => new_to_check.push(n),
                                 None => eprint!("Error: reached AABB without Sphere child node")
                             }
                         }
@@ -254,6 +212,7 @@ impl Renderable for BvhNode {
                             closest_yet = hit_rec.t;
                             hit_anything = true;
                             final_rec = hit_rec;
+                            final_rec.object_id = node.object_id;
                         }
                     }
                 };
@@ -264,13 +223,61 @@ impl Renderable for BvhNode {
     }
 
     fn bounding_box(&self) -> AABB {
-        match self.bbox {
-            Object::AABB(aabb) => aabb,
+        match &self.bbox {
+            Object::AABB(aabb) => *aabb,
             Object::Sphere(sphere) => sphere.bbox
         }
     }
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::material::{LambertianMaterial, RenderableMaterial};
+    use crate::texture::{RenderableTexture, SolidColor};
+    use crate::sphere::Sphere;
+    use crate::util::Vec3;
+
+    fn spheres() -> Vec<Object> {
+        let material = RenderableMaterial::Lambertian(LambertianMaterial::new(RenderableTexture::SolidColor(
+            SolidColor::from_values(0.5, 0.5, 0.5),
+        )));
+        // listed out of order along x, so building the tree has to reorder them
+        [3.0, -6.0, 9.0, 0.0, -3.0]
+            .iter()
+            .map(|x| Object::Sphere(Sphere::new(Point::new(*x, 0.0, 0.0), 1.0, material)))
+            .collect()
+    }
+
+    fn leaf_ids(node: &BvhNode, ids: &mut Vec<usize>) {
+        match (&node.left, &node.right) {
+            (None, None) => ids.push(node.object_id),
+            (left, right) => {
+                for child in [left, right].into_iter().flatten() {
+                    leaf_ids(child, ids);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn bvh_is_split_along_longest_axis() {
+        let mut ids = vec![];
+        leaf_ids(&BvhNode::new_from_renderables(&spheres()), &mut ids);
+        // left to right along x
+        assert_eq!(ids, vec![1, 4, 3, 0, 2]);
+    }
+
+    #[test]
+    fn bvh_hits_report_position_in_original_list() {
+        let root = BvhNode::new_from_renderables(&spheres());
+        let ray = Ray::new(Point::new(9.0, 5.0, 0.0), Vec3::new(0.0, -1.0, 0.0));
+        let (did_hit, hit_rec) = root.hit(&ray, Interval { min: 0.001, max: f32::INFINITY });
+        assert!(did_hit);
+        assert_eq!(hit_rec.object_id, 2);
+    }
+}
+
 // TODO: write tests for this file!
 // #[cfg(test)]
 // mod tests {
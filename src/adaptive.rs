@@ -0,0 +1,126 @@
+use serde::{Deserialize, Serialize};
+
+// means below this are treated as this dark, otherwise near black pixels could never converge
+const MIN_LUMINANCE: f32 = 0.01;
+
+/**
+ * Adaptive sampling settings, set on the scene (or the render options) in place of a fixed samples_per_pixel.
+ *
+ * Every pixel gets at least `min_samples`. After that it keeps being sampled until the standard error of
+ * its mean luminance is within `threshold` of the mean itself (0.01 = 1% noise), or `max_samples` is reached.
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AdaptiveSampling {
+    pub min_samples: i32,
+    pub max_samples: i32,
+    pub threshold: f32,
+}
+
+impl Default for AdaptiveSampling {
+    fn default() -> Self {
+        Self {
+            min_samples: 16,
+            max_samples: 256,
+            threshold: 0.01,
+        }
+    }
+}
+
+impl AdaptiveSampling {
+    // the variance estimate needs at least two samples, and max can't be below min
+    pub fn sample_range(&self) -> (i32, i32) {
+        let min_samples = i32::max(self.min_samples, 2);
+        (min_samples, i32::max(self.max_samples, min_samples))
+    }
+
+    pub fn is_converged(&self, estimate: &PixelEstimate) -> bool {
+        let (min_samples, max_samples) = self.sample_range();
+        let count = estimate.count() as i32;
+        count >= max_samples || (count >= min_samples && estimate.relative_error() <= self.threshold)
+    }
+}
+
+// running mean and variance of one pixel's luminance, using Welford's algorithm
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct PixelEstimate {
+    count: u32,
+    mean: f32,
+    // sum of squared differences from the mean
+    m2: f32,
+}
+
+impl PixelEstimate {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add(&mut self, luminance: f32) {
+        self.count += 1;
+        let delta = luminance - self.mean;
+        self.mean += delta / self.count as f32;
+        self.m2 += delta * (luminance - self.mean);
+    }
+
+    pub fn count(&self) -> u32 {
+        self.count
+    }
+
+    pub fn mean(&self) -> f32 {
+        self.mean
+    }
+
+    // unbiased variance of the samples divided by their count, i.e. how far the mean is likely off
+    pub fn variance_of_mean(&self) -> f32 {
+        if self.count < 2 {
+            return f32::INFINITY;
+        }
+        let n = self.count as f32;
+        self.m2 / (n - 1.0) / n
+    }
+
+    // standard error of the mean, relative to the mean
+    pub fn relative_error(&self) -> f32 {
+        f32::sqrt(self.variance_of_mean()) / f32::max(self.mean, MIN_LUMINANCE)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn estimate_matches_direct_computation() {
+        let mut estimate = PixelEstimate::new();
+        for value in [1.0, 3.0, 1.0, 3.0] {
+            estimate.add(value);
+        }
+        assert_eq!(estimate.count(), 4);
+        assert!(f32::abs(estimate.mean() - 2.0) < 1e-6);
+        // sample variance of 1, 3, 1, 3 is 4/3, and there are 4 samples
+        assert!(f32::abs(estimate.variance_of_mean() - 1.0 / 3.0) < 1e-6);
+    }
+
+    #[test]
+    fn constant_pixels_stop_at_min_samples() {
+        let adaptive = AdaptiveSampling { min_samples: 4, max_samples: 64, threshold: 0.01 };
+        let mut estimate = PixelEstimate::new();
+        for _ in 0..3 {
+            estimate.add(0.5);
+            assert!(!adaptive.is_converged(&estimate));
+        }
+        estimate.add(0.5);
+        assert!(adaptive.is_converged(&estimate));
+    }
+
+    #[test]
+    fn noisy_pixels_stop_at_max_samples() {
+        let adaptive = AdaptiveSampling { min_samples: 4, max_samples: 8, threshold: 0.01 };
+        let mut estimate = PixelEstimate::new();
+        for i in 0..8 {
+            assert!(!adaptive.is_converged(&estimate));
+            estimate.add(if i % 2 == 0 { 0.0 } else { 1.0 });
+        }
+        assert!(adaptive.is_converged(&estimate));
+    }
+}
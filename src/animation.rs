@@ -0,0 +1,195 @@
+use std::ops::{Add, Mul, Sub};
+
+use serde::{Deserialize, Serialize};
+
+use crate::camera::{CameraDescription, Shutter};
+use crate::util::Point;
+
+/**
+ * Turns a scene into a sequence of frames, numbered from 1. Frame n starts `(n - 1) * frame_time` into the scene's
+ * time and the camera's shutter opens and closes that much later than it's written, so objects keyframed in the
+ * same time carry on moving from one frame to the next.
+ *
+ * The camera follows `camera_keys`. Each setting is interpolated between the keys that have it and held before the
+ * first and after the last of them, settings no key has stay the way the scene's camera has them.
+ */
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Animation {
+    pub frames: u32,
+    #[serde(default = "default_frame_time")]
+    pub frame_time: f32,
+    #[serde(default)]
+    pub interpolation: Interpolation,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub camera_keys: Vec<CameraKeyframe>,
+}
+
+fn default_frame_time() -> f32 {
+    1.0
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Default)]
+pub enum Interpolation {
+    // straight from key to key, which turns sharply at every key
+    #[default]
+    Linear,
+    // a curve through every key (a Catmull-Rom spline) that doesn't change speed or direction suddenly
+    Smooth,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct CameraKeyframe {
+    // doesn't have to be a whole number, keys have to be in order
+    pub frame: f32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub look_from: Option<Point>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub look_at: Option<Point>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub vfov: Option<f32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub focus_distance: Option<f32>,
+}
+
+impl Animation {
+    // when in the scene's time `frame` starts
+    pub fn frame_start(&self, frame: u32) -> f32 {
+        (frame as f32 - 1.0) * self.frame_time
+    }
+
+    // `camera` as it is in `frame`
+    pub fn camera_at(&self, camera: &CameraDescription, frame: u32) -> CameraDescription {
+        let mut description = camera.clone();
+        let at = frame as f32;
+        if let Some(look_from) = self.channel(at, |key| key.look_from) {
+            description.look_from = look_from;
+        }
+        if let Some(look_at) = self.channel(at, |key| key.look_at) {
+            description.look_at = look_at;
+        }
+        if let Some(vfov) = self.channel(at, |key| key.vfov) {
+            description.vfov = vfov;
+        }
+        if let Some(focus_distance) = self.channel(at, |key| key.focus_distance) {
+            description.focus_distance = Some(focus_distance);
+        }
+        let start = self.frame_start(frame);
+        description.shutter = Shutter { open: camera.shutter.open + start, close: camera.shutter.close + start };
+        description
+    }
+
+    // one setting at `frame`, None when no key has it
+    fn channel<T, F>(&self, frame: f32, setting: F) -> Option<T>
+    where
+        T: Copy + Add<Output = T> + Sub<Output = T> + Mul<f32, Output = T>,
+        F: Fn(&CameraKeyframe) -> Option<T>,
+    {
+        let keys: Vec<(f32, T)> = self.camera_keys.iter().filter_map(|key| setting(key).map(|value| (key.frame, value))).collect();
+        let (first, last) = (keys.first()?, keys.last()?);
+        if frame <= first.0 {
+            return Some(first.1);
+        }
+        if frame >= last.0 {
+            return Some(last.1);
+        }
+        // the first key after `frame`, there is one since frame is before the last key
+        let next = keys.iter().position(|key| key.0 > frame).unwrap_or(keys.len() - 1);
+        let (a, b) = (keys[next - 1], keys[next]);
+        let s = (frame - a.0) / (b.0 - a.0);
+        Some(match self.interpolation {
+            Interpolation::Linear => a.1 + (b.1 - a.1) * s,
+            Interpolation::Smooth => {
+                // a cubic Hermite curve with each key's tangent pointing from the key before it to the one after,
+                // in change per frame, and scaled to the length of this stretch
+                let tangent = |index: usize| {
+                    let (before, after) = (keys[index.saturating_sub(1)], keys[usize::min(index + 1, keys.len() - 1)]);
+                    (after.1 - before.1) * (1.0 / (after.0 - before.0))
+                };
+                let length = b.0 - a.0;
+                let (s2, s3) = (s * s, s * s * s);
+                a.1 * (2.0 * s3 - 3.0 * s2 + 1.0)
+                    + tangent(next - 1) * ((s3 - 2.0 * s2 + s) * length)
+                    + b.1 * (3.0 * s2 - 2.0 * s3)
+                    + tangent(next) * ((s3 - s2) * length)
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::Vec3;
+
+    fn key(frame: f32, look_from: Option<Point>, vfov: Option<f32>) -> CameraKeyframe {
+        CameraKeyframe { frame, look_from, look_at: None, vfov, focus_distance: None }
+    }
+
+    fn close(a: Vec3, b: Vec3) -> bool {
+        (a - b).len() < 1e-4
+    }
+
+    fn camera() -> CameraDescription {
+        let camera = crate::camera::Camera::new(
+            Point::new(0.0, 0.0, 10.0),
+            Point::new(0.0, 0.0, 0.0),
+            Vec3::new(0.0, 1.0, 0.0),
+            40.0,
+            1.0,
+            0.0,
+            10.0,
+        );
+        camera.description().clone()
+    }
+
+    #[test]
+    fn settings_are_interpolated_between_the_keys_that_have_them() {
+        let animation = Animation {
+            frames: 30,
+            frame_time: 0.5,
+            interpolation: Interpolation::Linear,
+            camera_keys: vec![
+                key(1.0, Some(Point::new(0.0, 0.0, 10.0)), None),
+                key(11.0, None, Some(20.0)),
+                key(21.0, Some(Point::new(10.0, 0.0, 10.0)), Some(60.0)),
+            ],
+        };
+        let base = camera();
+        let frame = animation.camera_at(&base, 16);
+        assert!(close(frame.look_from, Point::new(7.5, 0.0, 10.0)));
+        assert!(f32::abs(frame.vfov - 40.0) < 1e-4);
+        // look_at isn't keyed, and the last keys are held
+        assert_eq!(frame.look_at, base.look_at);
+        let last = animation.camera_at(&base, 30);
+        assert!(close(last.look_from, Point::new(10.0, 0.0, 10.0)) && last.vfov == 60.0);
+        assert_eq!(animation.camera_at(&base, 1).vfov, 20.0);
+
+        // frame 5 starts 2 units of time in, the shutter moves along with it
+        let shutter = animation.camera_at(&base, 5).shutter;
+        assert_eq!((shutter.open, shutter.close), (2.0, 3.0));
+    }
+
+    #[test]
+    fn smooth_paths_go_through_every_key() {
+        // a square around the origin, the smooth path rounds off its corners
+        let corners = [(1.0, 1.0), (-1.0, 1.0), (-1.0, -1.0), (1.0, -1.0), (1.0, 1.0)];
+        let animation = Animation {
+            frames: 41,
+            frame_time: 1.0,
+            interpolation: Interpolation::Smooth,
+            camera_keys: corners
+                .iter()
+                .enumerate()
+                .map(|(i, (x, z))| key(1.0 + 10.0 * i as f32, Some(Point::new(*x, 0.0, *z)), None))
+                .collect(),
+        };
+        let base = camera();
+        for (i, (x, z)) in corners.iter().enumerate() {
+            let frame = 1 + 10 * i as u32;
+            assert!(close(animation.camera_at(&base, frame).look_from, Point::new(*x, 0.0, *z)));
+        }
+        // halfway along the second side it bulges out past the square
+        let halfway = animation.camera_at(&base, 16).look_from;
+        assert!(halfway.x() < -1.0 && f32::abs(halfway.z()) < 1e-4);
+    }
+}
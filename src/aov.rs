@@ -0,0 +1,298 @@
+use serde::{Deserialize, Serialize};
+
+use crate::framebuffer::FrameBuffer;
+use crate::tonemap::luminance;
+use crate::util::{Color, Vec3};
+
+/**
+ * Arbitrary output variables: extra passes the integrator can fill in next to the beauty pass.
+ *
+ * The lighting passes split the beauty pass by how many bounces a path took before reaching the sky
+ * (the only light source): `Emission` is sky seen straight from the camera, `Direct` is sky reached
+ * after one bounce and `Indirect` is everything after that, so emission + direct + indirect = beauty.
+ *
+ * `Variance` is the estimated variance of each pixel's mean luminance, i.e. how noisy the beauty pass still is.
+ * `SampleCount` is how many camera samples went into each pixel, which only varies with adaptive sampling.
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub enum Aov {
+    Albedo,
+    Normal,
+    Depth,
+    ObjectId,
+    MaterialId,
+    Direct,
+    Indirect,
+    Emission,
+    Variance,
+    SampleCount,
+}
+
+impl Aov {
+    pub const ALL: [Aov; 10] = [
+        Aov::Albedo,
+        Aov::Normal,
+        Aov::Depth,
+        Aov::ObjectId,
+        Aov::MaterialId,
+        Aov::Direct,
+        Aov::Indirect,
+        Aov::Emission,
+        Aov::Variance,
+        Aov::SampleCount,
+    ];
+
+    // used for EXR layer names and file name suffixes
+    pub fn name(&self) -> &'static str {
+        match self {
+            Aov::Albedo => "albedo",
+            Aov::Normal => "normal",
+            Aov::Depth => "depth",
+            Aov::ObjectId => "object_id",
+            Aov::MaterialId => "material_id",
+            Aov::Direct => "direct",
+            Aov::Indirect => "indirect",
+            Aov::Emission => "emission",
+            Aov::Variance => "variance",
+            Aov::SampleCount => "sample_count",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Aov> {
+        Aov::ALL.iter().copied().find(|aov| aov.name() == name)
+    }
+
+    // passes holding light, as opposed to data like normals or ids
+    pub fn is_lighting(&self) -> bool {
+        matches!(self, Aov::Direct | Aov::Indirect | Aov::Emission)
+    }
+
+    // identifiers can't be averaged, so they come from the first sample of a pixel only
+    fn is_identifier(&self) -> bool {
+        matches!(self, Aov::ObjectId | Aov::MaterialId)
+    }
+
+    // this pass's value for one camera sample, None if the sample shouldn't count towards the pixel
+    pub fn sample_value(&self, sample: &PathSample) -> Option<Color> {
+        // ids are stored off by one so that 0 can mean "nothing was hit"
+        let id = |id: Option<usize>| {
+            let value = id.map(|id| (id + 1) as f32).unwrap_or(0.0);
+            Color::new(value, value, value)
+        };
+        match self {
+            Aov::Albedo => Some(sample.albedo),
+            Aov::Normal => Some(sample.normal),
+            Aov::Depth => sample.depth.map(|depth| Color::new(depth, depth, depth)),
+            Aov::ObjectId => Some(id(sample.object_id)),
+            Aov::MaterialId => Some(id(sample.material_id)),
+            Aov::Direct => Some(sample.direct),
+            Aov::Indirect => Some(sample.indirect),
+            Aov::Emission => Some(sample.emission),
+            Aov::Variance => Some(sample.radiance),
+            // counted in `AovAccumulator::value`, there's nothing to add up
+            Aov::SampleCount => Some(Color::zero()),
+        }
+    }
+
+    /**
+     * Turns the raw pass into something viewable for 8/16 bit formats: normals are remapped from [-1, 1],
+     * depth is normalized by the farthest hit, ids get a distinct color each and sample counts become a
+     * heatmap running from blue (fewest) to red (most). Lighting passes are returned as they are.
+     */
+    pub fn visualize(&self, buffer: &FrameBuffer) -> FrameBuffer {
+        let mut visualized = FrameBuffer::new(buffer.width(), buffer.height());
+        let max_value = buffer.pixels().iter().map(|p| p.x()).fold(0.0, f32::max);
+        for y in 0..buffer.height() {
+            for x in 0..buffer.width() {
+                let value = buffer.get(x, y);
+                let color = match self {
+                    Aov::Normal => 0.5 * (value + Color::new(1.0, 1.0, 1.0)),
+                    Aov::Depth if max_value > 0.0 => value / max_value,
+                    Aov::SampleCount if max_value > 0.0 => heatmap_color(value.x() / max_value),
+                    Aov::ObjectId | Aov::MaterialId => id_color(value.x() as u32),
+                    _ => value,
+                };
+                visualized.set(x, y, color);
+            }
+        }
+        visualized
+    }
+}
+
+// stable, well spread color for an id, black for "nothing"
+fn id_color(id: u32) -> Color {
+    if id == 0 {
+        return Color::zero();
+    }
+    let hash = id.wrapping_mul(0x9e37_79b9) ^ (id >> 7);
+    Color::new(
+        ((hash >> 8) & 0xff) as f32 / 255.0,
+        ((hash >> 16) & 0xff) as f32 / 255.0,
+        ((hash >> 24) & 0xff) as f32 / 255.0,
+    )
+}
+
+// blue -> cyan -> green -> yellow -> red as t goes from 0 to 1
+fn heatmap_color(t: f32) -> Color {
+    let t = t.clamp(0.0, 1.0);
+    Color::new(
+        (2.0 * t - 0.5).clamp(0.0, 1.0),
+        1.0 - (2.0 * (t - 0.5)).abs().powi(2),
+        (1.5 - 2.0 * t).clamp(0.0, 1.0),
+    )
+}
+
+// everything the integrator learned from one camera sample
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PathSample {
+    pub radiance: Color,
+    // attenuation of the first surface hit, the sky color for a miss
+    pub albedo: Color,
+    pub normal: Vec3,
+    // distance along the camera ray, None for a miss
+    pub depth: Option<f32>,
+    pub object_id: Option<usize>,
+    pub material_id: Option<usize>,
+    pub direct: Color,
+    pub indirect: Color,
+    pub emission: Color,
+}
+
+impl PathSample {
+    pub fn new() -> Self {
+        Self {
+            radiance: Color::zero(),
+            albedo: Color::zero(),
+            normal: Vec3::zero(),
+            depth: None,
+            object_id: None,
+            material_id: None,
+            direct: Color::zero(),
+            indirect: Color::zero(),
+            emission: Color::zero(),
+        }
+    }
+
+    // the light carrying passes multiplied by `scale`, which is what a camera's exposure does to them
+    pub fn exposed(self, scale: f32) -> Self {
+        Self {
+            radiance: scale * self.radiance,
+            direct: scale * self.direct,
+            indirect: scale * self.indirect,
+            emission: scale * self.emission,
+            ..self
+        }
+    }
+}
+
+impl Default for PathSample {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// running average of one pass for one pixel
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct AovAccumulator {
+    aov: Aov,
+    sum: Color,
+    // only used for the variance pass
+    sum_of_squares: f32,
+    count: u32,
+}
+
+impl AovAccumulator {
+    pub fn new(aov: Aov) -> Self {
+        Self { aov, sum: Color::zero(), sum_of_squares: 0.0, count: 0 }
+    }
+
+    pub fn aov(&self) -> Aov {
+        self.aov
+    }
+
+    pub fn add(&mut self, sample: &PathSample) {
+        if self.aov.is_identifier() && self.count > 0 {
+            return;
+        }
+        if let Some(value) = self.aov.sample_value(sample) {
+            self.sum += value;
+            self.sum_of_squares += luminance(value) * luminance(value);
+            self.count += 1;
+        }
+    }
+
+    pub fn value(&self) -> Color {
+        if self.count == 0 {
+            return Color::zero();
+        }
+        let n = self.count as f32;
+        if self.aov == Aov::SampleCount {
+            return Color::new(n, n, n);
+        }
+        let mean = self.sum / n;
+        if self.aov == Aov::Variance {
+            if self.count < 2 {
+                return Color::zero();
+            }
+            // unbiased sample variance, divided by n again to get the variance of the mean
+            let sample_variance = (self.sum_of_squares - n * luminance(mean) * luminance(mean)) / (n - 1.0);
+            let variance = f32::max(sample_variance, 0.0) / n;
+            return Color::new(variance, variance, variance);
+        }
+        mean
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn names_round_trip() {
+        for aov in Aov::ALL {
+            assert_eq!(Aov::from_name(aov.name()), Some(aov));
+        }
+        assert_eq!(Aov::from_name("beauty"), None);
+    }
+
+    #[test]
+    fn depth_averages_only_hits() {
+        let mut accumulator = AovAccumulator::new(Aov::Depth);
+        accumulator.add(&PathSample { depth: Some(2.0), ..PathSample::new() });
+        accumulator.add(&PathSample::new());
+        accumulator.add(&PathSample { depth: Some(4.0), ..PathSample::new() });
+        assert_eq!(accumulator.value(), Color::new(3.0, 3.0, 3.0));
+    }
+
+    #[test]
+    fn variance_is_variance_of_the_mean() {
+        let mut accumulator = AovAccumulator::new(Aov::Variance);
+        for value in [1.0, 3.0, 1.0, 3.0] {
+            accumulator.add(&PathSample { radiance: Color::new(value, value, value), ..PathSample::new() });
+        }
+        // sample variance of 1, 3, 1, 3 is 4/3, and there are 4 samples
+        assert!(f32::abs(accumulator.value().x() - 1.0 / 3.0) < 1e-5);
+    }
+
+    #[test]
+    fn sample_count_counts_samples() {
+        let mut accumulator = AovAccumulator::new(Aov::SampleCount);
+        for _ in 0..5 {
+            accumulator.add(&PathSample::new());
+        }
+        assert_eq!(accumulator.value(), Color::new(5.0, 5.0, 5.0));
+        let mut counts = FrameBuffer::new(2, 1);
+        counts.set(1, 0, accumulator.value());
+        let heatmap = Aov::SampleCount.visualize(&counts);
+        assert_eq!(heatmap.get(0, 0), Color::new(0.0, 0.0, 1.0));
+        assert_eq!(heatmap.get(1, 0), Color::new(1.0, 0.0, 0.0));
+    }
+
+    #[test]
+    fn ids_come_from_first_sample() {
+        let mut accumulator = AovAccumulator::new(Aov::ObjectId);
+        accumulator.add(&PathSample { object_id: Some(4), ..PathSample::new() });
+        accumulator.add(&PathSample { object_id: Some(9), ..PathSample::new() });
+        assert_eq!(accumulator.value(), Color::new(5.0, 5.0, 5.0));
+    }
+}
@@ -0,0 +1,266 @@
+use std::cell::RefCell;
+use std::f32::consts::PI;
+use std::fmt;
+use std::fs::File;
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use serde::{Deserialize, Serialize};
+
+use crate::sampler::sample_unit_disk;
+use crate::util::{degrees_to_radians, Point};
+
+/**
+ * The shape of the lens opening, which is also the shape out of focus highlights (bokeh) take. Every shape is
+ * scaled to the aperture's diameter, a polygon's corners and an image's longer side touch the circle the default
+ * shape fills.
+ */
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Default)]
+#[serde(tag = "type")] // will expect { type: "Polygon", ... } in JSON format
+pub enum ApertureShape {
+    #[default]
+    Circle,
+    // the straight blades of a stopped down iris, with a corner `rotation` degrees counterclockwise from the right
+    Polygon {
+        blades: u32,
+        #[serde(default)]
+        rotation: f32,
+    },
+    // how bright the image is anywhere says how much light gets through there, e.g. a star or a heart cut out
+    Image(ApertureImage),
+}
+
+impl ApertureShape {
+    pub fn is_circle(&self) -> bool {
+        *self == ApertureShape::Circle
+    }
+
+    // a point in the aperture (around the origin, 2 across) for `u` in the unit square, denser where more light gets through
+    pub fn sample(&self, u: (f32, f32)) -> Point {
+        match self {
+            ApertureShape::Circle => sample_unit_disk(u),
+            ApertureShape::Polygon { blades, rotation } => sample_polygon(u, *blades, degrees_to_radians(*rotation)),
+            ApertureShape::Image(image) => image.sample(u),
+        }
+    }
+}
+
+fn sample_polygon(u: (f32, f32), blades: u32, rotation: f32) -> Point {
+    // one of the triangles between the center and two neighbouring corners, then a point evenly spread over it
+    let blades = u32::max(blades, 3) as f32;
+    let index = f32::min(f32::floor(u.0 * blades), blades - 1.0);
+    let corner = |i: f32| {
+        let angle = rotation + 2.0 * PI * i / blades;
+        Point::new(f32::cos(angle), f32::sin(angle), 0.0)
+    };
+    let distance = f32::sqrt(u.0 * blades - index);
+    distance * ((1.0 - u.1) * corner(index) + u.1 * corner(index + 1.0))
+}
+
+/**
+ * An aperture read from a PNG, kept as the running totals of its brightness so picking a pixel is a binary
+ * search: which row first, then which pixel in that row. A relative path is relative to the scene file, and it's
+ * the resolved path that's written back, so the image is read from the same place again whenever the scene is
+ * loaded (on every worker too, when rendering distributed).
+ */
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(try_from = "ApertureImageFile", into = "ApertureImageFile")]
+pub struct ApertureImage {
+    path: PathBuf,
+    width: usize,
+    height: usize,
+    // from 0 to 1 down the rows, top row first
+    rows: Arc<Vec<f32>>,
+    // from 0 to 1 along each row, row after row
+    columns: Arc<Vec<f32>>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct ApertureImageFile {
+    path: PathBuf,
+}
+
+thread_local! {
+    // the directory of the scene file being read on this thread, if any
+    static SCENE_DIRECTORY: RefCell<Option<PathBuf>> = const { RefCell::new(None) };
+}
+
+// runs `read` with relative aperture image paths taken to be relative to `directory`
+pub(crate) fn with_scene_directory<T>(directory: &Path, read: impl FnOnce() -> T) -> T {
+    let previous = SCENE_DIRECTORY.replace(Some(directory.to_path_buf()));
+    let result = read();
+    SCENE_DIRECTORY.set(previous);
+    result
+}
+
+impl TryFrom<ApertureImageFile> for ApertureImage {
+    type Error = String;
+
+    fn try_from(file: ApertureImageFile) -> Result<Self, Self::Error> {
+        let path = SCENE_DIRECTORY.with_borrow(|directory| match directory {
+            Some(directory) if file.path.is_relative() => directory.join(&file.path),
+            _ => file.path,
+        });
+        ApertureImage::load(&path)
+    }
+}
+
+impl From<ApertureImage> for ApertureImageFile {
+    fn from(image: ApertureImage) -> Self {
+        ApertureImageFile { path: image.path }
+    }
+}
+
+impl ApertureImage {
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let error = |error: &dyn fmt::Display| format!("couldn't read aperture image {}: {}", path.display(), error);
+        let file = File::open(path).map_err(|e| error(&e))?;
+        let mut decoder = png::Decoder::new(BufReader::new(file));
+        // 8 bits per channel, palettes expanded to RGB
+        decoder.set_transformations(png::Transformations::normalize_to_color8());
+        let mut reader = decoder.read_info().map_err(|e| error(&e))?;
+        let mut buffer = vec![0; reader.output_buffer_size()];
+        let info = reader.next_frame(&mut buffer).map_err(|e| error(&e))?;
+        let weights = buffer[..info.buffer_size()]
+            .chunks_exact(info.color_type.samples())
+            .map(|pixel| {
+                let channel = |i: usize| pixel[i] as f32 / 255.0;
+                match info.color_type {
+                    png::ColorType::GrayscaleAlpha => channel(0) * channel(1),
+                    png::ColorType::Rgb => (channel(0) + channel(1) + channel(2)) / 3.0,
+                    png::ColorType::Rgba => (channel(0) + channel(1) + channel(2)) / 3.0 * channel(3),
+                    _ => channel(0),
+                }
+            })
+            .collect();
+        Self::from_weights(path, info.width as usize, info.height as usize, weights)
+    }
+
+    // `weights` row by row from the top, how much light gets through each pixel
+    pub(crate) fn from_weights(path: &Path, width: usize, height: usize, weights: Vec<f32>) -> Result<Self, String> {
+        let mut columns = Vec::with_capacity(width * height);
+        let mut row_totals = Vec::with_capacity(height);
+        for row in weights.chunks_exact(width) {
+            let running = running_totals(row.iter().copied());
+            row_totals.push(running.last().copied().unwrap_or(0.0));
+            columns.extend(normalized(running));
+        }
+        let rows = running_totals(row_totals.into_iter());
+        if rows.last().copied().unwrap_or(0.0) <= 0.0 {
+            return Err(format!("aperture image {} is black, no light would get through", path.display()));
+        }
+        Ok(Self { path: path.to_path_buf(), width, height, rows: Arc::new(normalized(rows)), columns: Arc::new(columns) })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    fn sample(&self, u: (f32, f32)) -> Point {
+        let (row, v) = pick(&self.rows, u.1);
+        let (column, s) = pick(&self.columns[row * self.width..(row + 1) * self.width], u.0);
+        // the longer side goes from -1 to 1, with the top row up
+        let size = usize::max(self.width, self.height) as f32;
+        let x = (2.0 * (column as f32 + s) - self.width as f32) / size;
+        let y = (self.height as f32 - 2.0 * (row as f32 + v)) / size;
+        Point::new(x, y, 0.0)
+    }
+}
+
+fn running_totals(weights: impl Iterator<Item = f32>) -> Vec<f32> {
+    weights
+        .scan(0.0, |total, weight| {
+            *total += f32::max(weight, 0.0);
+            Some(*total)
+        })
+        .collect()
+}
+
+// scaled to end at exactly 1, everything after the last weight that isn't 0 included
+fn normalized(running: Vec<f32>) -> Vec<f32> {
+    let total = running.last().copied().unwrap_or(0.0);
+    if total <= 0.0 {
+        return running;
+    }
+    running.into_iter().map(|sum| if sum >= total { 1.0 } else { sum / total }).collect()
+}
+
+// the entry `u` falls into and how far into it, entries that don't let any light through never come up
+fn pick(running: &[f32], u: f32) -> (usize, f32) {
+    let index = usize::min(running.partition_point(|sum| *sum <= u), running.len() - 1);
+    let start = if index == 0 { 0.0 } else { running[index - 1] };
+    let fraction = (u - start) / (running[index] - start);
+    (index, fraction.clamp(0.0, 1.0))
+}
+
+impl PartialEq for ApertureImage {
+    fn eq(&self, other: &Self) -> bool {
+        self.path == other.path
+    }
+}
+
+impl fmt::Debug for ApertureImage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "ApertureImage({}, {}x{})", self.path.display(), self.width, self.height)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn grid(n: usize) -> impl Iterator<Item = (f32, f32)> {
+        (0..n).flat_map(move |i| (0..n).map(move |j| ((i as f32 + 0.5) / n as f32, (j as f32 + 0.5) / n as f32)))
+    }
+
+    #[test]
+    fn polygon_samples_stay_inside_the_blades() {
+        let shape = ApertureShape::Polygon { blades: 6, rotation: 30.0 };
+        // a hexagon with corners straight up and down has flat sides at the left and right, cos(30) out
+        let apothem = f32::cos(PI / 6.0);
+        let points: Vec<Point> = grid(32).map(|u| shape.sample(u)).collect();
+        assert!(points.iter().all(|p| p.len() <= 1.0 + 1e-5 && f32::abs(p.x()) <= apothem + 1e-5));
+        assert!(points.iter().any(|p| p.y() > 0.9) && points.iter().any(|p| p.y() < -0.9));
+        // evenly spread, as many points in the right half as in the left
+        let right = points.iter().filter(|p| p.x() > 0.0).count() as f32;
+        assert!(f32::abs(right / points.len() as f32 - 0.5) < 0.02);
+    }
+
+    #[test]
+    fn image_samples_land_where_light_gets_through() {
+        // 4 wide and 2 high, only the top right pixel and (twice as bright) the second one of the bottom row let light through
+        let weights = vec![0.0, 0.0, 0.0, 0.5, 0.0, 1.0, 0.0, 0.0];
+        let image = ApertureImage::from_weights(Path::new("test.png"), 4, 2, weights).unwrap();
+        let points: Vec<Point> = grid(30).map(|u| image.sample(u)).collect();
+        let top_right = |p: &&Point| (0.5..=1.0).contains(&p.x()) && (0.0..=0.5).contains(&p.y());
+        let bottom_second = |p: &&Point| (-0.5..=0.0).contains(&p.x()) && (-0.5..=0.0).contains(&p.y());
+        assert_eq!(points.iter().filter(top_right).count() + points.iter().filter(bottom_second).count(), points.len());
+        assert!(f32::abs(points.iter().filter(bottom_second).count() as f32 / points.len() as f32 - 2.0 / 3.0) < 0.02);
+
+        let black = ApertureImage::from_weights(Path::new("black.png"), 2, 1, vec![0.0, 0.0]);
+        assert_eq!(black.unwrap_err(), "aperture image black.png is black, no light would get through");
+    }
+
+    #[test]
+    fn aperture_images_are_read_from_png() {
+        let path = std::env::temp_dir().join(format!("aperture-{}.png", std::process::id()));
+        {
+            let file = std::io::BufWriter::new(File::create(&path).unwrap());
+            let mut encoder = png::Encoder::new(file, 2, 2);
+            encoder.set_color(png::ColorType::Grayscale);
+            encoder.set_depth(png::BitDepth::Eight);
+            // only the bottom right pixel is open
+            encoder.write_header().unwrap().write_image_data(&[0, 0, 0, 255]).unwrap();
+        }
+        let shape: ApertureShape =
+            serde_json::from_str(&format!(r#"{{ "type": "Image", "path": {:?} }}"#, path.display().to_string())).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let point = shape.sample((0.3, 0.6));
+        assert!((0.0..=1.0).contains(&point.x()) && (-1.0..=0.0).contains(&point.y()));
+        assert!(serde_json::to_string(&shape).unwrap().contains(r#""type":"Image","path""#));
+
+        let missing = serde_json::from_str::<ApertureShape>(r#"{ "type": "Image", "path": "missing.png" }"#).unwrap_err();
+        assert!(missing.to_string().starts_with("couldn't read aperture image missing.png"));
+    }
+}
@@ -1,11 +1,250 @@
+use crate::aperture::ApertureShape;
 use crate::ray::Ray;
-use crate::util::{degrees_to_radians, Point, Vec3, random_between_0_1};
+use crate::sampler::Sampler;
+use crate::util::{degrees_to_radians, Point, Vec3};
+use std::f32::consts::PI;
 use std::fmt;
 
+use serde::de::{self, Deserializer};
 use serde::{Deserialize, Serialize};
 
+/**
+ * A camera the way it's written in scene files, everything `Camera` needs for generating rays is derived from
+ * this when the scene is loaded.
+ */
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CameraDescription {
+    pub look_from: Point,
+    pub look_at: Point,
+    // which way is up in the image, it doesn't have to be perpendicular to the view direction
+    #[serde(default = "default_vup")]
+    pub vup: Vec3,
+    #[serde(default, skip_serializing_if = "Projection::is_perspective")]
+    pub projection: Projection,
+    // vertical field of view in degrees, only perspective cameras have one
+    #[serde(default)]
+    pub vfov: f32,
+    // diameter of the lens, 0 keeps everything in focus. Set by the f-number instead when there's an exposure
+    #[serde(default)]
+    pub aperture: f32,
+    #[serde(default, skip_serializing_if = "ApertureShape::is_circle")]
+    pub aperture_shape: ApertureShape,
+    // distance to the plane that's in perfect focus, the distance to look_at when left out
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub focus_distance: Option<f32>,
+    // width / height of the viewport, only given when it isn't the image's (and the render gets stretched). With
+    // stereo it's the aspect ratio of one eye's half of the image
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub aspect_ratio: Option<f32>,
+    #[serde(default, skip_serializing_if = "Shutter::is_default")]
+    pub shutter: Shutter,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub exposure: Option<PhotographicExposure>,
+    // renders a view for each eye next to each other in the image, for VR headsets
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stereo: Option<Stereo>,
+}
+
+fn default_vup() -> Vec3 {
+    Vec3::new(0.0, 1.0, 0.0)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, Default)]
+#[serde(tag = "type")] // will expect { type: "Orthographic", ... } in JSON format
+pub enum Projection {
+    // rays fan out from a thin lens at look_from, `vfov` wide
+    #[default]
+    Perspective,
+    // parallel rays along the view direction from a `view_width` (in world units) wide rectangle around look_from,
+    // with no depth of field
+    Orthographic { view_width: f32 },
+    // the whole sphere around look_from, longitude across the image and latitude up it (best at 2:1)
+    Equirectangular,
+    // a circle as tall as the image (or as wide, if that's shorter) that sees `fov` degrees across, up to 360
+    Fisheye {
+        fov: f32,
+        #[serde(default)]
+        mapping: FisheyeMapping,
+    },
+}
+
+// how far from the center of a fisheye image a direction ends up, by its angle from the view direction
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Default)]
+pub enum FisheyeMapping {
+    // proportional to the angle
+    #[default]
+    Equidistant,
+    // proportional to sin(angle / 2), which keeps the solid angle every pixel covers the same
+    Equisolid,
+}
+
+impl Projection {
+    fn is_perspective(&self) -> bool {
+        *self == Projection::Perspective
+    }
+}
+
+/**
+ * When the shutter opens and closes, camera rays are spread evenly over the time in between and anything that
+ * moves in that time is blurred. Times are in the units object motion is keyframed in, a moving sphere goes from
+ * its first center to its second between 0 and 1.
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Shutter {
+    pub open: f32,
+    pub close: f32,
+}
+
+impl Default for Shutter {
+    fn default() -> Self {
+        Self { open: 0.0, close: 1.0 }
+    }
+}
+
+impl Shutter {
+    fn is_default(&self) -> bool {
+        *self == Shutter::default()
+    }
+}
+
+// f/16 at 1/100 s and ISO 100, the sunny 16 rule
+const SUNNY_16_EXPOSURE: f32 = 0.01 * 100.0 / (16.0 * 16.0);
+
+/**
+ * Settings off a real camera. The f-number sets the aperture from the focal length a `sensor_height` mm tall
+ * sensor has at the camera's vfov (with world units taken to be meters), and together with the shutter time and
+ * ISO how bright the image gets: scene radiance is taken to be daylight, so the sunny 16 rule leaves it as it is
+ * and every stop more or less light doubles or halves it. `shutter_time` is in seconds and only brightens, motion
+ * blur still follows the camera's `shutter`.
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct PhotographicExposure {
+    pub f_number: f32,
+    #[serde(default = "default_shutter_time")]
+    pub shutter_time: f32,
+    #[serde(default = "default_iso")]
+    pub iso: f32,
+    // 24 mm is a full frame sensor
+    #[serde(default = "default_sensor_height")]
+    pub sensor_height: f32,
+}
+
+fn default_shutter_time() -> f32 {
+    0.01
+}
+
+fn default_iso() -> f32 {
+    100.0
+}
+
+fn default_sensor_height() -> f32 {
+    24.0
+}
+
+impl PhotographicExposure {
+    // in mm
+    pub fn focal_length(&self, vfov: f32) -> f32 {
+        self.sensor_height / 2.0 / f32::tan(degrees_to_radians(vfov) / 2.0)
+    }
+
+    // what the image's radiance is multiplied by
+    pub fn radiance_scale(&self) -> f32 {
+        self.shutter_time * self.iso / (self.f_number * self.f_number) / SUNNY_16_EXPOSURE
+    }
+}
+
+/**
+ * Two views from eyes `interpupillary_distance` apart along the camera's horizontal axis. Perspective eyes look
+ * through the same window at `convergence_distance`, which is where things appear at the depth of the headset's
+ * screen: nearer ones pop out of it and farther ones sink in. Equirectangular cameras render omni-directional
+ * stereo, where every direction is seen from eyes on a circle around look_from so the depth holds up whichever
+ * way you turn. Fisheye eyes look straight ahead, side by side the way VR180 cameras do.
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Stereo {
+    #[serde(default)]
+    pub layout: StereoLayout,
+    // in world units, 0.064 is an average adult's when a unit is a meter
+    #[serde(default = "default_interpupillary_distance")]
+    pub interpupillary_distance: f32,
+    // the focus distance when left out
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub convergence_distance: Option<f32>,
+}
+
+fn default_interpupillary_distance() -> f32 {
+    0.064
+}
+
+// where the eyes go in the image
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Default)]
+pub enum StereoLayout {
+    // the left eye in the left half
+    #[default]
+    SideBySide,
+    // the left eye in the top half
+    TopBottom,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Eye {
+    Left,
+    Right,
+}
+
+impl StereoLayout {
+    // width / height of one eye's view in an image of this aspect ratio
+    pub fn eye_aspect_ratio(&self, image_aspect_ratio: f32) -> f32 {
+        match self {
+            StereoLayout::SideBySide => image_aspect_ratio / 2.0,
+            StereoLayout::TopBottom => image_aspect_ratio * 2.0,
+        }
+    }
+
+    // which eye sees (s, t) on the image and where that is on the eye's own view
+    pub fn eye_at(&self, s: f32, t: f32) -> (Eye, f32, f32) {
+        match self {
+            StereoLayout::SideBySide if s < 0.5 => (Eye::Left, 2.0 * s, t),
+            StereoLayout::SideBySide => (Eye::Right, 2.0 * s - 1.0, t),
+            StereoLayout::TopBottom if t >= 0.5 => (Eye::Left, s, 2.0 * t - 1.0),
+            StereoLayout::TopBottom => (Eye::Right, s, 2.0 * t),
+        }
+    }
+}
+
+impl CameraDescription {
+    pub fn focus_distance(&self) -> f32 {
+        self.focus_distance.unwrap_or_else(|| (self.look_from - self.look_at).len())
+    }
+
+    // in world units, from the exposure's f-number when there is one
+    pub fn aperture(&self) -> f32 {
+        match self.exposure {
+            Some(exposure) => exposure.focal_length(self.vfov) / exposure.f_number / 1000.0,
+            None => self.aperture,
+        }
+    }
+
+    // where the eyes' views meet, only meaningful with stereo
+    pub fn convergence_distance(&self) -> f32 {
+        self.stereo.and_then(|stereo| stereo.convergence_distance).unwrap_or_else(|| self.focus_distance())
+    }
+
+    // width / height of what one view of the camera covers in an image of this aspect ratio
+    pub fn view_aspect_ratio(&self, image_aspect_ratio: f32) -> f32 {
+        match self.stereo {
+            Some(stereo) => stereo.layout.eye_aspect_ratio(image_aspect_ratio),
+            None => image_aspect_ratio,
+        }
+    }
+}
+
+/**
+ * What scene files used to store, only the derived basis. Still read so older scenes keep rendering exactly
+ * the way they did.
+ */
 #[derive(Debug, Clone, Copy, Serialize, Deserialize)]
-pub struct Camera {
+pub(crate) struct LegacyCamera {
     origin: Point,
     lower_left_corner: Point,
     horizontal: Vec3,
@@ -16,6 +255,279 @@ pub struct Camera {
     lens_radius: f32,
 }
 
+// either camera format, told apart by whether the derived fields are there
+#[derive(Debug, Clone, Serialize)]
+#[serde(untagged)]
+pub(crate) enum CameraFile {
+    Description(CameraDescription),
+    Legacy(LegacyCamera),
+}
+
+impl<'de> Deserialize<'de> for CameraFile {
+    // not #[serde(untagged)], its errors don't say what's wrong with the camera
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let value = serde_json::Value::deserialize(deserializer)?;
+        let camera = if value.get("lower_left_corner").is_some() {
+            serde_json::from_value(value).map(CameraFile::Legacy)
+        } else {
+            serde_json::from_value(value).map(CameraFile::Description)
+        };
+        camera.map_err(|error| de::Error::custom(format!("invalid camera: {}", error)))
+    }
+}
+
+impl CameraFile {
+    pub(crate) fn into_camera(self, image_aspect_ratio: f32) -> Camera {
+        match self {
+            CameraFile::Description(description) => Camera::from_description(description, image_aspect_ratio),
+            CameraFile::Legacy(legacy) => Camera::from_legacy(legacy, image_aspect_ratio),
+        }
+    }
+}
+
+/**
+ * How a camera model turns a point on the image into a ray. `s` and `t` go from 0 to 1 across and up the image,
+ * `lens` is a random point in the unit square for models with a lens to sample and `time` is when the ray is
+ * shot. Points the model doesn't see anything at (outside a fisheye's image circle) have no ray.
+ */
+pub trait CameraModel {
+    fn get_ray(&self, s: f32, t: f32, lens: (f32, f32), time: f32) -> Option<Ray>;
+}
+
+#[derive(Debug, Clone)]
+pub enum RenderableCamera {
+    Perspective(PerspectiveCamera),
+    Orthographic(OrthographicCamera),
+    Equirectangular(EquirectangularCamera),
+    Fisheye(FisheyeCamera),
+}
+
+impl CameraModel for RenderableCamera {
+    fn get_ray(&self, s: f32, t: f32, lens: (f32, f32), time: f32) -> Option<Ray> {
+        match self {
+            RenderableCamera::Perspective(camera) => camera.get_ray(s, t, lens, time),
+            RenderableCamera::Orthographic(camera) => camera.get_ray(s, t, lens, time),
+            RenderableCamera::Equirectangular(camera) => camera.get_ray(s, t, lens, time),
+            RenderableCamera::Fisheye(camera) => camera.get_ray(s, t, lens, time),
+        }
+    }
+}
+
+// the camera's orientation, w points back from look_at towards look_from and v is up in the image
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CameraFrame {
+    pub origin: Point,
+    pub u: Vec3,
+    pub v: Vec3,
+    pub w: Vec3,
+}
+
+impl CameraFrame {
+    pub fn new(description: &CameraDescription) -> Self {
+        let w = (description.look_from - description.look_at).unit_vector();
+        let u = Vec3::cross(description.vup, w).unit_vector();
+        let v = Vec3::cross(w, u);
+        Self { origin: description.look_from, u, v, w }
+    }
+
+    // from (x, y, z) with z towards the viewer to world coordinates
+    fn direction(&self, x: f32, y: f32, z: f32) -> Vec3 {
+        x * self.u + y * self.v + z * self.w
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct PerspectiveCamera {
+    origin: Point,
+    lower_left_corner: Point,
+    horizontal: Vec3,
+    vertical: Vec3,
+    u: Vec3,
+    v: Vec3,
+    lens_radius: f32,
+    aperture_shape: ApertureShape,
+}
+
+impl PerspectiveCamera {
+    pub fn new(frame: &CameraFrame, vfov: f32, aspect_ratio: f32, aperture: f32, focus_distance: f32) -> Self {
+        let theta = degrees_to_radians(vfov);
+        let h = f32::tan(theta / 2.0);
+        let viewport_height = 2.0 * h;
+        let viewport_width = aspect_ratio * viewport_height;
+
+        let origin = frame.origin;
+        let horizontal = focus_distance * viewport_width * frame.u;
+        let vertical = focus_distance * viewport_height * frame.v;
+        let lower_left_corner = origin - horizontal / 2.0 - vertical / 2.0 - (focus_distance * frame.w);
+        Self {
+            origin,
+            lower_left_corner,
+            horizontal,
+            vertical,
+            u: frame.u,
+            v: frame.v,
+            lens_radius: aperture / 2.0,
+            aperture_shape: ApertureShape::Circle,
+        }
+    }
+
+    pub fn with_aperture_shape(self, aperture_shape: ApertureShape) -> Self {
+        Self { aperture_shape, ..self }
+    }
+
+    /**
+     * One eye of a stereo pair, `eye_offset` along u from look_from. Rather than turning the eyes in towards each
+     * other (which tilts their focus planes apart and gives vertical parallax in the corners) both look through
+     * the window the centered camera has at `convergence_distance`, with their focus planes kept parallel to it.
+     */
+    pub fn new_eye(
+        frame: &CameraFrame,
+        vfov: f32,
+        aspect_ratio: f32,
+        aperture: f32,
+        focus_distance: f32,
+        eye_offset: f32,
+        convergence_distance: f32,
+    ) -> Self {
+        let window = Self::new(frame, vfov, aspect_ratio, aperture, convergence_distance);
+        let origin = frame.origin + eye_offset * frame.u;
+        // the window scaled around the eye until it's focus_distance away
+        let scale = focus_distance / convergence_distance;
+        Self {
+            origin,
+            lower_left_corner: origin + scale * (window.lower_left_corner - origin),
+            horizontal: scale * window.horizontal,
+            vertical: scale * window.vertical,
+            ..window
+        }
+    }
+}
+
+impl CameraModel for PerspectiveCamera {
+    fn get_ray(&self, s: f32, t: f32, lens: (f32, f32), time: f32) -> Option<Ray> {
+        // start ray from random spot in aperature
+        let disk_samp = self.lens_radius * self.aperture_shape.sample(lens);
+        // calculate the offset in reference to the origin (this ties back to how u, v are calculated in constructor)
+        let offset = (self.u * disk_samp.x()) + (self.v * disk_samp.y());
+        Some(Ray::new_with_time(
+            self.origin + offset,
+            self.lower_left_corner + s * self.horizontal + t * self.vertical - self.origin - offset,
+            time,
+        ))
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct OrthographicCamera {
+    lower_left_corner: Point,
+    horizontal: Vec3,
+    vertical: Vec3,
+    direction: Vec3,
+}
+
+impl OrthographicCamera {
+    pub fn new(frame: &CameraFrame, view_width: f32, aspect_ratio: f32) -> Self {
+        // the viewport is where the rays start from, centered on look_from
+        let horizontal = view_width * frame.u;
+        let vertical = view_width / aspect_ratio * frame.v;
+        let lower_left_corner = frame.origin - horizontal / 2.0 - vertical / 2.0;
+        Self { lower_left_corner, horizontal, vertical, direction: -frame.w }
+    }
+}
+
+impl CameraModel for OrthographicCamera {
+    fn get_ray(&self, s: f32, t: f32, _lens: (f32, f32), time: f32) -> Option<Ray> {
+        Some(Ray::new_with_time(self.lower_left_corner + s * self.horizontal + t * self.vertical, self.direction, time))
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct EquirectangularCamera {
+    frame: CameraFrame,
+    // along the horizontal axis of whichever way a ray looks, 0 for a single view
+    eye_offset: f32,
+    convergence_distance: f32,
+}
+
+impl EquirectangularCamera {
+    pub fn new(frame: &CameraFrame) -> Self {
+        Self { frame: *frame, eye_offset: 0.0, convergence_distance: 0.0 }
+    }
+
+    pub fn new_eye(frame: &CameraFrame, eye_offset: f32, convergence_distance: f32) -> Self {
+        Self { frame: *frame, eye_offset, convergence_distance }
+    }
+}
+
+impl CameraModel for EquirectangularCamera {
+    fn get_ray(&self, s: f32, t: f32, _lens: (f32, f32), time: f32) -> Option<Ray> {
+        // the middle of the image looks at look_at, the left and right edges straight behind
+        let longitude = (s - 0.5) * 2.0 * PI;
+        let latitude = (t - 0.5) * PI;
+        let direction = self.frame.direction(
+            f32::cos(latitude) * f32::sin(longitude),
+            f32::sin(latitude),
+            -f32::cos(latitude) * f32::cos(longitude),
+        );
+        if self.eye_offset == 0.0 {
+            return Some(Ray::new_with_time(self.frame.origin, direction, time));
+        }
+        // omni-directional stereo: the eye sits to the side of look_from for the way this ray looks, closing in on
+        // it towards the poles where there's no telling which side that is
+        let sideways = self.frame.direction(f32::cos(longitude), 0.0, f32::sin(longitude));
+        let eye = self.frame.origin + self.eye_offset * f32::cos(latitude) * sideways;
+        let target = self.frame.origin + self.convergence_distance * direction;
+        Some(Ray::new_with_time(eye, target - eye, time))
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct FisheyeCamera {
+    frame: CameraFrame,
+    // in radians
+    fov: f32,
+    mapping: FisheyeMapping,
+    aspect_ratio: f32,
+}
+
+impl FisheyeCamera {
+    pub fn new(frame: &CameraFrame, fov: f32, mapping: FisheyeMapping, aspect_ratio: f32) -> Self {
+        Self { frame: *frame, fov: degrees_to_radians(fov), mapping, aspect_ratio }
+    }
+}
+
+impl CameraModel for FisheyeCamera {
+    fn get_ray(&self, s: f32, t: f32, _lens: (f32, f32), time: f32) -> Option<Ray> {
+        // the image goes from -aspect_ratio to aspect_ratio across and -1 to 1 up, the circle fits the shorter side
+        let x = (2.0 * s - 1.0) * self.aspect_ratio;
+        let y = 2.0 * t - 1.0;
+        let radius = f32::sqrt(x * x + y * y) / f32::min(self.aspect_ratio, 1.0);
+        if radius > 1.0 {
+            return None;
+        }
+        let theta = match self.mapping {
+            FisheyeMapping::Equidistant => radius * self.fov / 2.0,
+            FisheyeMapping::Equisolid => 2.0 * f32::asin(radius * f32::sin(self.fov / 4.0)),
+        };
+        let phi = f32::atan2(y, x);
+        let direction = self.frame.direction(
+            f32::sin(theta) * f32::cos(phi),
+            f32::sin(theta) * f32::sin(phi),
+            -f32::cos(theta),
+        );
+        Some(Ray::new_with_time(self.frame.origin, direction, time))
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Camera {
+    // with the aspect ratio always filled in
+    description: CameraDescription,
+    // the left eye's with stereo
+    model: RenderableCamera,
+    right_eye: Option<RenderableCamera>,
+}
+
 impl Camera {
     pub fn new(
         look_from: Point,
@@ -26,62 +538,485 @@ impl Camera {
         aperature: f32,
         focus_distance: f32,
     ) -> Self {
-        let theta = degrees_to_radians(vfov);
-        let h = f32::tan(theta / 2.0);
-        let viewport_height = 2.0 * h;
-        let viewport_width = aspect_ratio * viewport_height;
+        let description = CameraDescription {
+            look_from,
+            look_at,
+            vup,
+            projection: Projection::Perspective,
+            vfov,
+            aperture: aperature,
+            aperture_shape: ApertureShape::Circle,
+            focus_distance: Some(focus_distance),
+            aspect_ratio: None,
+            shutter: Shutter::default(),
+            exposure: None,
+            stereo: None,
+        };
+        Self::from_description(description, aspect_ratio)
+    }
 
-        let w = (look_from - look_at).unit_vector();
-        let u = Vec3::cross(vup, w).unit_vector();
-        let v = Vec3::cross(w, u);
+    // the image's aspect ratio (or one eye's half of it) is used unless the description has one of its own
+    pub fn from_description(description: CameraDescription, image_aspect_ratio: f32) -> Self {
+        let aspect_ratio = description.aspect_ratio.unwrap_or(description.view_aspect_ratio(image_aspect_ratio));
+        let frame = CameraFrame::new(&description);
 
-        eprintln!("w: {}\nu: {}\nv: {}", w, u, v);
+        let (model, right_eye) = match description.stereo {
+            Some(stereo) => {
+                let half = stereo.interpupillary_distance / 2.0;
+                (
+                    Self::eye_model(&description, &frame, aspect_ratio, -half),
+                    Some(Self::eye_model(&description, &frame, aspect_ratio, half)),
+                )
+            }
+            None => (Self::eye_model(&description, &frame, aspect_ratio, 0.0), None),
+        };
+        Self { description: CameraDescription { aspect_ratio: Some(aspect_ratio), ..description }, model, right_eye }
+    }
 
-        let origin = look_from;
-        let horizontal = focus_distance * viewport_width * u;
-        let vertical = focus_distance * viewport_height * v;
-        let lower_left_corner = origin - horizontal / 2.0 - vertical / 2.0 - (focus_distance * w);
+    // the view from `eye_offset` along u, which is 0 without stereo
+    fn eye_model(
+        description: &CameraDescription,
+        frame: &CameraFrame,
+        aspect_ratio: f32,
+        eye_offset: f32,
+    ) -> RenderableCamera {
+        match description.projection {
+            Projection::Perspective => {
+                let camera = if eye_offset == 0.0 {
+                    PerspectiveCamera::new(
+                        frame,
+                        description.vfov,
+                        aspect_ratio,
+                        description.aperture(),
+                        description.focus_distance(),
+                    )
+                } else {
+                    PerspectiveCamera::new_eye(
+                        frame,
+                        description.vfov,
+                        aspect_ratio,
+                        description.aperture(),
+                        description.focus_distance(),
+                        eye_offset,
+                        description.convergence_distance(),
+                    )
+                };
+                RenderableCamera::Perspective(camera.with_aperture_shape(description.aperture_shape.clone()))
+            }
+            // parallel rays don't see any depth from a second eye, the validator won't let stereo through
+            Projection::Orthographic { view_width } => {
+                RenderableCamera::Orthographic(OrthographicCamera::new(frame, view_width, aspect_ratio))
+            }
+            Projection::Equirectangular if eye_offset == 0.0 => {
+                RenderableCamera::Equirectangular(EquirectangularCamera::new(frame))
+            }
+            Projection::Equirectangular => RenderableCamera::Equirectangular(EquirectangularCamera::new_eye(
+                frame,
+                eye_offset,
+                description.convergence_distance(),
+            )),
+            Projection::Fisheye { fov, mapping } => {
+                let eye = CameraFrame { origin: frame.origin + eye_offset * frame.u, ..*frame };
+                RenderableCamera::Fisheye(FisheyeCamera::new(&eye, fov, mapping, aspect_ratio))
+            }
+        }
+    }
 
-        let lens_radius = aperature / 2.0;
+    /**
+     * Keeps the basis exactly as it was saved and works out a description that gives the same view, which is
+     * what the scene is written back out with.
+     */
+    pub(crate) fn from_legacy(legacy: LegacyCamera, image_aspect_ratio: f32) -> Self {
+        let center = legacy.lower_left_corner + legacy.horizontal / 2.0 + legacy.vertical / 2.0;
+        let focus_distance = (legacy.origin - center).len();
+        let vfov = (2.0 * f32::atan(legacy.vertical.len() / 2.0 / focus_distance)).to_degrees();
+        let mut aspect_ratio = legacy.horizontal.len() / legacy.vertical.len();
+        // whatever's left over from rounding shouldn't turn into an aspect ratio of its own
+        if f32::abs(aspect_ratio / image_aspect_ratio - 1.0) < 1e-4 {
+            aspect_ratio = image_aspect_ratio;
+        }
         Self {
-            origin,
-            horizontal,
-            lower_left_corner,
-            vertical,
-            u,
-            v,
-            w,
-            lens_radius,
+            description: CameraDescription {
+                look_from: legacy.origin,
+                look_at: center,
+                vup: legacy.v,
+                projection: Projection::Perspective,
+                vfov,
+                aperture: 2.0 * legacy.lens_radius,
+                aperture_shape: ApertureShape::Circle,
+                focus_distance: Some(focus_distance),
+                aspect_ratio: Some(aspect_ratio),
+                shutter: Shutter::default(),
+                exposure: None,
+                stereo: None,
+            },
+            model: RenderableCamera::Perspective(PerspectiveCamera {
+                origin: legacy.origin,
+                lower_left_corner: legacy.lower_left_corner,
+                horizontal: legacy.horizontal,
+                vertical: legacy.vertical,
+                u: legacy.u,
+                v: legacy.v,
+                lens_radius: legacy.lens_radius,
+                aperture_shape: ApertureShape::Circle,
+            }),
+            right_eye: None,
         }
     }
 
-    pub fn get_ray(&self, u: f32, v: f32) -> Ray {
-        // start ray from random spot in aperature
-        let disk_samp = self.lens_radius * Vec3::random_in_unit_disk();
-        // calculate the offset in reference to the origin (this ties back to how u, v are calculated in constructor)
-        let offset = (self.u * disk_samp.x()) + (self.v * disk_samp.y());
-        let ray_time = random_between_0_1();
-        Ray::new_with_time(
-            self.origin + offset,
-            self.lower_left_corner + u * self.horizontal + v * self.vertical - self.origin - offset,
-            ray_time
-        )
+    pub fn description(&self) -> &CameraDescription {
+        &self.description
+    }
+
+    // the left eye's with stereo
+    pub fn model(&self) -> &RenderableCamera {
+        &self.model
+    }
+
+    pub fn eye(&self, eye: Eye) -> &RenderableCamera {
+        match (eye, &self.right_eye) {
+            (Eye::Right, Some(right_eye)) => right_eye,
+            _ => &self.model,
+        }
+    }
+
+    pub fn aspect_ratio(&self) -> f32 {
+        self.description.aspect_ratio.unwrap_or_default()
+    }
+
+    // how much brighter the exposure makes the image, 1 without one
+    pub fn radiance_scale(&self) -> f32 {
+        self.description.exposure.map_or(1.0, |exposure| exposure.radiance_scale())
+    }
+
+    // what's written to a scene file with an image of this aspect ratio
+    pub(crate) fn to_file(&self, image_aspect_ratio: f32) -> CameraFile {
+        let mut description = self.description.clone();
+        if description.aspect_ratio == Some(description.view_aspect_ratio(image_aspect_ratio)) {
+            description.aspect_ratio = None;
+        }
+        CameraFile::Description(description)
+    }
+
+    // a ray through (u, v) on the image, at a random time while the shutter is open
+    pub fn get_ray(&self, u: f32, v: f32, sampler: &mut dyn Sampler) -> Option<Ray> {
+        let lens = sampler.get_2d();
+        let shutter = self.description.shutter;
+        let ray_time = shutter.open + sampler.get_1d() * (shutter.close - shutter.open);
+        match self.description.stereo {
+            Some(stereo) => {
+                let (eye, s, t) = stereo.layout.eye_at(u, v);
+                self.eye(eye).get_ray(s, t, lens, ray_time)
+            }
+            None => self.model.get_ray(u, v, lens, ray_time),
+        }
     }
 }
 
 impl fmt::Display for Camera {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(
-            f,
-            "CAMERA\n{}\n{}\n{}\n{}\n{}\n{}\n{}\n{}",
-            self.origin,
-            self.lower_left_corner,
-            self.horizontal,
-            self.vertical,
-            self.u,
-            self.v,
-            self.w,
-            self.lens_radius
+        match &self.right_eye {
+            Some(right_eye) => write!(f, "CAMERA\nleft eye: {:?}\nright eye: {:?}", self.model, right_eye),
+            None => write!(f, "CAMERA\n{:?}", self.model),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn camera(aspect_ratio: f32) -> Camera {
+        Camera::new(
+            Point::new(13.0, 2.0, 3.0),
+            Point::new(0.0, 0.0, 0.0),
+            Vec3::new(0.0, 1.0, 0.0),
+            20.0,
+            aspect_ratio,
+            0.1,
+            10.0,
         )
     }
+
+    fn perspective(camera: &Camera) -> PerspectiveCamera {
+        match camera.model() {
+            RenderableCamera::Perspective(perspective) => perspective.clone(),
+            model => panic!("expected a perspective camera, got {:?}", model),
+        }
+    }
+
+    fn basis(camera: &Camera) -> [Vec3; 6] {
+        let p = perspective(camera);
+        [p.origin, p.lower_left_corner, p.horizontal, p.vertical, p.u, p.v]
+    }
+
+    fn close(a: Vec3, b: Vec3) -> bool {
+        (a - b).len() < 1e-4 * f32::max(1.0, a.len())
+    }
+
+    #[test]
+    fn descriptions_rebuild_the_same_camera() {
+        let original = camera(1.5);
+        let json = serde_json::to_string(&original.to_file(1.5)).unwrap();
+        assert!(!json.contains("lower_left_corner") && !json.contains("aspect_ratio"));
+
+        let loaded = serde_json::from_str::<CameraFile>(&json).unwrap().into_camera(1.5);
+        assert_eq!(basis(&loaded), basis(&original));
+        assert_eq!(perspective(&loaded).lens_radius, perspective(&original).lens_radius);
+        assert_eq!(loaded.description(), original.description());
+    }
+
+    #[test]
+    fn hand_written_descriptions_fill_in_defaults() {
+        let json = r#"{ "look_from": { "vec": [0, 0, 5] }, "look_at": { "vec": [0, 0, 0] }, "vfov": 40 }"#;
+        let description = match serde_json::from_str::<CameraFile>(json).unwrap() {
+            CameraFile::Description(description) => description,
+            CameraFile::Legacy(_) => panic!("read as a legacy camera"),
+        };
+        assert_eq!(description.vup, Vec3::new(0.0, 1.0, 0.0));
+        assert_eq!(description.aperture, 0.0);
+        assert_eq!(description.focus_distance(), 5.0);
+
+        let error = serde_json::from_str::<CameraFile>(r#"{ "look_from": { "vec": [0, 0, 5] } }"#).unwrap_err();
+        assert!(error.to_string().starts_with("invalid camera: missing field `look_at`"));
+    }
+
+    #[test]
+    fn legacy_cameras_keep_their_basis() {
+        let original = camera(1.5);
+        let p = perspective(&original);
+        let legacy = LegacyCamera {
+            origin: p.origin,
+            lower_left_corner: p.lower_left_corner,
+            horizontal: p.horizontal,
+            vertical: p.vertical,
+            u: p.u,
+            v: p.v,
+            w: Vec3::cross(p.u, p.v),
+            lens_radius: p.lens_radius,
+        };
+        let json = serde_json::to_string(&legacy).unwrap();
+        let loaded = serde_json::from_str::<CameraFile>(&json).unwrap().into_camera(1.5);
+        assert_eq!(basis(&loaded), basis(&original));
+
+        // written back out as a description that gives the same view
+        let description = match loaded.to_file(1.5) {
+            CameraFile::Description(description) => description,
+            CameraFile::Legacy(_) => panic!("written as a legacy camera"),
+        };
+        assert_eq!(description.aspect_ratio, None);
+        assert!(f32::abs(description.vfov - 20.0) < 1e-3);
+        assert!(f32::abs(description.focus_distance() - 10.0) < 1e-3);
+        let rebuilt = Camera::from_description(description, 1.5);
+        assert!(basis(&rebuilt).iter().zip(basis(&original)).all(|(a, b)| close(*a, b)));
+    }
+
+    #[test]
+    fn ray_times_are_spread_over_the_shutter() {
+        let mut description = camera(1.5).description().clone();
+        description.shutter = Shutter { open: 2.0, close: 2.5 };
+        let camera = Camera::from_description(description, 1.5);
+        let mut sampler = crate::sampler::IndependentSampler::new(7);
+        let times: Vec<f32> = (0..64)
+            .map(|index| {
+                sampler.start_pixel_sample((0, 0), index);
+                camera.get_ray(0.5, 0.5, &mut sampler).unwrap().time
+            })
+            .collect();
+        assert!(times.iter().all(|time| (2.0..2.5).contains(time)));
+        assert!(times.iter().any(|time| *time < 2.1) && times.iter().any(|time| *time > 2.4));
+
+        let json = serde_json::to_string(&camera.to_file(1.5)).unwrap();
+        assert!(json.contains(r#""shutter":{"open":2.0,"close":2.5}"#));
+    }
+
+    #[test]
+    fn orthographic_rays_are_parallel() {
+        let json = r#"{
+            "look_from": { "vec": [0, 0, 10] },
+            "look_at": { "vec": [0, 0, 0] },
+            "projection": { "type": "Orthographic", "view_width": 4 },
+            "aperture": 0.5
+        }"#;
+        let camera = serde_json::from_str::<CameraFile>(json).unwrap().into_camera(2.0);
+        let mut sampler = crate::sampler::IndependentSampler::new(7);
+        sampler.start_pixel_sample((0, 0), 0);
+        let corner = camera.get_ray(0.0, 0.0, &mut sampler).unwrap();
+        let center = camera.get_ray(0.5, 0.5, &mut sampler).unwrap();
+        let far_corner = camera.get_ray(1.0, 1.0, &mut sampler).unwrap();
+        for ray in [corner, center, far_corner] {
+            assert!(close(ray.direction, Vec3::new(0.0, 0.0, -1.0)));
+        }
+        // 4 wide and 2 high around look_from, the aperture doesn't move them
+        assert!(close(corner.origin, Point::new(-2.0, -1.0, 10.0)));
+        assert!(close(center.origin, Point::new(0.0, 0.0, 10.0)));
+        assert!(close(far_corner.origin, Point::new(2.0, 1.0, 10.0)));
+
+        let written = serde_json::to_string(&camera.to_file(2.0)).unwrap();
+        assert!(written.contains(r#""projection":{"type":"Orthographic","view_width":4.0}"#));
+    }
+
+    fn panoramic(projection: Projection, aspect_ratio: f32) -> Camera {
+        let mut description = camera(aspect_ratio).description().clone();
+        description.projection = projection;
+        description.aspect_ratio = None;
+        Camera::from_description(description, aspect_ratio)
+    }
+
+    fn direction(camera: &Camera, s: f32, t: f32) -> Option<Vec3> {
+        camera.model().get_ray(s, t, (0.5, 0.5), 0.0).map(|ray| ray.direction.unit_vector())
+    }
+
+    #[test]
+    fn equirectangular_covers_the_whole_sphere() {
+        let camera = panoramic(Projection::Equirectangular, 2.0);
+        let frame = CameraFrame::new(camera.description());
+        let forward = -frame.w;
+        assert!(close(direction(&camera, 0.5, 0.5).unwrap(), forward));
+        assert!(close(direction(&camera, 0.0, 0.5).unwrap(), -forward));
+        assert!(close(direction(&camera, 1.0, 0.5).unwrap(), -forward));
+        assert!(close(direction(&camera, 0.75, 0.5).unwrap(), frame.u));
+        assert!(close(direction(&camera, 0.3, 1.0).unwrap(), frame.v));
+        assert!(close(direction(&camera, 0.9, 0.0).unwrap(), -frame.v));
+    }
+
+    #[test]
+    fn fisheyes_map_the_image_circle_by_angle() {
+        for mapping in [FisheyeMapping::Equidistant, FisheyeMapping::Equisolid] {
+            let camera = panoramic(Projection::Fisheye { fov: 180.0, mapping }, 1.5);
+            let frame = CameraFrame::new(camera.description());
+            assert!(close(direction(&camera, 0.5, 0.5).unwrap(), -frame.w));
+            // the edge of the circle is 90 degrees off to the side, at the top and bottom of the image
+            assert!(close(direction(&camera, 0.5, 1.0).unwrap(), frame.v));
+            assert!(close(direction(&camera, 0.5, 0.0).unwrap(), -frame.v));
+            // the circle is only as wide as the image is high
+            let circle_edge = 0.5 + 0.5 / 1.5 - 1e-6;
+            assert!(close(direction(&camera, circle_edge, 0.5).unwrap(), frame.u));
+            assert!(direction(&camera, 0.95, 0.5).is_none());
+            assert!(direction(&camera, 0.0, 0.0).is_none());
+        }
+
+        // halfway out from the center is 45 degrees off with equidistant, less with equisolid
+        let off_axis = |mapping: FisheyeMapping| {
+            let camera = panoramic(Projection::Fisheye { fov: 180.0, mapping }, 1.0);
+            let forward = -CameraFrame::new(camera.description()).w;
+            f32::acos(direction(&camera, 0.5, 0.75).unwrap().dot(forward)).to_degrees()
+        };
+        assert!(f32::abs(off_axis(FisheyeMapping::Equidistant) - 45.0) < 1e-3);
+        let equisolid = 2.0 * f32::asin(0.5 * f32::sin(PI / 4.0));
+        assert!(f32::abs(off_axis(FisheyeMapping::Equisolid) - equisolid.to_degrees()) < 1e-3);
+    }
+
+    fn stereo(projection: Projection, layout: StereoLayout, image_aspect_ratio: f32) -> Camera {
+        let mut description = panoramic(projection, image_aspect_ratio).description().clone();
+        description.aspect_ratio = None;
+        description.stereo = Some(Stereo { layout, interpupillary_distance: 0.5, convergence_distance: Some(4.0) });
+        Camera::from_description(description, image_aspect_ratio)
+    }
+
+    #[test]
+    fn stereo_eyes_look_through_the_same_window() {
+        let camera = stereo(Projection::Perspective, StereoLayout::SideBySide, 3.0);
+        assert_eq!(camera.aspect_ratio(), 1.5);
+        let frame = CameraFrame::new(camera.description());
+        let window_center = frame.origin - 4.0 * frame.w;
+        let window_corner = |camera: &RenderableCamera, s: f32, t: f32| {
+            let ray = camera.get_ray(s, t, (0.5, 0.5), 0.0).unwrap();
+            // where the ray crosses the plane at the convergence distance
+            let along = 4.0 / ray.direction.dot(-frame.w);
+            (ray.origin, ray.origin + along * ray.direction)
+        };
+        let (left, left_center) = window_corner(camera.eye(Eye::Left), 0.5, 0.5);
+        let (right, right_center) = window_corner(camera.eye(Eye::Right), 0.5, 0.5);
+        assert!(close(left, frame.origin - 0.25 * frame.u) && close(right, frame.origin + 0.25 * frame.u));
+        assert!(close(left_center, window_center) && close(right_center, window_center));
+        let (_, left_corner) = window_corner(camera.eye(Eye::Left), 0.0, 1.0);
+        let (_, right_corner) = window_corner(camera.eye(Eye::Right), 0.0, 1.0);
+        assert!(close(left_corner, right_corner));
+
+        // the left half of the image is the left eye's
+        let mut sampler = crate::sampler::IndependentSampler::new(7);
+        sampler.start_pixel_sample((0, 0), 0);
+        let ray = camera.get_ray(0.25, 0.5, &mut sampler).unwrap();
+        assert!((ray.origin - left).len() <= 0.05 + 1e-4);
+
+        let json = serde_json::to_string(&camera.to_file(3.0)).unwrap();
+        assert!(!json.contains("aspect_ratio") && json.contains(r#""stereo":{"layout":"SideBySide""#));
+    }
+
+    #[test]
+    fn omni_directional_stereo_circles_look_from() {
+        let camera = stereo(Projection::Equirectangular, StereoLayout::TopBottom, 1.0);
+        assert_eq!(camera.aspect_ratio(), 2.0);
+        let frame = CameraFrame::new(camera.description());
+        for s in [0.0, 0.2, 0.5, 0.75, 0.9] {
+            for (eye, side) in [(Eye::Left, -1.0), (Eye::Right, 1.0)] {
+                let ray = camera.eye(eye).get_ray(s, 0.5, (0.5, 0.5), 0.0).unwrap();
+                let offset = ray.origin - frame.origin;
+                assert!(f32::abs(offset.len() - 0.25) < 1e-5);
+                let center_direction = direction(&panoramic(Projection::Equirectangular, 2.0), s, 0.5).unwrap();
+                // off to the eye's side of the direction looked in, and converging 4 away along it
+                assert!(f32::abs(offset.dot(center_direction)) < 1e-5);
+                assert!(Vec3::cross(center_direction, offset).dot(frame.v) * side < 0.0);
+                assert!(close(ray.origin + ray.direction, frame.origin + 4.0 * center_direction));
+            }
+        }
+        // both eyes are at look_from looking straight up
+        let up = camera.eye(Eye::Left).get_ray(0.3, 1.0, (0.5, 0.5), 0.0).unwrap();
+        assert!(close(up.origin, frame.origin) && close(up.direction.unit_vector(), frame.v));
+
+        // the top half of the image is the left eye's
+        assert_eq!(StereoLayout::TopBottom.eye_at(0.3, 0.75), (Eye::Left, 0.3, 0.5));
+        assert_eq!(StereoLayout::TopBottom.eye_at(0.3, 0.25), (Eye::Right, 0.3, 0.5));
+    }
+
+    #[test]
+    fn exposures_set_the_aperture_and_brightness() {
+        // a 50 mm lens on a full frame sensor at f/2
+        let json = r#"{
+            "look_from": { "vec": [0, 0, 5] },
+            "look_at": { "vec": [0, 0, 0] },
+            "vfov": 27,
+            "aperture_shape": { "type": "Polygon", "blades": 5, "rotation": 90 },
+            "exposure": { "f_number": 2 }
+        }"#;
+        let mut description = match serde_json::from_str::<CameraFile>(json).unwrap() {
+            CameraFile::Description(description) => description,
+            CameraFile::Legacy(_) => panic!("read as a legacy camera"),
+        };
+        description.vfov = 2.0 * f32::atan(0.24).to_degrees();
+        let exposure = description.exposure.unwrap();
+        assert!(f32::abs(exposure.focal_length(description.vfov) - 50.0) < 1e-3);
+        assert!(f32::abs(description.aperture() - 0.025) < 1e-6);
+        // the sunny 16 rule is the reference, f/2 lets in 6 stops more
+        assert!(f32::abs(exposure.radiance_scale() - 64.0) < 1e-3);
+        let sunny = PhotographicExposure { f_number: 16.0, ..exposure };
+        assert!(f32::abs(sunny.radiance_scale() - 1.0) < 1e-6);
+
+        let camera = Camera::from_description(description, 1.0);
+        assert!(f32::abs(camera.radiance_scale() - 64.0) < 1e-3);
+        // rays start inside the pentagon, which has a corner straight up and a flat side at the bottom
+        let lens_radius = perspective(&camera).lens_radius;
+        let bottom = -lens_radius * f32::cos(PI / 5.0);
+        for i in 0..16 {
+            for j in 0..16 {
+                let lens = ((i as f32 + 0.5) / 16.0, (j as f32 + 0.5) / 16.0);
+                let origin = camera.model().get_ray(0.5, 0.5, lens, 0.0).unwrap().origin;
+                assert!(origin.y() >= bottom - 1e-6 && (origin - Point::new(0.0, 0.0, 5.0)).len() <= lens_radius + 1e-6);
+            }
+        }
+        let written = serde_json::to_string(&camera.to_file(1.0)).unwrap();
+        assert!(written.contains(r#""aperture_shape":{"type":"Polygon","blades":5,"rotation":90.0}"#));
+    }
+
+    #[test]
+    fn stretched_viewports_keep_their_aspect_ratio() {
+        let stretched = camera(1.0);
+        let description = match stretched.to_file(1.5) {
+            CameraFile::Description(description) => description,
+            CameraFile::Legacy(_) => panic!("written as a legacy camera"),
+        };
+        assert_eq!(description.aspect_ratio, Some(1.0));
+        assert_eq!(basis(&Camera::from_description(description, 1.5)), basis(&stretched));
+    }
 }
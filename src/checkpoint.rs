@@ -0,0 +1,122 @@
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+use crate::aov::Aov;
+use crate::film::PixelBounds;
+use crate::filter::Filter;
+use crate::renderer::{Accumulation, Integrator};
+use crate::sampler::SamplerType;
+use crate::scene::Scene;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CheckpointOptions {
+    pub path: PathBuf,
+    // by default checkpoints are written after every pass, with this set whenever this long has gone by since
+    // the last one instead, also while a pass is running (there's always one at the end of the render)
+    pub interval: Option<Duration>,
+}
+
+/**
+ * A render's accumulated state saved to disk: the filtered radiance sums, every pixel's running estimate
+ * (which includes its sample count) and its AOV accumulators. There is no random number generator state to
+ * save, every sample's random numbers are derived from the seed, pixel and sample index, so resuming with the
+ * same settings carries on with exactly the samples the render would have taken next.
+ */
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Checkpoint {
+    pub(crate) settings: CheckpointSettings,
+    pub(crate) accumulation: Accumulation,
+}
+
+// everything that decides what the samples of a render are, apart from how many there are
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CheckpointSettings {
+    pub image_width: i32,
+    pub image_height: i32,
+    // the pixels that get samples, which is less than the whole image with a crop window
+    pub region: PixelBounds,
+    pub seed: u64,
+    pub sampler: SamplerType,
+    pub filter: Filter,
+    pub integrator: Integrator,
+    pub max_depth: i32,
+    pub filtered_aovs: Vec<Aov>,
+    pub pixel_aovs: Vec<Aov>,
+    // see `scene_hash`
+    pub scene_hash: u64,
+}
+
+impl CheckpointSettings {
+    // the first setting that differs from `other`, continuing a render with it would blend two different images
+    pub fn mismatch(&self, other: &CheckpointSettings) -> Option<&'static str> {
+        if (self.image_width, self.image_height) != (other.image_width, other.image_height) {
+            Some("image size")
+        } else if self.region != other.region {
+            Some("crop window")
+        } else if self.seed != other.seed {
+            Some("seed")
+        } else if self.sampler != other.sampler {
+            Some("sampler")
+        } else if self.filter != other.filter {
+            Some("filter")
+        } else if (self.integrator, self.max_depth) != (other.integrator, other.max_depth) {
+            Some("integrator")
+        } else if (&self.filtered_aovs, &self.pixel_aovs) != (&other.filtered_aovs, &other.pixel_aovs) {
+            Some("set of AOVs")
+        } else if self.scene_hash != other.scene_hash {
+            Some("camera or world")
+        } else {
+            None
+        }
+    }
+}
+
+impl Checkpoint {
+    pub fn settings(&self) -> &CheckpointSettings {
+        &self.settings
+    }
+
+    // the fewest samples any pixel has so far
+    pub fn samples_per_pixel(&self) -> u32 {
+        self.accumulation.estimates.iter().map(|estimate| estimate.count()).min().unwrap_or(0)
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Checkpoint> {
+        let reader = BufReader::new(File::open(path)?);
+        Ok(serde_json::from_reader(reader)?)
+    }
+
+    /**
+     * Writes to a temporary file next to `path` first and then moves it into place, so a crash while saving
+     * leaves the previous checkpoint intact.
+     */
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let path = path.as_ref();
+        let mut temporary = path.as_os_str().to_owned();
+        temporary.push(".tmp");
+        let mut writer = BufWriter::new(File::create(&temporary)?);
+        serde_json::to_writer(&mut writer, self)?;
+        writer.flush()?;
+        drop(writer);
+        fs::rename(&temporary, path)
+    }
+}
+
+/**
+ * FNV-1a over the camera and world as JSON. Everything else in the scene either has its own checkpoint setting
+ * or (like the sample count) is fine to change when resuming.
+ */
+pub fn scene_hash(scene: &Scene) -> u64 {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for json in [serde_json::to_vec(&scene.camera.description()), serde_json::to_vec(&scene.world)] {
+        for byte in json.expect("scene should serialize") {
+            hash ^= byte as u64;
+            hash = hash.wrapping_mul(0x100000001b3);
+        }
+    }
+    hash
+}
@@ -0,0 +1,252 @@
+use serde::{Deserialize, Serialize};
+
+use crate::framebuffer::FrameBuffer;
+use crate::tonemap::luminance;
+use crate::util::Color;
+
+// albedo below this is treated as black, there's nothing to divide the lighting out of
+const MIN_ALBEDO: f32 = 0.01;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum DenoiseMethod {
+    // neighbours are weighted by distance, feature similarity and color difference relative to the noise level
+    JointBilateral,
+    // like JointBilateral, but colors are compared over a small patch around each pixel instead of one pixel
+    NonLocalMeans,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DenoiseOptions {
+    pub method: DenoiseMethod,
+    // the filter looks at a (2 * radius + 1)^2 window around every pixel
+    pub radius: i32,
+    // only used by NonLocalMeans
+    pub patch_radius: i32,
+    pub spatial_sigma: f32,
+    pub albedo_sigma: f32,
+    // exponent on the cosine between normals, higher keeps edges between faces sharper
+    pub normal_power: f32,
+    // relative depth difference at which neighbours stop contributing
+    pub depth_sigma: f32,
+    // how many standard deviations of noise a color difference may be before it's considered an edge
+    pub strength: f32,
+}
+
+impl Default for DenoiseOptions {
+    fn default() -> Self {
+        Self {
+            method: DenoiseMethod::JointBilateral,
+            radius: 5,
+            patch_radius: 1,
+            spatial_sigma: 3.0,
+            albedo_sigma: 0.1,
+            normal_power: 32.0,
+            depth_sigma: 0.1,
+            strength: 2.0,
+        }
+    }
+}
+
+// the AOVs the denoiser is guided by, all the same size as the image being denoised
+pub struct DenoiseFeatures<'a> {
+    pub albedo: &'a FrameBuffer,
+    pub normal: &'a FrameBuffer,
+    pub depth: &'a FrameBuffer,
+    // variance of each pixel's mean luminance
+    pub variance: &'a FrameBuffer,
+}
+
+/**
+ * Denoises `color` with a cross filter guided by the feature buffers.
+ *
+ * Lighting is first divided by the albedo so that texture detail isn't blurred along with the noise, and
+ * multiplied back afterwards. Each neighbour's weight is the product of a spatial gaussian, how closely
+ * its albedo, normal and depth match, and how far its color is from this pixel's compared to how noisy
+ * the two pixels are, so flat noisy areas get averaged heavily while converged pixels are left alone.
+ */
+pub fn denoise(color: &FrameBuffer, features: &DenoiseFeatures, options: &DenoiseOptions) -> FrameBuffer {
+    let width = color.width();
+    let height = color.height();
+
+    // demodulate albedo, both from the color and from the variance estimate
+    let mut irradiance = FrameBuffer::new(width, height);
+    let mut variance = vec![0.0; (width * height) as usize];
+    for y in 0..height {
+        for x in 0..width {
+            let albedo = features.albedo.get(x, y);
+            irradiance.set(x, y, demodulate(color.get(x, y), albedo));
+            let albedo_luminance = f32::max(luminance(albedo), MIN_ALBEDO);
+            variance[(y * width + x) as usize] = features.variance.get(x, y).x() / (albedo_luminance * albedo_luminance);
+        }
+    }
+
+    let mut denoised = FrameBuffer::new(width, height);
+    for y in 0..height {
+        for x in 0..width {
+            let mut sum = Color::zero();
+            let mut weight_sum = 0.0;
+            for qy in i32::max(y - options.radius, 0)..=i32::min(y + options.radius, height - 1) {
+                for qx in i32::max(x - options.radius, 0)..=i32::min(x + options.radius, width - 1) {
+                    let weight = feature_weight(features, options, (x, y), (qx, qy))
+                        * color_weight(&irradiance, &variance, options, (x, y), (qx, qy));
+                    sum += weight * irradiance.get(qx, qy);
+                    weight_sum += weight;
+                }
+            }
+            // the center pixel always has weight 1, so weight_sum is never 0
+            let filtered = sum / weight_sum;
+            denoised.set(x, y, remodulate(filtered, features.albedo.get(x, y)));
+        }
+    }
+    denoised
+}
+
+fn demodulate(color: Color, albedo: Color) -> Color {
+    let channel = |c: f32, a: f32| if a > MIN_ALBEDO { c / a } else { c };
+    Color::new(
+        channel(color.x(), albedo.x()),
+        channel(color.y(), albedo.y()),
+        channel(color.z(), albedo.z()),
+    )
+}
+
+fn remodulate(irradiance: Color, albedo: Color) -> Color {
+    let channel = |c: f32, a: f32| if a > MIN_ALBEDO { c * a } else { c };
+    Color::new(
+        channel(irradiance.x(), albedo.x()),
+        channel(irradiance.y(), albedo.y()),
+        channel(irradiance.z(), albedo.z()),
+    )
+}
+
+fn feature_weight(features: &DenoiseFeatures, options: &DenoiseOptions, p: (i32, i32), q: (i32, i32)) -> f32 {
+    let dx = (p.0 - q.0) as f32;
+    let dy = (p.1 - q.1) as f32;
+    let spatial = f32::exp(-(dx * dx + dy * dy) / (2.0 * options.spatial_sigma * options.spatial_sigma));
+
+    let albedo_difference = (features.albedo.get(p.0, p.1) - features.albedo.get(q.0, q.1)).len_squared();
+    let albedo = f32::exp(-albedo_difference / (2.0 * options.albedo_sigma * options.albedo_sigma));
+
+    let normal_p = features.normal.get(p.0, p.1);
+    let normal_q = features.normal.get(q.0, q.1);
+    // misses have a zero normal, they should only blend with other misses
+    let normal = if normal_p.near_zero() || normal_q.near_zero() {
+        if normal_p.near_zero() == normal_q.near_zero() { 1.0 } else { 0.0 }
+    } else {
+        f32::powf(f32::max(normal_p.dot(normal_q), 0.0), options.normal_power)
+    };
+
+    let depth_p = features.depth.get(p.0, p.1).x();
+    let depth_q = features.depth.get(q.0, q.1).x();
+    let depth_difference = f32::abs(depth_p - depth_q) / f32::max(f32::max(depth_p, depth_q), 1e-4);
+    let depth = f32::exp(-depth_difference / options.depth_sigma);
+
+    spatial * albedo * normal * depth
+}
+
+fn color_weight(irradiance: &FrameBuffer, variance: &[f32], options: &DenoiseOptions, p: (i32, i32), q: (i32, i32)) -> f32 {
+    let width = irradiance.width();
+    let height = irradiance.height();
+    let pixel_variance = |x: i32, y: i32| variance[(y * width + x) as usize];
+    let difference = |a: (i32, i32), b: (i32, i32)| {
+        // squared luminance difference, minus the part of it that's explained by noise alone
+        let d = luminance(irradiance.get(a.0, a.1)) - luminance(irradiance.get(b.0, b.1));
+        let var_a = pixel_variance(a.0, a.1);
+        let var_b = pixel_variance(b.0, b.1);
+        (d * d - (var_a + f32::min(var_a, var_b))) / (1e-6 + options.strength * options.strength * (var_a + var_b))
+    };
+
+    let distance = match options.method {
+        DenoiseMethod::JointBilateral => difference(p, q),
+        DenoiseMethod::NonLocalMeans => {
+            let mut total = 0.0;
+            let mut count = 0;
+            for oy in -options.patch_radius..=options.patch_radius {
+                for ox in -options.patch_radius..=options.patch_radius {
+                    let a = (p.0 + ox, p.1 + oy);
+                    let b = (q.0 + ox, q.1 + oy);
+                    let inside = |c: (i32, i32)| c.0 >= 0 && c.1 >= 0 && c.0 < width && c.1 < height;
+                    if inside(a) && inside(b) {
+                        total += difference(a, b);
+                        count += 1;
+                    }
+                }
+            }
+            total / (count as f32)
+        }
+    };
+    f32::exp(-f32::max(distance, 0.0))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn filled(width: i32, height: i32, color: Color) -> FrameBuffer {
+        let mut buffer = FrameBuffer::new(width, height);
+        for y in 0..height {
+            for x in 0..width {
+                buffer.set(x, y, color);
+            }
+        }
+        buffer
+    }
+
+    fn flat_features(width: i32, height: i32) -> (FrameBuffer, FrameBuffer, FrameBuffer, FrameBuffer) {
+        (
+            filled(width, height, Color::new(0.5, 0.5, 0.5)),
+            filled(width, height, Color::new(0.0, 1.0, 0.0)),
+            filled(width, height, Color::new(2.0, 2.0, 2.0)),
+            filled(width, height, Color::new(0.05, 0.05, 0.05)),
+        )
+    }
+
+    #[test]
+    fn constant_image_is_unchanged() {
+        let (albedo, normal, depth, variance) = flat_features(8, 8);
+        let features = DenoiseFeatures { albedo: &albedo, normal: &normal, depth: &depth, variance: &variance };
+        let color = filled(8, 8, Color::new(0.3, 0.2, 0.1));
+        for method in [DenoiseMethod::JointBilateral, DenoiseMethod::NonLocalMeans] {
+            let options = DenoiseOptions { method, ..Default::default() };
+            let denoised = denoise(&color, &features, &options);
+            for pixel in denoised.pixels() {
+                assert!((*pixel - Color::new(0.3, 0.2, 0.1)).len() < 1e-5);
+            }
+        }
+    }
+
+    #[test]
+    fn noise_is_reduced_on_flat_surfaces() {
+        let (albedo, normal, depth, variance) = flat_features(16, 16);
+        let features = DenoiseFeatures { albedo: &albedo, normal: &normal, depth: &depth, variance: &variance };
+        // checkerboard of +-0.2 around 0.5 is about as noisy as the variance buffer claims
+        let mut color = FrameBuffer::new(16, 16);
+        for y in 0..16 {
+            for x in 0..16 {
+                let value = if (x + y) % 2 == 0 { 0.7 } else { 0.3 };
+                color.set(x, y, Color::new(value, value, value));
+            }
+        }
+        let denoised = denoise(&color, &features, &DenoiseOptions::default());
+        let center = denoised.get(8, 8);
+        assert!(f32::abs(center.x() - 0.5) < 0.05, "got {}", center);
+    }
+
+    #[test]
+    fn albedo_edges_are_preserved() {
+        let (mut albedo, normal, depth, variance) = flat_features(16, 16);
+        let mut color = FrameBuffer::new(16, 16);
+        for y in 0..16 {
+            for x in 0..16 {
+                let value = if x < 8 { 0.1 } else { 0.9 };
+                albedo.set(x, y, Color::new(value, value, value));
+                color.set(x, y, Color::new(value, value, value));
+            }
+        }
+        let features = DenoiseFeatures { albedo: &albedo, normal: &normal, depth: &depth, variance: &variance };
+        let denoised = denoise(&color, &features, &DenoiseOptions::default());
+        assert!((denoised.get(7, 8) - Color::new(0.1, 0.1, 0.1)).len() < 1e-3);
+        assert!((denoised.get(8, 8) - Color::new(0.9, 0.9, 0.9)).len() < 1e-3);
+    }
+}
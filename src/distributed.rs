@@ -0,0 +1,544 @@
+use std::collections::VecDeque;
+use std::fmt;
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::net::{TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::{mpsc, Arc, Condvar, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+
+use crate::adaptive::AdaptiveSampling;
+use crate::aov::Aov;
+use crate::checkpoint::CheckpointSettings;
+use crate::film::PixelBounds;
+use crate::filter::Filter;
+use crate::progress::{CancellationToken, RenderProgress};
+use crate::renderer::{
+    tiles_for_bounds, AccelStructure, CropOptions, Integrator, RenderOptions, RenderOutput, Renderer, TileResult,
+};
+use crate::sampler::SamplerType;
+use crate::scene::Scene;
+use crate::validate::{validate_scene, Issue};
+
+/**
+ * Rendering one image on several worker processes, on this machine or others.
+ *
+ * Workers listen on a TCP port. The coordinator connects to every one of them, sends over the scene and the
+ * settings that decide what the samples are, and then hands out tiles, keeping as many in flight on each
+ * worker as it has threads. Workers send back every tile's film and pixel state, which the coordinator merges
+ * in tile order just like a local render does. Since a sample's random numbers only depend on the seed, pixel
+ * and sample index, the result is exactly the image a single machine would have rendered.
+ *
+ * Messages are JSON, one per line. A tile a worker doesn't return (because it hung up, say) goes to another
+ * worker.
+ */
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "type")]
+enum Message {
+    // worker to coordinator as soon as it's connected
+    Hello { threads: usize },
+    // coordinator to worker, once before any tiles
+    Setup { scene: serde_json::Value, options: JobOptions, settings: CheckpointSettings },
+    // coordinator to worker, sample every pixel of the tile up to `target` samples
+    Render { index: usize, tile: PixelBounds, target: i32 },
+    // worker to coordinator
+    Rendered { result: TileResult },
+    // worker to coordinator when it can't render what it was sent
+    Failed { message: String },
+}
+
+// the render options a worker needs to take the same samples the coordinator would
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct JobOptions {
+    accel_structure: AccelStructure,
+    integrator: Integrator,
+    samples_per_pixel: i32,
+    adaptive_sampling: Option<AdaptiveSampling>,
+    max_depth: i32,
+    sampler: SamplerType,
+    seed: u64,
+    filter: Filter,
+    crop: Option<CropOptions>,
+    aovs: Vec<Aov>,
+}
+
+impl JobOptions {
+    fn new(renderer: &Renderer) -> Self {
+        let options = renderer.options();
+        Self {
+            accel_structure: options.accel_structure,
+            integrator: options.integrator,
+            samples_per_pixel: renderer.samples_per_pixel(),
+            adaptive_sampling: renderer.adaptive_sampling(),
+            max_depth: options.max_depth,
+            sampler: options.sampler,
+            seed: renderer.seed(),
+            filter: renderer.filter(),
+            crop: options.crop,
+            aovs: renderer.sampled_aovs().to_vec(),
+        }
+    }
+
+    // the denoiser only runs on the coordinator, the worker just samples the passes it needs
+    fn render_options(&self) -> RenderOptions {
+        RenderOptions {
+            threads: 1,
+            accel_structure: self.accel_structure,
+            integrator: self.integrator,
+            samples_per_pixel: Some(self.samples_per_pixel),
+            adaptive_sampling: self.adaptive_sampling,
+            max_depth: self.max_depth,
+            sampler: self.sampler,
+            seed: Some(self.seed),
+            filter: Some(self.filter),
+            crop: self.crop,
+            aovs: self.aovs.clone(),
+            ..Default::default()
+        }
+    }
+}
+
+fn write_message<W: Write>(writer: &mut W, message: &Message) -> io::Result<()> {
+    serde_json::to_writer(&mut *writer, message)?;
+    writer.write_all(b"\n")?;
+    writer.flush()
+}
+
+// None once the other side has hung up
+fn read_message<R: BufRead>(reader: &mut R) -> io::Result<Option<Message>> {
+    let mut line = String::new();
+    if reader.read_line(&mut line)? == 0 {
+        return Ok(None);
+    }
+    Ok(Some(serde_json::from_str(&line)?))
+}
+
+fn unexpected(message: &Message) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("unexpected message: {:?}", message))
+}
+
+fn hung_up() -> io::Error {
+    io::Error::new(io::ErrorKind::UnexpectedEof, "the other side hung up")
+}
+
+// something that went wrong on a worker, which carries on serving other coordinators regardless
+#[derive(Debug)]
+pub enum WorkerError {
+    // a connection that couldn't be accepted
+    Accept(io::Error),
+    // the render for the coordinator at `peer` stopped partway
+    Render { peer: String, error: io::Error },
+}
+
+impl fmt::Display for WorkerError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WorkerError::Accept(error) => write!(f, "unable to accept a connection: {}", error),
+            WorkerError::Render { peer, error } => write!(f, "render for {} failed: {}", peer, error),
+        }
+    }
+}
+
+/**
+ * Serves every coordinator that connects to `listener`, each on its own thread, for as long as the process
+ * runs. `on_error` is told about connections that fail, from whichever thread they failed on.
+ */
+pub fn run_worker<E>(listener: TcpListener, threads: usize, on_error: E)
+where
+    E: Fn(WorkerError) + Send + Sync + 'static,
+{
+    let on_error = Arc::new(on_error);
+    for stream in listener.incoming() {
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(error) => {
+                on_error(WorkerError::Accept(error));
+                // e.g. out of file descriptors, which won't be better right away
+                thread::sleep(Duration::from_millis(100));
+                continue;
+            }
+        };
+        let on_error = Arc::clone(&on_error);
+        thread::spawn(move || {
+            let peer = stream.peer_addr().map(|address| address.to_string()).unwrap_or_default();
+            if let Err(error) = serve_coordinator(stream, threads) {
+                on_error(WorkerError::Render { peer, error });
+            }
+        });
+    }
+}
+
+// renders tiles for one coordinator, `threads` at a time, until it hangs up
+pub fn serve_coordinator(stream: TcpStream, threads: usize) -> io::Result<()> {
+    let threads = usize::max(threads, 1);
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let writer = Mutex::new(BufWriter::new(stream));
+    write_message(&mut *writer.lock().unwrap(), &Message::Hello { threads })?;
+
+    let renderer = match read_message(&mut reader)? {
+        Some(Message::Setup { scene, options, settings }) => match setup_renderer(scene, &options, &settings) {
+            Ok(renderer) => renderer,
+            Err(message) => {
+                write_message(&mut *writer.lock().unwrap(), &Message::Failed { message: message.clone() })?;
+                return Err(io::Error::new(io::ErrorKind::InvalidData, message));
+            }
+        },
+        Some(message) => return Err(unexpected(&message)),
+        None => return Ok(()),
+    };
+    // tiles are always rendered from scratch, so the workers share one empty accumulation to start from
+    let accumulation = renderer.new_accumulation();
+    let cancel = CancellationToken::new();
+
+    let (job_transmitter, job_receiver) = mpsc::channel::<(usize, PixelBounds, i32)>();
+    let job_receiver = Mutex::new(job_receiver);
+    thread::scope(|s| {
+        let workers: Vec<_> = (0..threads)
+            .map(|_| {
+                s.spawn(|| -> io::Result<()> {
+                    loop {
+                        let job = job_receiver.lock().unwrap().recv();
+                        let Ok((index, tile, target)) = job else {
+                            return Ok(());
+                        };
+                        let result = renderer.render_tile(index, tile, target, &accumulation, &cancel);
+                        write_message(&mut *writer.lock().unwrap(), &Message::Rendered { result })?;
+                    }
+                })
+            })
+            .collect();
+
+        let read_outcome = loop {
+            match read_message(&mut reader) {
+                Ok(Some(Message::Render { index, tile, target })) => job_transmitter.send((index, tile, target)).unwrap(),
+                Ok(None) => break Ok(()),
+                Ok(Some(message)) => break Err(unexpected(&message)),
+                Err(error) => break Err(error),
+            }
+        };
+        // nothing more is coming, so the workers finish once the queue is empty
+        drop(job_transmitter);
+        let write_outcome = workers.into_iter().try_for_each(|worker| worker.join().unwrap());
+        read_outcome.and(write_outcome)
+    })
+}
+
+// the renderer a coordinator's setup describes, or why the worker can't render it
+fn setup_renderer(scene: serde_json::Value, options: &JobOptions, settings: &CheckpointSettings) -> Result<Renderer, String> {
+    let scene: Scene = serde_json::from_value(scene).map_err(|error| format!("the scene couldn't be read: {}", error))?;
+    let errors: Vec<String> = validate_scene(&scene).iter().filter(|issue| issue.is_error()).map(Issue::to_string).collect();
+    if !errors.is_empty() {
+        return Err(format!("the scene isn't valid: {}", errors.join(", ")));
+    }
+    let renderer = Renderer::new(scene, options.render_options());
+    // e.g. a worker built from different code
+    match renderer.checkpoint_settings().mismatch(settings) {
+        Some(setting) => Err(format!("the worker ended up with a different {}", setting)),
+        None => Ok(renderer),
+    }
+}
+
+// the work the coordinator shares out between its workers
+struct Assignment<'a> {
+    tiles: &'a [PixelBounds],
+    target: i32,
+    queue: Mutex<TileQueue>,
+    // signalled whenever a tile comes back or is put back into the queue
+    changed: Condvar,
+    cancel: &'a CancellationToken,
+}
+
+struct TileQueue {
+    // indices of the tiles no worker is working on
+    pending: VecDeque<usize>,
+    // tiles that haven't come back yet, pending or not
+    outstanding: usize,
+}
+
+/**
+ * Keeps one worker busy with pending tiles until every tile is back or the render is cancelled. Tiles it had
+ * in flight when something went wrong go back into the queue for the other workers, which is why a worker
+ * with nothing left to do waits for the others to finish instead of hanging up.
+ */
+fn drive_worker(
+    mut reader: BufReader<TcpStream>,
+    mut writer: BufWriter<TcpStream>,
+    threads: usize,
+    assignment: &Assignment,
+    results: mpsc::Sender<TileResult>,
+) -> io::Result<()> {
+    let mut in_flight: Vec<usize> = vec![];
+    let mut outcome = || -> io::Result<()> {
+        loop {
+            let mut picked_up = vec![];
+            {
+                let mut queue = assignment.queue.lock().unwrap();
+                while in_flight.len() + picked_up.len() < threads && !assignment.cancel.is_cancelled() {
+                    match queue.pending.pop_front() {
+                        Some(index) => picked_up.push(index),
+                        None => break,
+                    }
+                }
+                if in_flight.is_empty() && picked_up.is_empty() {
+                    if queue.outstanding == 0 || assignment.cancel.is_cancelled() {
+                        return Ok(());
+                    }
+                    // another worker may still fail and put its tiles back, checking for cancellation now and then
+                    let _ = assignment.changed.wait_timeout(queue, Duration::from_millis(100)).unwrap();
+                    continue;
+                }
+            }
+            for index in picked_up {
+                in_flight.push(index);
+                let message = Message::Render { index, tile: assignment.tiles[index], target: assignment.target };
+                write_message(&mut writer, &message)?;
+            }
+            match read_message(&mut reader)? {
+                Some(Message::Rendered { result }) => {
+                    // a tile that wasn't asked for (or was already sent back) would be merged twice or not at all
+                    if !in_flight.contains(&result.index) {
+                        let message = format!("the worker sent back tile {}, which it wasn't rendering", result.index);
+                        return Err(io::Error::new(io::ErrorKind::InvalidData, message));
+                    }
+                    in_flight.retain(|index| *index != result.index);
+                    assignment.queue.lock().unwrap().outstanding -= 1;
+                    assignment.changed.notify_all();
+                    results.send(result).unwrap();
+                }
+                Some(Message::Failed { message }) => return Err(io::Error::other(message)),
+                Some(message) => return Err(unexpected(&message)),
+                None => return Err(hung_up()),
+            }
+        }
+    };
+    let outcome = outcome();
+    if outcome.is_err() {
+        assignment.queue.lock().unwrap().pending.extend(in_flight);
+        assignment.changed.notify_all();
+    }
+    outcome
+}
+
+impl Renderer {
+    /**
+     * Renders the scene on the workers listening at `workers` (see `run_worker`) instead of on this machine's
+     * threads, in a single pass. `on_progress` is called each time a tile comes back, and `cancel` stops
+     * handing out tiles, returning whatever came back up to that point, like `render_with_progress`.
+     *
+     * Fails if a worker can't be reached or tiles are left over that no worker could render, and for renders
+     * set up to resume, save checkpoints or render in passes, none of which distributed renders do.
+     */
+    pub fn render_distributed<A, F>(&self, workers: &[A], mut on_progress: F, cancel: &CancellationToken) -> io::Result<RenderOutput>
+    where
+        A: ToSocketAddrs,
+        F: FnMut(&RenderProgress),
+    {
+        let start = Instant::now();
+        if workers.is_empty() {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "no workers to render on"));
+        }
+        let options = self.options();
+        if self.is_resumed() || options.checkpoint.is_some() || options.progressive.is_some() {
+            let message = "distributed renders are a single pass from scratch, without checkpoints";
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, message));
+        }
+        let sample_bounds = self.sample_bounds();
+        let tiles = tiles_for_bounds(&sample_bounds);
+        let target = self.max_samples_per_pixel();
+
+        let setup = Message::Setup {
+            scene: serde_json::to_value(self.scene())?,
+            options: JobOptions::new(self),
+            settings: self.checkpoint_settings(),
+        };
+        let mut connections = vec![];
+        for address in workers {
+            let stream = TcpStream::connect(address)?;
+            let mut reader = BufReader::new(stream.try_clone()?);
+            let mut writer = BufWriter::new(stream);
+            let threads = match read_message(&mut reader)? {
+                Some(Message::Hello { threads }) => usize::max(threads, 1),
+                Some(message) => return Err(unexpected(&message)),
+                None => return Err(hung_up()),
+            };
+            write_message(&mut writer, &setup)?;
+            connections.push((reader, writer, threads));
+        }
+
+        let assignment = Assignment {
+            tiles: &tiles,
+            target,
+            queue: Mutex::new(TileQueue { pending: (0..tiles.len()).collect(), outstanding: tiles.len() }),
+            changed: Condvar::new(),
+            cancel,
+        };
+        let mut results: Vec<Option<TileResult>> = (0..tiles.len()).map(|_| None).collect();
+        let mut progress = RenderProgress {
+            tiles_done: 0,
+            tiles_total: tiles.len(),
+            passes_done: 0,
+            passes_total: 1,
+            samples_done: 0,
+            samples_total: sample_bounds.area() as u64 * target as u64,
+            rays_traced: 0,
+            elapsed: start.elapsed(),
+        };
+        let failures: Vec<io::Error> = thread::scope(|s| {
+            let (result_transmitter, result_receiver) = mpsc::channel();
+            let drivers: Vec<_> = connections
+                .into_iter()
+                .map(|(reader, writer, threads)| {
+                    let result_transmitter = result_transmitter.clone();
+                    let assignment = &assignment;
+                    s.spawn(move || drive_worker(reader, writer, threads, assignment, result_transmitter))
+                })
+                .collect();
+            drop(result_transmitter);
+
+            for result in result_receiver {
+                progress.tiles_done += 1;
+                if progress.tiles_done == tiles.len() {
+                    progress.passes_done = 1;
+                }
+                progress.samples_done += result.samples;
+                progress.samples_total -= result.samples_saved;
+                progress.rays_traced += result.rays;
+                progress.elapsed = start.elapsed();
+                on_progress(&progress);
+                let index = result.index;
+                results[index] = Some(result);
+            }
+            drivers.into_iter().filter_map(|driver| driver.join().unwrap().err()).collect()
+        });
+
+        if results.iter().any(Option::is_none) && !cancel.is_cancelled() {
+            let error = failures.into_iter().next();
+            return Err(error.unwrap_or_else(|| io::Error::other("tiles were lost to failed workers")));
+        }
+        let mut accumulation = self.new_accumulation();
+        for result in results.iter().flatten() {
+            accumulation.merge(result);
+        }
+        Ok(self.resolve(&accumulation))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::renderer::tests::sphere_scene;
+
+    // a worker on a free local port serving a single coordinator
+    fn spawn_worker(threads: usize) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            serve_coordinator(stream, threads).unwrap();
+        });
+        address
+    }
+
+    fn options() -> RenderOptions {
+        RenderOptions {
+            threads: 2,
+            filter: Some(Filter::Mitchell { radius: 2.0, b: 1.0 / 3.0, c: 1.0 / 3.0 }),
+            aovs: vec![Aov::Depth, Aov::Direct, Aov::SampleCount],
+            ..Default::default()
+        }
+    }
+
+    fn scene() -> Scene {
+        // several tiles across
+        let mut scene = sphere_scene();
+        scene.image_width = 40;
+        scene.image_height = 24;
+        scene
+    }
+
+    #[test]
+    fn distributed_render_matches_local_render() {
+        let workers = [spawn_worker(2), spawn_worker(1)];
+        let renderer = Renderer::new(scene(), options());
+        let mut tiles_done = 0;
+        let output = renderer.render_distributed(&workers, |progress| tiles_done = progress.tiles_done, &CancellationToken::new());
+        let output = output.unwrap();
+        let local = renderer.render();
+        assert_eq!(tiles_done, 6);
+        assert_eq!(output.beauty, local.beauty);
+        assert_eq!(output.aovs, local.aovs);
+    }
+
+    #[test]
+    fn tiles_of_a_worker_that_hangs_up_go_to_the_others() {
+        // says hello, then drops the connection as soon as the first tile comes in
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let flaky = listener.local_addr().unwrap().to_string();
+        thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut writer = BufWriter::new(stream);
+            write_message(&mut writer, &Message::Hello { threads: 4 }).unwrap();
+            read_message(&mut reader).unwrap();
+            read_message(&mut reader).unwrap();
+        });
+        let workers = [flaky, spawn_worker(2)];
+        let renderer = Renderer::new(scene(), options());
+        let output = renderer.render_distributed(&workers, |_| {}, &CancellationToken::new()).unwrap();
+        assert_eq!(output.beauty, renderer.render().beauty);
+    }
+
+    #[test]
+    fn passes_and_checkpoints_are_refused() {
+        let path = std::env::temp_dir().join("unused-checkpoint.json");
+        let checkpoint = Some(crate::checkpoint::CheckpointOptions { path, interval: None });
+        let renderer = Renderer::new(scene(), RenderOptions { checkpoint, ..options() });
+        let error = renderer.render_distributed(&["127.0.0.1:1"], |_| {}, &CancellationToken::new()).err().unwrap();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn tiles_of_a_worker_that_sends_back_the_wrong_tile_go_to_the_others() {
+        // renders the first tile it's sent, but says it's a tile that doesn't exist
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let confused = listener.local_addr().unwrap().to_string();
+        thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut writer = BufWriter::new(stream);
+            write_message(&mut writer, &Message::Hello { threads: 1 }).unwrap();
+            read_message(&mut reader).unwrap();
+            let Some(Message::Render { index, tile, target }) = read_message(&mut reader).unwrap() else {
+                panic!("expected a tile");
+            };
+            let renderer = Renderer::new(scene(), options());
+            let result = renderer.render_tile(index + 1000, tile, target, &renderer.new_accumulation(), &CancellationToken::new());
+            write_message(&mut writer, &Message::Rendered { result }).unwrap();
+            read_message(&mut reader).ok();
+        });
+        let workers = [confused, spawn_worker(2)];
+        let renderer = Renderer::new(scene(), options());
+        let output = renderer.render_distributed(&workers, |_| {}, &CancellationToken::new()).unwrap();
+        assert_eq!(output.beauty, renderer.render().beauty);
+    }
+
+    #[test]
+    fn workers_refuse_invalid_scenes() {
+        let mut scene = scene();
+        let mut camera = scene.camera.description().clone();
+        camera.aperture = -1.0;
+        scene.camera = crate::camera::Camera::from_description(camera, scene.aspect_ratio());
+        let renderer = Renderer::new(scene, options());
+        let error = renderer.render_distributed(&[spawn_worker(1)], |_| {}, &CancellationToken::new()).err().unwrap();
+        assert!(error.to_string().contains("camera.aperture"), "{}", error);
+    }
+
+    #[test]
+    fn unreachable_worker_fails_the_render() {
+        // bound and then dropped, so nothing is listening there anymore
+        let address = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap();
+        let renderer = Renderer::new(scene(), options());
+        assert!(renderer.render_distributed(&[address], |_| {}, &CancellationToken::new()).is_err());
+    }
+}
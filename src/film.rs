@@ -0,0 +1,258 @@
+use serde::{Deserialize, Serialize};
+
+use crate::filter::Filter;
+use crate::framebuffer::FrameBuffer;
+use crate::util::Color;
+
+// rectangle of pixels [x0, x1) x [y0, y1)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PixelBounds {
+    pub x0: i32,
+    pub y0: i32,
+    pub x1: i32,
+    pub y1: i32,
+}
+
+impl PixelBounds {
+    pub fn width(&self) -> i32 {
+        self.x1 - self.x0
+    }
+
+    pub fn height(&self) -> i32 {
+        self.y1 - self.y0
+    }
+
+    pub fn area(&self) -> usize {
+        (i32::max(self.width(), 0) * i32::max(self.height(), 0)) as usize
+    }
+
+    // grown by `margin` pixels on every side, but not past `limit`
+    pub fn expanded(&self, margin: i32, limit: &PixelBounds) -> PixelBounds {
+        PixelBounds {
+            x0: i32::max(self.x0 - margin, limit.x0),
+            y0: i32::max(self.y0 - margin, limit.y0),
+            x1: i32::min(self.x1 + margin, limit.x1),
+            y1: i32::min(self.y1 + margin, limit.y1),
+        }
+    }
+
+    // the part of this rectangle that's also inside `other`
+    pub fn intersection(&self, other: &PixelBounds) -> PixelBounds {
+        PixelBounds {
+            x0: i32::max(self.x0, other.x0),
+            y0: i32::max(self.y0, other.y0),
+            x1: i32::max(i32::min(self.x1, other.x1), i32::max(self.x0, other.x0)),
+            y1: i32::max(i32::min(self.y1, other.y1), i32::max(self.y0, other.y0)),
+        }
+    }
+}
+
+/**
+ * Part of the image to render, measured from the top left corner the way image viewers and editors show it
+ * (framebuffers count rows from the bottom). Anything reaching past the image is clipped.
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type")]
+pub enum CropWindow {
+    // pixels [x0, x1) x [y0, y1)
+    Pixels { x0: i32, y0: i32, x1: i32, y1: i32 },
+    // fractions of the image width and height, so the same window works at any resolution
+    Normalized { x0: f32, y0: f32, x1: f32, y1: f32 },
+}
+
+impl CropWindow {
+    // the window in framebuffer pixels, i.e. with rows counted from the bottom
+    pub fn pixel_bounds(&self, width: i32, height: i32) -> PixelBounds {
+        let (x0, y0, x1, y1) = match *self {
+            CropWindow::Pixels { x0, y0, x1, y1 } => (x0, y0, x1, y1),
+            // rounding both edges the same way lets windows that share an edge tile the image exactly
+            CropWindow::Normalized { x0, y0, x1, y1 } => {
+                let column = |x: f32| f32::ceil(x * width as f32) as i32;
+                let row = |y: f32| f32::ceil(y * height as f32) as i32;
+                (column(x0), row(y0), column(x1), row(y1))
+            }
+        };
+        let window = PixelBounds { x0, y0: height - y1, x1, y1: height - y0 };
+        window.intersection(&PixelBounds { x0: 0, y0: 0, x1: width, y1: height })
+    }
+}
+
+/**
+ * Weighted sums of filtered samples over part of the image, for one or more layers that all share the same
+ * weights (the beauty pass and the lighting AOVs, so they keep adding up to each other).
+ *
+ * Each worker splats into a film covering its tile plus the filter's reach, and the tile films are then
+ * merged into one film over the whole image. Pixels are only divided by their total weight at the very end,
+ * when the film is resolved into framebuffers.
+ */
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Film {
+    bounds: PixelBounds,
+    layers: usize,
+    // layer after layer, each row by row
+    weighted_sums: Vec<Color>,
+    weights: Vec<f32>,
+}
+
+impl Film {
+    pub fn new(bounds: PixelBounds, layers: usize) -> Self {
+        Self {
+            bounds,
+            layers,
+            weighted_sums: vec![Color::zero(); bounds.area() * layers],
+            weights: vec![0.0; bounds.area()],
+        }
+    }
+
+    pub fn bounds(&self) -> PixelBounds {
+        self.bounds
+    }
+
+    // whether it covers `bounds` with `layers` layers, which a film read back from a file might not
+    pub fn has_shape(&self, bounds: PixelBounds, layers: usize) -> bool {
+        (self.bounds, self.layers) == (bounds, layers)
+            && self.weighted_sums.len() == bounds.area() * layers
+            && self.weights.len() == bounds.area()
+    }
+
+    // how many pixels away from the one a sample was taken in the filter can reach
+    pub fn filter_margin(filter: &Filter) -> i32 {
+        i32::max(f32::ceil(filter.radius() + 0.5) as i32 - 1, 0)
+    }
+
+    fn index(&self, x: i32, y: i32) -> usize {
+        ((y - self.bounds.y0) * self.bounds.width() + (x - self.bounds.x0)) as usize
+    }
+
+    /**
+     * Adds one sample taken at `position` (in pixels, so pixel (x, y) covers [x, x + 1) x [y, y + 1)) to every
+     * pixel of the film the filter reaches. `values` has one color per layer.
+     */
+    pub fn add_sample(&mut self, filter: &Filter, position: (f32, f32), values: &[Color]) {
+        let radius = filter.radius();
+        // pixels whose center is within [-radius, radius) of the sample
+        let x0 = i32::max(f32::floor(position.0 - radius - 0.5) as i32 + 1, self.bounds.x0);
+        let x1 = i32::min(f32::floor(position.0 + radius - 0.5) as i32 + 1, self.bounds.x1);
+        let y0 = i32::max(f32::floor(position.1 - radius - 0.5) as i32 + 1, self.bounds.y0);
+        let y1 = i32::min(f32::floor(position.1 + radius - 0.5) as i32 + 1, self.bounds.y1);
+        let area = self.bounds.area();
+        for y in y0..y1 {
+            for x in x0..x1 {
+                let weight = filter.evaluate(position.0 - (x as f32 + 0.5), position.1 - (y as f32 + 0.5));
+                if weight == 0.0 {
+                    continue;
+                }
+                let index = self.index(x, y);
+                self.weights[index] += weight;
+                for (layer, value) in values.iter().enumerate() {
+                    self.weighted_sums[layer * area + index] += weight * *value;
+                }
+            }
+        }
+    }
+
+    // adds everything splatted into `other` (which has to lie inside this film) to this film
+    pub fn merge(&mut self, other: &Film) {
+        let area = self.bounds.area();
+        let other_area = other.bounds.area();
+        for y in other.bounds.y0..other.bounds.y1 {
+            for x in other.bounds.x0..other.bounds.x1 {
+                let index = self.index(x, y);
+                let other_index = other.index(x, y);
+                self.weights[index] += other.weights[other_index];
+                for layer in 0..usize::min(self.layers, other.layers) {
+                    self.weighted_sums[layer * area + index] += other.weighted_sums[layer * other_area + other_index];
+                }
+            }
+        }
+    }
+
+    // the weighted average of every pixel in one layer, pixels nothing landed in are black
+    pub fn resolve(&self, layer: usize) -> FrameBuffer {
+        let area = self.bounds.area();
+        let mut buffer = FrameBuffer::new(self.bounds.width(), self.bounds.height());
+        for y in self.bounds.y0..self.bounds.y1 {
+            for x in self.bounds.x0..self.bounds.x1 {
+                let index = self.index(x, y);
+                let weight = self.weights[index];
+                if weight != 0.0 {
+                    buffer.set(x - self.bounds.x0, y - self.bounds.y0, self.weighted_sums[layer * area + index] / weight);
+                }
+            }
+        }
+        buffer
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bounds(x0: i32, y0: i32, x1: i32, y1: i32) -> PixelBounds {
+        PixelBounds { x0, y0, x1, y1 }
+    }
+
+    #[test]
+    fn crop_windows_are_measured_from_the_top() {
+        let pixels = CropWindow::Pixels { x0: 2, y0: 0, x1: 6, y1: 3 };
+        assert_eq!(pixels.pixel_bounds(8, 10), bounds(2, 7, 6, 10));
+        let normalized = CropWindow::Normalized { x0: 0.25, y0: 0.0, x1: 0.75, y1: 0.3 };
+        assert_eq!(normalized.pixel_bounds(8, 10), bounds(2, 7, 6, 10));
+        // clipped to the image
+        let past_edge = CropWindow::Pixels { x0: -4, y0: 8, x1: 20, y1: 30 };
+        assert_eq!(past_edge.pixel_bounds(8, 10), bounds(0, 0, 8, 2));
+        let outside = CropWindow::Pixels { x0: 10, y0: 0, x1: 12, y1: 2 };
+        assert_eq!(outside.pixel_bounds(8, 10).area(), 0);
+    }
+
+    #[test]
+    fn box_filter_averages_within_pixel() {
+        let mut film = Film::new(bounds(0, 0, 3, 3), 1);
+        let filter = Filter::default();
+        film.add_sample(&filter, (1.0, 1.0), &[Color::new(1.0, 1.0, 1.0)]);
+        film.add_sample(&filter, (1.99, 1.5), &[Color::new(3.0, 3.0, 3.0)]);
+        let resolved = film.resolve(0);
+        assert_eq!(resolved.get(1, 1), Color::new(2.0, 2.0, 2.0));
+        assert_eq!(resolved.get(0, 1), Color::zero());
+        assert_eq!(resolved.get(2, 1), Color::zero());
+    }
+
+    #[test]
+    fn wide_filters_splat_into_neighbours() {
+        let mut film = Film::new(bounds(0, 0, 5, 5), 2);
+        let filter = Filter::Tent { radius: 1.5 };
+        assert_eq!(Film::filter_margin(&filter), 1);
+        film.add_sample(&filter, (2.5, 2.5), &[Color::new(1.0, 1.0, 1.0), Color::new(2.0, 2.0, 2.0)]);
+        let beauty = film.resolve(0);
+        let second_layer = film.resolve(1);
+        for (x, y) in [(1, 2), (3, 2), (2, 1), (2, 3), (1, 1), (2, 2)] {
+            assert_eq!(beauty.get(x, y), Color::new(1.0, 1.0, 1.0));
+            assert_eq!(second_layer.get(x, y), Color::new(2.0, 2.0, 2.0));
+        }
+        assert_eq!(beauty.get(0, 2), Color::zero());
+    }
+
+    #[test]
+    fn merging_tiles_matches_one_film() {
+        let filter = Filter::Gaussian { radius: 1.5, sigma: 0.5 };
+        let image = bounds(0, 0, 8, 4);
+        let tiles = [bounds(0, 0, 4, 4), bounds(4, 0, 8, 4)];
+        let mut whole = Film::new(image, 1);
+        let mut merged = Film::new(image, 1);
+        for tile in tiles {
+            let mut tile_film = Film::new(tile.expanded(Film::filter_margin(&filter), &image), 1);
+            for y in tile.y0..tile.y1 {
+                for x in tile.x0..tile.x1 {
+                    let position = (x as f32 + 0.3, y as f32 + 0.6);
+                    let value = [Color::new(x as f32, y as f32, 1.0)];
+                    tile_film.add_sample(&filter, position, &value);
+                    whole.add_sample(&filter, position, &value);
+                }
+            }
+            merged.merge(&tile_film);
+        }
+        for (a, b) in whole.resolve(0).pixels().iter().zip(merged.resolve(0).pixels()) {
+            assert!((*a - *b).len() < 1e-5);
+        }
+    }
+}
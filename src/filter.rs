@@ -0,0 +1,138 @@
+use std::f32::consts::PI;
+
+use serde::{Deserialize, Serialize};
+
+/**
+ * Pixel reconstruction filters. Every camera sample is splatted into all pixels whose center lies within
+ * `radius` (in pixels, on both axes) of the sample, weighted by the filter, and each pixel ends up as the
+ * weighted average of what landed in it.
+ *
+ * The default 0.5 pixel box filter only ever reaches the pixel the sample was taken in, which is the plain
+ * per-pixel average the renderer always did.
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type")] // will expect { type: "Gaussian", ... } in JSON format
+pub enum Filter {
+    Box { radius: f32 },
+    // weight falls off linearly to 0 at the radius, 1.0 is a good radius
+    Tent { radius: f32 },
+    // shifted down so it reaches 0 at the radius, radius 1.5 with sigma 0.5 is a good start
+    Gaussian { radius: f32, sigma: f32 },
+    // Mitchell-Netravali cubic, b = c = 1/3 with radius 2 is the usual recommendation
+    Mitchell { radius: f32, b: f32, c: f32 },
+    // sinc windowed by a wider sinc, tau is how many lobes fit in the window (radius 2, tau 2 is Lanczos2)
+    Lanczos { radius: f32, tau: f32 },
+}
+
+impl Default for Filter {
+    fn default() -> Self {
+        Filter::Box { radius: 0.5 }
+    }
+}
+
+impl Filter {
+    pub fn radius(&self) -> f32 {
+        match self {
+            Filter::Box { radius }
+            | Filter::Tent { radius }
+            | Filter::Gaussian { radius, .. }
+            | Filter::Mitchell { radius, .. }
+            | Filter::Lanczos { radius, .. } => *radius,
+        }
+    }
+
+    /**
+     * Weight of a sample offset by (dx, dy) pixels from a pixel's center. The support is [-radius, radius)
+     * so that with a 0.5 radius box every sample lands in exactly one pixel, even right on the edge.
+     */
+    pub fn evaluate(&self, dx: f32, dy: f32) -> f32 {
+        let radius = self.radius();
+        let outside = |d: f32| d < -radius || d >= radius;
+        if outside(dx) || outside(dy) {
+            return 0.0;
+        }
+        // every filter here is separable
+        match self {
+            Filter::Box { .. } => 1.0,
+            Filter::Tent { .. } => (radius - f32::abs(dx)) * (radius - f32::abs(dy)),
+            Filter::Gaussian { sigma, .. } => gaussian(dx, radius, *sigma) * gaussian(dy, radius, *sigma),
+            Filter::Mitchell { b, c, .. } => mitchell(2.0 * dx / radius, *b, *c) * mitchell(2.0 * dy / radius, *b, *c),
+            Filter::Lanczos { tau, .. } => sinc(dx) * sinc(dx / tau) * sinc(dy) * sinc(dy / tau),
+        }
+    }
+}
+
+fn gaussian(x: f32, radius: f32, sigma: f32) -> f32 {
+    let g = |x: f32| f32::exp(-x * x / (2.0 * sigma * sigma));
+    f32::max(g(x) - g(radius), 0.0)
+}
+
+// x is in [-2, 2]
+fn mitchell(x: f32, b: f32, c: f32) -> f32 {
+    let x = f32::abs(x);
+    let value = if x <= 1.0 {
+        (12.0 - 9.0 * b - 6.0 * c) * x * x * x + (-18.0 + 12.0 * b + 6.0 * c) * x * x + (6.0 - 2.0 * b)
+    } else if x <= 2.0 {
+        (-b - 6.0 * c) * x * x * x + (6.0 * b + 30.0 * c) * x * x + (-12.0 * b - 48.0 * c) * x + (8.0 * b + 24.0 * c)
+    } else {
+        0.0
+    };
+    value / 6.0
+}
+
+fn sinc(x: f32) -> f32 {
+    if f32::abs(x) < 1e-5 {
+        return 1.0;
+    }
+    f32::sin(PI * x) / (PI * x)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FILTERS: [Filter; 5] = [
+        Filter::Box { radius: 0.5 },
+        Filter::Tent { radius: 1.0 },
+        Filter::Gaussian { radius: 1.5, sigma: 0.5 },
+        Filter::Mitchell { radius: 2.0, b: 1.0 / 3.0, c: 1.0 / 3.0 },
+        Filter::Lanczos { radius: 2.0, tau: 2.0 },
+    ];
+
+    #[test]
+    fn filters_peak_at_center_and_vanish_at_radius() {
+        for filter in FILTERS {
+            let radius = filter.radius();
+            let center = filter.evaluate(0.0, 0.0);
+            assert!(center > 0.0);
+            assert!(filter.evaluate(0.3 * radius, 0.0) <= center);
+            assert_eq!(filter.evaluate(radius, 0.0), 0.0);
+            assert_eq!(filter.evaluate(0.0, -radius - 0.01), 0.0);
+            assert_eq!(filter.evaluate(0.5 * radius, 2.0 * radius), 0.0);
+        }
+    }
+
+    #[test]
+    fn half_pixel_box_covers_each_offset_once() {
+        let filter = Filter::default();
+        for offset in [0.0, 0.25, 0.5, 0.999] {
+            // offsets of a sample at `offset` within a pixel from that pixel's center and its neighbours'
+            let weights: f32 = [-1.5, -0.5, 0.5].iter().map(|center| filter.evaluate(offset + center, 0.0)).sum();
+            assert_eq!(weights, 1.0);
+        }
+    }
+
+    #[test]
+    fn mitchell_has_negative_lobes() {
+        let filter = Filter::Mitchell { radius: 2.0, b: 1.0 / 3.0, c: 1.0 / 3.0 };
+        assert!(filter.evaluate(1.5, 0.0) < 0.0);
+        // the usual cubic evaluated at 0 is (6 - 2b) / 6 on each axis
+        assert!(f32::abs(filter.evaluate(0.0, 0.0) - (8.0 / 9.0) * (8.0 / 9.0)) < 1e-5);
+    }
+
+    #[test]
+    fn filter_deserializes_from_scene_json() {
+        let filter: Filter = serde_json::from_str(r#"{ "type": "Gaussian", "radius": 1.5, "sigma": 0.5 }"#).unwrap();
+        assert_eq!(filter, Filter::Gaussian { radius: 1.5, sigma: 0.5 });
+    }
+}
@@ -0,0 +1,70 @@
+use crate::util::Color;
+
+/**
+ * In-memory result of a render. Each pixel holds the averaged (linear, not yet gamma corrected) radiance
+ * of all samples taken for it.
+ *
+ * Rows are stored bottom row first, matching how the camera's (u, v) coordinates are laid out, so (0, 0)
+ * is the bottom left pixel of the image.
+ */
+#[derive(Debug, Clone, PartialEq)]
+pub struct FrameBuffer {
+    width: i32,
+    height: i32,
+    pixels: Vec<Color>,
+}
+
+impl FrameBuffer {
+    pub fn new(width: i32, height: i32) -> Self {
+        Self {
+            width,
+            height,
+            pixels: vec![Color::zero(); (width * height) as usize],
+        }
+    }
+
+    pub fn width(&self) -> i32 {
+        self.width
+    }
+
+    pub fn height(&self) -> i32 {
+        self.height
+    }
+
+    pub fn pixels(&self) -> &[Color] {
+        &self.pixels
+    }
+
+    fn index(&self, x: i32, y: i32) -> usize {
+        (y * self.width + x) as usize
+    }
+
+    pub fn get(&self, x: i32, y: i32) -> Color {
+        self.pixels[self.index(x, y)]
+    }
+
+    pub fn set(&mut self, x: i32, y: i32, color: Color) {
+        let index = self.index(x, y);
+        self.pixels[index] = color;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn new_framebuffer_is_black() {
+        let fb = FrameBuffer::new(4, 3);
+        assert_eq!(fb.pixels().len(), 12);
+        assert!(fb.pixels().iter().all(|p| *p == Color::zero()));
+    }
+
+    #[test]
+    fn set_then_get_returns_same_pixel() {
+        let mut fb = FrameBuffer::new(4, 3);
+        fb.set(3, 2, Color::new(0.25, 0.5, 1.0));
+        assert_eq!(fb.get(3, 2), Color::new(0.25, 0.5, 1.0));
+        assert_eq!(fb.get(2, 2), Color::zero());
+    }
+}
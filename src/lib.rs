@@ -1 +1,30 @@
+#[macro_use]
+extern crate fstrings;
 
+pub mod aabb;
+pub mod adaptive;
+pub mod animation;
+pub mod aov;
+pub mod aperture;
+pub mod camera;
+pub mod checkpoint;
+pub mod denoise;
+pub mod distributed;
+pub mod film;
+pub mod filter;
+pub mod framebuffer;
+pub mod material;
+pub mod motion;
+pub mod output;
+pub mod progress;
+pub mod ray;
+pub mod renderable;
+pub mod renderer;
+pub mod sampler;
+pub mod scene;
+pub mod scene_builder;
+pub mod sphere;
+pub mod texture;
+pub mod tonemap;
+pub mod util;
+pub mod validate;
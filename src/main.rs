@@ -1,392 +1,592 @@
-pub mod camera;
-pub mod material;
-pub mod ray;
-pub mod renderable;
-pub mod scene;
-pub mod sphere;
-pub mod util;
-pub mod aabb;
-pub mod scene_builder;
-pub mod texture;
-
 #[macro_use]
 extern crate fstrings;
 
 use std::env;
-
-use crate::{
-    aabb::AABB, scene::{default_scene, load_scene, random_scene, save_scene, test_scene, Scene, SceneMetaData}, 
-    scene_builder::{ create, create_checker_test, create_test, create_checker_spheres_test }, util::random_between_0_1
+use std::fs;
+use std::net::TcpListener;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::time::{Duration, Instant};
+
+use clap::{Args, Parser, Subcommand, ValueEnum};
+
+use raytracer::{
+    aov::Aov,
+    camera::Projection,
+    checkpoint::{Checkpoint, CheckpointOptions},
+    denoise::DenoiseOptions,
+    distributed::run_worker,
+    film::CropWindow,
+    output::{frame_path, write_render_output, BitDepth, ExrPrecision, OutputOptions, PpmEncoding},
+    progress::{CancellationToken, RenderProgress},
+    renderable::Object,
+    renderer::{AccelStructure, CropOptions, ProgressiveOptions, RenderOptions, RenderOutput, Renderer},
+    sampler::SamplerType,
+    scene::{load_scene, load_scene_unchecked, Scene},
+    scene_builder::{create, create_checker_spheres_test, create_checker_test},
+    tonemap::ToneMapOperator,
+    validate::{validate_scene, Severity},
 };
-use aabb::BvhNode;
-use camera::Camera;
-use material::Material;
-use ray::Ray;
-use renderable::{Renderable, RenderableList};
-use texture::Texture;
-use std::{f32::INFINITY};
-use util::{clamp, Color, Point, Vec3, Interval};
-
-use std::sync::mpsc;
-use std::thread;
-use std::time::{Instant};
-
-const COLOR_LIM: i32 = 256;
-const BOUNCE_DEPTH: i32 = 50;
-
-struct RenderOptions {
-    use_bvh_node: bool
-}
 
-fn write_color_to_output(color: Color, samples_per_pixel: i32) {
-    let mut r = color.x();
-    let mut g = color.y();
-    let mut b = color.z();
+// workers render whatever scene they're sent, so other machines only reach them when asked for with --listen
+const WORKER_ADDRESS: &str = "127.0.0.1:7878";
 
-    let scale = 1.0 / (samples_per_pixel as f32);
+#[derive(Debug, Parser)]
+#[command(name = "raytracer", about = "A multithreaded raytracer")]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+}
 
-    // approximation of gamma correction, as image viewers for some reason scale by ^2
-    r = f32::sqrt(scale * r);
-    g = f32::sqrt(scale * g);
-    b = f32::sqrt(scale * b);
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// Render a scene file to an image
+    Render(Box<RenderArgs>),
+    /// Check a scene file and list everything wrong with it
+    Validate { scene: PathBuf },
+    /// Print a summary of a scene file
+    Info { scene: PathBuf },
+    /// Write one of the built in scenes to a scene file
+    Generate {
+        builtin: BuiltinScene,
+        /// Where to write the scene, scenes/<builtin>.json by default
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+    },
+    /// Render tiles for coordinators started with `render --workers`
+    Worker {
+        /// Address to listen on, e.g. --listen 0.0.0.0:7878 to take tiles from other machines
+        #[arg(long, default_value = WORKER_ADDRESS)]
+        listen: String,
+        #[arg(long)]
+        threads: Option<usize>,
+    },
+}
 
-    let r_out = ((COLOR_LIM as f32) * clamp(r, 0.0, 0.999)) as i32;
-    let g_out = ((COLOR_LIM as f32) * clamp(g, 0.0, 0.999)) as i32;
-    let b_out = ((COLOR_LIM as f32) * clamp(b, 0.0, 0.999)) as i32;
-    println!("{} {} {}", r_out, g_out, b_out);
+// flags set here win over the scene file
+#[derive(Debug, Args)]
+struct RenderArgs {
+    scene: PathBuf,
+    /// Output image, the format follows the extension (png, ppm, exr, hdr or pfm)
+    #[arg(short, long, default_value = "images/output.png")]
+    output: PathBuf,
+    /// Bits per channel of PNG output, 8 or 16
+    #[arg(long, value_parser = parse_bit_depth)]
+    bit_depth: Option<BitDepth>,
+    /// Write PPM output as plain text (P3) instead of binary (P6)
+    #[arg(long)]
+    ppm_ascii: bool,
+    /// How radiance is brought into the displayable range for PNG and PPM output: clamp, aces, agx or
+    /// reinhard:<white point>
+    #[arg(long, value_parser = parse_tone_map)]
+    tone_map: Option<ToneMapOperator>,
+    /// Exposure compensation in stops before tone mapping, every +1 doubles the brightness
+    #[arg(long, allow_negative_numbers = true)]
+    exposure: Option<f32>,
+    /// Channel precision of EXR output
+    #[arg(long, value_enum)]
+    exr_precision: Option<ExrPrecisionArg>,
+    /// Worker threads, all cores by default
+    #[arg(long)]
+    threads: Option<usize>,
+    /// Samples per pixel, turns off the scene's adaptive sampling
+    #[arg(long, value_parser = clap::value_parser!(i32).range(1..))]
+    spp: Option<i32>,
+    /// Intersect rays against a bounding volume hierarchy instead of every object in turn
+    #[arg(long)]
+    bvh: bool,
+    #[arg(long)]
+    seed: Option<u64>,
+    #[arg(long, value_enum)]
+    sampler: Option<SamplerArg>,
+    /// Extra passes to write next to the image, e.g. --aovs albedo,normal,depth
+    #[arg(long, value_delimiter = ',', value_parser = parse_aov)]
+    aovs: Vec<Aov>,
+    #[arg(long)]
+    denoise: bool,
+    /// Render in passes of this many samples per pixel, rewriting the output after each one
+    #[arg(long, value_parser = clap::value_parser!(i32).range(1..))]
+    pass_spp: Option<i32>,
+    /// Seconds between rewrites of the output in a progressive render
+    #[arg(long, value_parser = parse_seconds, requires = "pass_spp")]
+    snapshot_interval: Option<f32>,
+    /// Seconds after which no new pass is started in a progressive render
+    #[arg(long, value_parser = parse_seconds, requires = "pass_spp")]
+    time_limit: Option<f32>,
+    /// Save the render's state to this file every --checkpoint-interval seconds and at the end
+    #[arg(long)]
+    checkpoint: Option<PathBuf>,
+    /// Seconds between checkpoints, they're also written while a pass is rendering
+    #[arg(long, default_value_t = 60.0, requires = "checkpoint", value_parser = parse_seconds)]
+    checkpoint_interval: f32,
+    /// Carry on from the --checkpoint file instead of starting over
+    #[arg(long, requires = "checkpoint")]
+    resume: bool,
+    /// Only render x0,y0,x1,y1 from the top left, in pixels or, with decimal points, fractions of the image
+    #[arg(long, value_parser = parse_crop)]
+    crop: Option<CropWindow>,
+    /// Keep the full image size with everything outside the crop black
+    #[arg(long, requires = "crop")]
+    full_frame: bool,
+    /// Render on the worker processes at these addresses, e.g. --workers host1:7878,host2:7878. Renders in one
+    /// pass without checkpoints
+    #[arg(long, value_delimiter = ',', conflicts_with_all = ["pass_spp", "checkpoint", "resume"])]
+    workers: Vec<String>,
+    /// Only render these frames of an animation, e.g. --frames 12 or --frames 1-24. Every frame by default, written
+    /// next to the output with the frame number added, e.g. images/output_0001.png
+    #[arg(long, value_parser = parse_frames)]
+    frames: Option<(u32, u32)>,
 }
 
-fn ray_color_world(ray: &Ray, world: &RenderableList, call_depth: i32) -> Color {
-    if call_depth >= BOUNCE_DEPTH {
-        return Color::zero();
-    }
-    let (did_hit, hit_rec) = world.hit(ray, Interval{min: 0.001, max: INFINITY});
-    if did_hit {
-        // if we hit something, determing how this ray scatters (if at all)
-        let (did_scatter, scatter_color, scatter_ray) = hit_rec.material_ptr.scatter(ray, &hit_rec);
-        if did_scatter {
-            // if we do scatter,
-            return scatter_color * ray_color_world(&scatter_ray, world, call_depth + 1);
-        }
-    }
-    let unit_direction: Vec3 = ray.direction.unit_vector();
-    let t: f32 = 0.5 * (unit_direction.y() + 1.0);
-    let skybox = (Color::new(1.0, 1.0, 1.0) * (1.0 - t)) + (Color::new(0.5, 0.7, 1.0) * t);
-    skybox
+#[derive(Debug, Clone, Copy, ValueEnum)]
+enum SamplerArg {
+    Independent,
+    Stratified,
+    Halton,
+    Sobol,
 }
 
-fn ray_color_bvh_node(ray: &Ray, root: &BvhNode, call_depth: i32) -> Color {
-    if call_depth >= BOUNCE_DEPTH {
-        return Color::zero();
-    }
-    let (did_hit, hit_rec) = root.hit(ray, Interval{min: 0.001, max: INFINITY});
-    if did_hit {
-        // if we hit something, determing how this ray scatters (if at all)
-        let (did_scatter, scatter_color, scatter_ray) = hit_rec.material_ptr.scatter(ray, &hit_rec);
-        if did_scatter {
-            // if we do scatter,
-            return scatter_color * ray_color_bvh_node(&scatter_ray, root, call_depth + 1);
+impl From<SamplerArg> for SamplerType {
+    fn from(sampler: SamplerArg) -> Self {
+        match sampler {
+            SamplerArg::Independent => SamplerType::Independent,
+            SamplerArg::Stratified => SamplerType::Stratified,
+            SamplerArg::Halton => SamplerType::Halton,
+            SamplerArg::Sobol => SamplerType::Sobol,
         }
     }
-    let unit_direction: Vec3 = ray.direction.unit_vector();
-    let t: f32 = 0.5 * (unit_direction.y() + 1.0);
-    let skybox = (Color::new(1.0, 1.0, 1.0) * (1.0 - t)) + (Color::new(0.5, 0.7, 1.0) * t);
-    skybox
 }
 
-fn render(scene_path: &str) {
-    let scene: Scene = load_scene(scene_path);
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum)]
+enum ExrPrecisionArg {
+    // 16-bit floats, plenty for images and half the size
+    Half,
+    // 32-bit floats, for data passes like depth that need the range
+    Float,
+}
 
-    // progress printing
-    let mut scanlines_remaining = scene.image_height;
+impl From<ExrPrecisionArg> for ExrPrecision {
+    fn from(precision: ExrPrecisionArg) -> Self {
+        match precision {
+            ExrPrecisionArg::Half => ExrPrecision::Half,
+            ExrPrecisionArg::Float => ExrPrecision::Float,
+        }
+    }
+}
 
-    // initialize as all black
-    println_f!("P3\n{scene.image_width} {scene.image_height}\n255\n");
+#[derive(Debug, Clone, Copy, ValueEnum)]
+enum BuiltinScene {
+    // two big checkered spheres on top of each other
+    TwoSpheresChecker,
+    // the "Ray Tracing in One Weekend" cover, on a checkered floor
+    RandomChecker,
+    // the "Ray Tracing in One Weekend" cover
+    Random,
+}
 
-    let mut pixels =
-        vec![vec![Color::zero(); scene.image_width as usize]; scene.image_height as usize];
+fn parse_aov(name: &str) -> Result<Aov, String> {
+    Aov::from_name(name).ok_or_else(|| {
+        let names: Vec<&str> = Aov::ALL.iter().map(|aov| aov.name()).collect();
+        format!("unknown AOV, expected one of {}", names.join(", "))
+    })
+}
 
-    for j in 0..scene.image_height {
-        for i in 0..scene.image_width {
-            // spawn threads
-            for _s in 0..scene.samples_per_pixel {
-                // random point for ray to shoot at within this pixel
-                let u = ((i as f32) + random_between_0_1()) / (scene.image_width as f32);
-                let v = ((j as f32) + random_between_0_1()) / (scene.image_height as f32);
-                let r = scene.camera.get_ray(u, v);
-                // starting call depth is 0 as increases until hitting bounce depth
-                pixels[j as usize][i as usize] += ray_color_world(&r, &scene.world, 0);
-            }
-        }
-        scanlines_remaining -= 1;
-        eprintln_f!("Scanlines remaining: {scanlines_remaining}");
+fn parse_seconds(seconds: &str) -> Result<f32, String> {
+    match seconds.trim().parse::<f32>() {
+        Ok(seconds) if seconds >= 0.0 && seconds.is_finite() => Ok(seconds),
+        _ => Err("expected a number of seconds".to_string()),
     }
+}
 
-    // write final colors to output
-    for y in (0..pixels.len()).rev() {
-        for x in 0..pixels[y].len() {
-            write_color_to_output(pixels[y][x], scene.samples_per_pixel);
-        }
+fn parse_bit_depth(bits: &str) -> Result<BitDepth, String> {
+    match bits {
+        "8" => Ok(BitDepth::Eight),
+        "16" => Ok(BitDepth::Sixteen),
+        _ => Err("expected 8 or 16".to_string()),
     }
 }
 
-fn render_bvh(scene_path: &str) {
-    let scene: Scene = load_scene(scene_path);
-
-    // progress printing
-    let mut scanlines_remaining = scene.image_height;
-
-    // initialize as all black
-    println_f!("P3\n{scene.image_width} {scene.image_height}\n255\n");
-
-    let mut pixels =
-        vec![vec![Color::zero(); scene.image_width as usize]; scene.image_height as usize];
-    let root = BvhNode::new_from_renderables(&scene.world.objects);
-    for j in 0..scene.image_height {
-        for i in 0..scene.image_width {
-            // spawn threads
-            for _s in 0..scene.samples_per_pixel {
-                // random point for ray to shoot at within this pixel
-                let u = ((i as f32) + random_between_0_1()) / (scene.image_width as f32);
-                let v = ((j as f32) + random_between_0_1()) / (scene.image_height as f32);
-                let r = scene.camera.get_ray(u, v);
-                // starting call depth is 0 as increases until hitting bounce depth
-                pixels[j as usize][i as usize] += ray_color_bvh_node(&r, &root, 0);
-            }
-        }
-        scanlines_remaining -= 1;
-        eprintln_f!("Scanlines remaining: {scanlines_remaining}");
+// "reinhard:4" maps a luminance of 4 to white, the other operators take no settings
+fn parse_tone_map(operator: &str) -> Result<ToneMapOperator, String> {
+    match operator.split_once(':') {
+        None if operator == "clamp" => Ok(ToneMapOperator::Clamp),
+        None if operator == "aces" => Ok(ToneMapOperator::Aces),
+        None if operator == "agx" => Ok(ToneMapOperator::Agx),
+        Some(("reinhard", white_point)) => match white_point.trim().parse::<f32>() {
+            Ok(white_point) if white_point > 0.0 && white_point.is_finite() => Ok(ToneMapOperator::Reinhard { white_point }),
+            _ => Err(format!("the white point in {} has to be a number greater than 0", operator)),
+        },
+        _ => Err("expected clamp, aces, agx or reinhard:<white point>".to_string()),
     }
+}
 
-    // write final colors to output
-    for y in (0..pixels.len()).rev() {
-        for x in 0..pixels[y].len() {
-            write_color_to_output(pixels[y][x], scene.samples_per_pixel);
-        }
+// "8,0,64,32" is in pixels, "0.25,0,1,0.5" in fractions of the image size
+fn parse_crop(window: &str) -> Result<CropWindow, String> {
+    let edges: Vec<&str> = window.split(',').map(str::trim).collect();
+    if edges.len() != 4 {
+        return Err("expected x0,y0,x1,y1".to_string());
     }
+    if let Ok(pixels) = edges.iter().map(|edge| edge.parse::<i32>()).collect::<Result<Vec<i32>, _>>() {
+        return Ok(CropWindow::Pixels { x0: pixels[0], y0: pixels[1], x1: pixels[2], y1: pixels[3] });
+    }
+    let fractions = edges.iter().map(|edge| edge.parse::<f32>()).collect::<Result<Vec<f32>, _>>();
+    let fractions = fractions.map_err(|error| format!("{} in {}", error, window))?;
+    Ok(CropWindow::Normalized { x0: fractions[0], y0: fractions[1], x1: fractions[2], y1: fractions[3] })
 }
 
-fn render_multi_threaded(scene_path: &str, mut num_threads: i32) {
-    let scene: Scene = load_scene(scene_path);
-
-    let samples_per_pixel_per_thread =
-        f32::round(scene.samples_per_pixel as f32 / num_threads as f32) as i32;
-
-    let mut handles = vec![];
-    // let num_pixels: usize= (scene.image_height * scene.image_width) as usize;
-    let mut final_pixels =
-        vec![vec![Color::zero(); scene.image_width as usize]; scene.image_height as usize];
-
-    // progress printing
-    let mut scanline_completion_array = vec![0; scene.image_height as usize];
-    let mut scanlines_remaining = scene.image_height;
-
-    // initialize as all black
-    println_f!("P3\n{scene.image_width} {scene.image_height}\n255\n");
+// "12" is just frame 12, "1-24" the first 24 frames
+fn parse_frames(frames: &str) -> Result<(u32, u32), String> {
+    let parse = |frame: &str| frame.trim().parse::<u32>().map_err(|error| format!("{} in {}", error, frames));
+    let (first, last) = match frames.split_once('-') {
+        Some((first, last)) => (parse(first)?, parse(last)?),
+        None => (parse(frames)?, parse(frames)?),
+    };
+    if first == 0 || last < first {
+        return Err("expected frames counting from 1, first-last".to_string());
+    }
+    Ok((first, last))
+}
 
-    // maybe only use channels? aggregate color fully within thread, then once pixel is finished
-    // send fractionally sampled pixel to awating main thread, which will collect and aggregate them
-    // to build final output color.
-    let (pixel_value_transmitter, pixel_value_receiver) = mpsc::channel();
-    let (scanline_completion_transmitter, scanline_completion_receiver) = mpsc::channel();
+fn read_scene(path: &Path) -> Result<Scene, String> {
+    let (scene, warnings) = load_scene(path).map_err(|error| error.to_string())?;
+    for warning in warnings {
+        eprintln!("warning: {}: {}", path.display(), warning);
+    }
+    Ok(scene)
+}
 
-    for thread in 0..num_threads {
-        let objects = scene.world.objects.clone();
-        let mut thread_world = RenderableList::new();
-        for obj in objects {
-            thread_world.add(obj)
+fn create_parent_directory(path: &Path) -> Result<(), String> {
+    match path.parent().filter(|directory| !directory.as_os_str().is_empty()) {
+        Some(directory) => {
+            fs::create_dir_all(directory).map_err(|error| format!("Unable to create {}: {}", directory.display(), error))
         }
-        let pixel_value_transmitter = pixel_value_transmitter.clone();
-        let scanline_completion_transmitter = scanline_completion_transmitter.clone();
-        let mut pixels =
-            vec![vec![Color::zero(); scene.image_width as usize]; scene.image_height as usize];
-        let handle = thread::spawn(move || {
-            for j in 0..scene.image_height {
-                for i in 0..scene.image_width {
-                    // spawn threads
-                    for _s in 0..samples_per_pixel_per_thread {
-                        // random point for ray to shoot at within this pixel
-                        let u = ((i as f32) + random_between_0_1()) / (scene.image_width as f32);
-                        let v = ((j as f32) + random_between_0_1()) / (scene.image_height as f32);
-                        let r = scene.camera.get_ray(u, v);
-                        // starting call depth is 0 as increases until hitting bounce depth
-                        pixels[j as usize][i as usize] += ray_color_world(&r, &thread_world, 0);
-                    }
-                }
-                // eprintln_f!("Completed Scanline {j}");
-                // notify that this thread has completed this scanline
-                scanline_completion_transmitter.send(j).unwrap();
-            }
-            // send completed partial image once finished
-            pixel_value_transmitter.send(pixels).unwrap();
-            drop(pixel_value_transmitter);
-            drop(scanline_completion_transmitter);
-            eprintln_f!("Thread {thread} completed");
-        });
-        handles.push(handle);
+        None => Ok(()),
     }
+}
+
+fn print_progress(progress: &RenderProgress) {
+    let percent = 100.0 * progress.fraction_done();
+    let eta = progress.eta().map(|eta| eta.as_secs_f32()).unwrap_or(0.0);
+    let rays_per_second = progress.rays_per_second();
+    eprintln_f!("{percent:.1}% ({progress.tiles_done}/{progress.tiles_total} tiles, pass {progress.passes_done}/{progress.passes_total}), ETA {eta:.1}s, {rays_per_second:.0} rays/s");
+}
 
-    // handle printing progress from threads
-    for completed_thread_scanline in scanline_completion_receiver {
-        scanline_completion_array[completed_thread_scanline as usize] += 1;
-        if scanline_completion_array[completed_thread_scanline as usize] == num_threads {
-            scanlines_remaining -= 1;
-            eprintln_f!("Scanlines Remaining: {scanlines_remaining}");
+fn render(args: RenderArgs) -> Result<(), String> {
+    let start = Instant::now();
+    let mut scene = read_scene(&args.scene)?;
+    if let Some(operator) = args.tone_map {
+        scene.tone_mapping.operator = operator;
+    }
+    if let Some(exposure) = args.exposure {
+        scene.tone_mapping.exposure = exposure;
+    }
+    let output_defaults = OutputOptions::default();
+    let output_options = OutputOptions {
+        bit_depth: args.bit_depth.unwrap_or(output_defaults.bit_depth),
+        ppm_encoding: if args.ppm_ascii { PpmEncoding::Ascii } else { output_defaults.ppm_encoding },
+        exr_precision: args.exr_precision.map(ExrPrecision::from).unwrap_or(output_defaults.exr_precision),
+        tone_mapping: scene.tone_mapping,
+    };
+    let defaults = RenderOptions::default();
+    let options = RenderOptions {
+        threads: args.threads.unwrap_or(defaults.threads),
+        accel_structure: if args.bvh { AccelStructure::Bvh } else { AccelStructure::List },
+        samples_per_pixel: args.spp,
+        seed: args.seed,
+        sampler: args.sampler.map(SamplerType::from).unwrap_or_default(),
+        aovs: args.aovs.clone(),
+        denoise: args.denoise.then(DenoiseOptions::default),
+        progressive: args.pass_spp.map(|samples_per_pass| ProgressiveOptions {
+            samples_per_pass,
+            time_limit: args.time_limit.map(Duration::from_secs_f32),
+            snapshot_interval: args.snapshot_interval.map(Duration::from_secs_f32),
+        }),
+        checkpoint: args.checkpoint.clone().map(|path| CheckpointOptions {
+            path,
+            interval: Some(Duration::from_secs_f32(args.checkpoint_interval)),
+        }),
+        crop: args.crop.map(|window| CropOptions { window, full_frame: args.full_frame }),
+        ..defaults
+    };
+
+    let frames = match (&scene.animation, args.frames) {
+        (Some(animation), Some((_, last))) if last > animation.frames => {
+            return Err(format!("There's no frame {}, the animation has {} frames", last, animation.frames));
         }
-        if scanlines_remaining == 0 {
-            break;
+        (Some(animation), frames) => Some(frames.unwrap_or((1, animation.frames))),
+        (None, Some(_)) => return Err(format!("{} isn't animated, there are no frames", args.scene.display())),
+        (None, None) => None,
+    };
+    let mut renderer = Renderer::new(scene, options);
+    if let Some((first, last)) = frames {
+        // a checkpoint only holds one image
+        if args.checkpoint.is_some() && first != last {
+            return Err("Checkpoints only work for a single frame, pick one with --frames".to_string());
         }
+        renderer.set_frame(first);
     }
-
-    for partially_rendered_scene in pixel_value_receiver {
-        for y in 0..partially_rendered_scene.len() {
-            for x in 0..partially_rendered_scene[y].len() {
-                final_pixels[y][x] += partially_rendered_scene[y][x];
+    if let (true, Some(path)) = (args.resume, &args.checkpoint) {
+        let checkpoint = Checkpoint::load(path).map_err(|error| format!("Unable to read {}: {}", path.display(), error))?;
+        renderer.resume(checkpoint).map_err(|error| format!("Unable to resume {}: {}", path.display(), error))?;
+    }
+    create_parent_directory(&args.output)?;
+
+    match frames {
+        Some((first, last)) => {
+            for frame in first..=last {
+                eprintln!("frame {} ({} to {})", frame, first, last);
+                renderer.set_frame(frame);
+                render_image(&renderer, &args, &frame_path(&args.output, frame), &output_options)?;
             }
         }
-        num_threads -= 1;
-        eprintln_f!("Threads Remaining: {num_threads}");
-        if num_threads == 0 {
-            break;
-        }
+        None => render_image(&renderer, &args, &args.output, &output_options)?,
     }
+    let elapsed = start.elapsed().as_secs_f32();
+    eprintln_f!("scene rendered in {elapsed}");
+    Ok(())
+}
 
-    drop(pixel_value_transmitter);
-    drop(scanline_completion_transmitter);
+// renders the image (or the frame the renderer is set to) to `output`
+fn render_image(renderer: &Renderer, args: &RenderArgs, output: &Path, output_options: &OutputOptions) -> Result<(), String> {
+    let cancel = CancellationToken::new();
+    let rendered = if args.workers.is_empty() {
+        // the output is rewritten as the render refines, so it can be stopped once it looks good enough
+        let write_snapshot = |snapshot: &RenderOutput, _: &RenderProgress| {
+            if let Err(error) = write_render_output(output, snapshot, output_options) {
+                eprintln_f!("Unable to write snapshot: {error}");
+            }
+        };
+        renderer.render_progressive(print_progress, write_snapshot, &cancel)
+    } else {
+        renderer
+            .render_distributed(&args.workers, print_progress, &cancel)
+            .map_err(|error| format!("Distributed render failed: {}", error))?
+    };
+    if let Some(checkpoint) = &args.checkpoint {
+        for error in &rendered.checkpoint_errors {
+            eprintln!("Unable to write checkpoint {}: {}", checkpoint.display(), error);
+        }
+    }
+    write_render_output(output, &rendered, output_options)
+        .map_err(|error| format!("Unable to write {}: {}", output.display(), error))
+}
 
-    // clean up threads
-    for handle in handles {
-        handle.join().unwrap();
-        eprintln_f!("Cleaning up threads");
+fn validate(path: &Path) -> Result<(), String> {
+    let scene = load_scene_unchecked(path).map_err(|error| error.to_string())?;
+    let issues = validate_scene(&scene);
+    for issue in &issues {
+        let severity = match issue.severity {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        };
+        println!("{}: {}: {}", severity, path.display(), issue);
+    }
+    let errors = issues.iter().filter(|issue| issue.is_error()).count();
+    if errors > 0 {
+        return Err(format!("{} is not a valid scene, {} errors and {} warnings", path.display(), errors, issues.len() - errors));
     }
+    println!("{} is valid ({} warnings)", path.display(), issues.len());
+    Ok(())
+}
 
-    // write final colors to output
-    for y in (0..final_pixels.len()).rev() {
-        for x in 0..final_pixels[y].len() {
-            write_color_to_output(final_pixels[y][x], scene.samples_per_pixel);
+fn info(path: &Path) -> Result<(), String> {
+    let scene = read_scene(path)?;
+    let spheres = scene.world.objects.iter().filter(|object| matches!(object, Object::Sphere(_))).count();
+    println!("{}", path.display());
+    println!("  resolution: {}x{} (aspect ratio {})", scene.image_width, scene.image_height, scene.aspect_ratio());
+    let camera = scene.camera.description();
+    match camera.projection {
+        Projection::Perspective => println!(
+            "  camera: from {} looking at {}, vfov {}, aperture {}, focus distance {}",
+            camera.look_from,
+            camera.look_at,
+            camera.vfov,
+            camera.aperture(),
+            camera.focus_distance()
+        ),
+        Projection::Orthographic { view_width } => println!(
+            "  camera: orthographic from {} looking at {}, view width {}",
+            camera.look_from, camera.look_at, view_width
+        ),
+        Projection::Equirectangular => {
+            println!("  camera: equirectangular from {} looking at {}", camera.look_from, camera.look_at)
         }
+        Projection::Fisheye { fov, mapping } => println!(
+            "  camera: {:?} fisheye from {} looking at {}, fov {}",
+            mapping, camera.look_from, camera.look_at, fov
+        ),
+    }
+    if !camera.aperture_shape.is_circle() {
+        println!("  aperture shape: {:?}", camera.aperture_shape);
+    }
+    if let Some(exposure) = camera.exposure {
+        println!(
+            "  exposure: f/{} at {} s and ISO {}, {} mm lens, radiance scaled by {}",
+            exposure.f_number,
+            exposure.shutter_time,
+            exposure.iso,
+            exposure.focal_length(camera.vfov),
+            exposure.radiance_scale()
+        );
+    }
+    if let Some(stereo) = camera.stereo {
+        println!(
+            "  stereo: {:?}, interpupillary distance {}, convergence distance {}",
+            stereo.layout,
+            stereo.interpupillary_distance,
+            camera.convergence_distance()
+        );
+    }
+    if let Some(animation) = &scene.animation {
+        println!(
+            "  animation: {} frames {} apart, {} camera keys ({:?})",
+            animation.frames,
+            animation.frame_time,
+            animation.camera_keys.len(),
+            animation.interpolation
+        );
     }
+    match scene.adaptive_sampling {
+        Some(adaptive) => println!(
+            "  sampling: adaptive, {} to {} samples per pixel, threshold {}",
+            adaptive.min_samples, adaptive.max_samples, adaptive.threshold
+        ),
+        None => println!("  sampling: {} samples per pixel", scene.samples_per_pixel),
+    }
+    println!("  seed: {}", scene.seed);
+    println!("  filter: {:?}", scene.filter);
+    println!("  tone mapping: {:?}, exposure {}", scene.tone_mapping.operator, scene.tone_mapping.exposure);
+    println!("  objects: {} ({} spheres)", scene.world.objects.len(), spheres);
+    Ok(())
 }
 
-fn render_multi_threaded_bvh(scene_path: &str, mut num_threads: i32) {
-    let scene: Scene = load_scene(scene_path);
-
-    let samples_per_pixel_per_thread =
-        f32::round(scene.samples_per_pixel as f32 / num_threads as f32) as i32;
-
-    let mut handles = vec![];
-    // let num_pixels: usize= (scene.image_height * scene.image_width) as usize;
-    let mut final_pixels =
-        vec![vec![Color::zero(); scene.image_width as usize]; scene.image_height as usize];
-
-    // progress printing
-    let mut scanline_completion_array = vec![0; scene.image_height as usize];
-    let mut scanlines_remaining = scene.image_height;
-
-    // initialize as all black
-    println_f!("P3\n{scene.image_width} {scene.image_height}\n255\n");
+fn generate(builtin: BuiltinScene, output: Option<PathBuf>) -> Result<(), String> {
+    let (name, create): (&str, fn(&str)) = match builtin {
+        BuiltinScene::TwoSpheresChecker => ("two-spheres-checker-texture", create_checker_spheres_test),
+        BuiltinScene::RandomChecker => ("random-checker", create_checker_test),
+        BuiltinScene::Random => ("random", create),
+    };
+    let output = output.unwrap_or_else(|| Path::new("scenes").join(format!("{}.json", name)));
+    create_parent_directory(&output)?;
+    create(&output.to_string_lossy());
+    Ok(())
+}
 
-    // maybe only use channels? aggregate color fully within thread, then once pixel is finished
-    // send fractionally sampled pixel to awating main thread, which will collect and aggregate them
-    // to build final output color.
-    let (pixel_value_transmitter, pixel_value_receiver) = mpsc::channel();
-    let (scanline_completion_transmitter, scanline_completion_receiver) = mpsc::channel();
+fn worker(address: &str, threads: Option<usize>) -> Result<(), String> {
+    let threads = threads.unwrap_or(RenderOptions::default().threads);
+    let listener = TcpListener::bind(address).map_err(|error| format!("Unable to listen on {}: {}", address, error))?;
+    // the port actually bound, for addresses like 127.0.0.1:0
+    let bound = listener.local_addr().map(|address| address.to_string()).unwrap_or_else(|_| address.to_string());
+    eprintln_f!("worker listening on {bound} with {threads} threads");
+    run_worker(listener, threads, move |error| eprintln!("Worker on {}: {}", bound, error));
+    Ok(())
+}
 
-    for thread in 0..num_threads {
-        let objects = scene.world.objects.clone();
-        let root = BvhNode::new_from_renderables(&objects);
-        let mut thread_world = RenderableList::new();
-        for obj in objects {
-            thread_world.add(obj)
+fn main() -> ExitCode {
+    env::set_var("RUST_BACKTRACE", "1");
+    let cli = Cli::parse();
+    let result = match cli.command {
+        Command::Render(args) => render(*args),
+        Command::Validate { scene } => validate(&scene),
+        Command::Info { scene } => info(&scene),
+        Command::Generate { builtin, output } => generate(builtin, output),
+        Command::Worker { listen, threads } => worker(&listen, threads),
+    };
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(message) => {
+            eprintln!("{}", message);
+            ExitCode::FAILURE
         }
-        let pixel_value_transmitter = pixel_value_transmitter.clone();
-        let scanline_completion_transmitter = scanline_completion_transmitter.clone();
-        let mut pixels =
-            vec![vec![Color::zero(); scene.image_width as usize]; scene.image_height as usize];
-        let handle = thread::spawn(move || {
-            for j in 0..scene.image_height {
-                for i in 0..scene.image_width {
-                    // spawn threads
-                    for _s in 0..samples_per_pixel_per_thread {
-                        // random point for ray to shoot at within this pixel
-                        let u = ((i as f32) + random_between_0_1()) / (scene.image_width as f32);
-                        let v = ((j as f32) + random_between_0_1()) / (scene.image_height as f32);
-                        let r = scene.camera.get_ray(u, v);
-                        // starting call depth is 0 as increases until hitting bounce depth
-                        pixels[j as usize][i as usize] += ray_color_bvh_node(&r, &root, 0);
-                    }
-                }
-                // eprintln_f!("Completed Scanline {j}");
-                // notify that this thread has completed this scanline
-                scanline_completion_transmitter.send(j).unwrap();
-            }
-            // send completed partial image once finished
-            pixel_value_transmitter.send(pixels).unwrap();
-            drop(pixel_value_transmitter);
-            drop(scanline_completion_transmitter);
-            eprintln_f!("Thread {thread} completed");
-        });
-        handles.push(handle);
     }
+}
 
-    // handle printing progress from threads
-    for completed_thread_scanline in scanline_completion_receiver {
-        scanline_completion_array[completed_thread_scanline as usize] += 1;
-        if scanline_completion_array[completed_thread_scanline as usize] == num_threads {
-            scanlines_remaining -= 1;
-            eprintln_f!("Scanlines Remaining: {scanlines_remaining}");
-        }
-        if scanlines_remaining == 0 {
-            break;
-        }
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn render_flags_parse() {
+        let cli = Cli::try_parse_from([
+            "raytracer", "render", "scene.json", "-o", "out.exr", "--threads", "4", "--spp", "64", "--bvh", "--seed", "7",
+            "--aovs", "albedo,depth", "--crop", "0,0,10,20",
+        ])
+        .unwrap();
+        let Command::Render(args) = cli.command else {
+            panic!("expected a render command");
+        };
+        assert_eq!(args.scene, PathBuf::from("scene.json"));
+        assert_eq!(args.output, PathBuf::from("out.exr"));
+        assert_eq!((args.threads, args.spp, args.bvh, args.seed), (Some(4), Some(64), true, Some(7)));
+        assert_eq!(args.aovs, vec![Aov::Albedo, Aov::Depth]);
+        assert_eq!(args.crop, Some(CropWindow::Pixels { x0: 0, y0: 0, x1: 10, y1: 20 }));
+        assert_eq!(parse_crop("0.5, 0, 1, 0.25"), Ok(CropWindow::Normalized { x0: 0.5, y0: 0.0, x1: 1.0, y1: 0.25 }));
+        assert!(parse_crop("1,2,3").is_err());
+        assert_eq!((args.bit_depth, args.ppm_ascii), (None, false));
+        assert_eq!((parse_frames("12"), parse_frames("1-24")), (Ok((12, 12)), Ok((1, 24))));
+        assert_eq!(args.checkpoint_interval, 60.0);
+        let cli =
+            Cli::try_parse_from(["raytracer", "render", "scene.json", "--checkpoint", "c.json", "--checkpoint-interval", "2.5"]);
+        let Command::Render(args) = cli.unwrap().command else {
+            panic!("expected a render command");
+        };
+        assert_eq!(args.checkpoint_interval, 2.5);
     }
 
-    for partially_rendered_scene in pixel_value_receiver {
-        for y in 0..partially_rendered_scene.len() {
-            for x in 0..partially_rendered_scene[y].len() {
-                final_pixels[y][x] += partially_rendered_scene[y][x];
-            }
-        }
-        num_threads -= 1;
-        eprintln_f!("Threads Remaining: {num_threads}");
-        if num_threads == 0 {
-            break;
-        }
+    #[test]
+    fn output_format_flags_parse() {
+        let cli = Cli::try_parse_from(["raytracer", "render", "scene.json", "-o", "out.png", "--bit-depth", "16", "--ppm-ascii", "--exr-precision", "float"])
+            .unwrap();
+        let Command::Render(args) = cli.command else {
+            panic!("expected a render command");
+        };
+        assert_eq!((args.bit_depth, args.ppm_ascii), (Some(BitDepth::Sixteen), true));
+        assert_eq!(args.exr_precision, Some(ExrPrecisionArg::Float));
+        assert!(Cli::try_parse_from(["raytracer", "render", "scene.json", "--bit-depth", "12"]).is_err());
+        assert!(Cli::try_parse_from(["raytracer", "render", "scene.json", "--exr-precision", "double"]).is_err());
     }
 
-    drop(pixel_value_transmitter);
-    drop(scanline_completion_transmitter);
-
-    // clean up threads
-    for handle in handles {
-        handle.join().unwrap();
-        eprintln_f!("Cleaning up threads");
+    #[test]
+    fn tone_mapping_flags_parse() {
+        let cli =
+            Cli::try_parse_from(["raytracer", "render", "scene.json", "--tone-map", "reinhard:4", "--exposure", "-1.5"]).unwrap();
+        let Command::Render(args) = cli.command else {
+            panic!("expected a render command");
+        };
+        assert_eq!((args.tone_map, args.exposure), (Some(ToneMapOperator::Reinhard { white_point: 4.0 }), Some(-1.5)));
+        assert_eq!(parse_tone_map("aces"), Ok(ToneMapOperator::Aces));
+        for operator in ["reinhard", "reinhard:0", "reinhard:x", "filmic", "aces:2"] {
+            assert!(parse_tone_map(operator).is_err(), "{}", operator);
+        }
     }
 
-    // write final colors to output
-    for y in (0..final_pixels.len()).rev() {
-        for x in 0..final_pixels[y].len() {
-            write_color_to_output(final_pixels[y][x], scene.samples_per_pixel);
+    #[test]
+    fn bad_arguments_are_rejected() {
+        assert!(Cli::try_parse_from(["raytracer", "render", "scene.json", "--aovs", "shininess"]).is_err());
+        assert!(Cli::try_parse_from(["raytracer", "render", "scene.json", "--spp", "0"]).is_err());
+        assert!(Cli::try_parse_from(["raytracer", "render", "scene.json", "--spp=-3"]).is_err());
+        assert!(Cli::try_parse_from(["raytracer", "render", "scene.json", "--pass-spp", "0"]).is_err());
+        assert!(Cli::try_parse_from(["raytracer", "render", "scene.json", "--resume"]).is_err());
+        assert!(Cli::try_parse_from(["raytracer", "render", "scene.json", "--checkpoint-interval", "5"]).is_err());
+        let negative = ["raytracer", "render", "scene.json", "--checkpoint", "c.json", "--checkpoint-interval", "-5"];
+        assert!(Cli::try_parse_from(negative).is_err());
+        assert!(Cli::try_parse_from(["raytracer", "generate", "teapot"]).is_err());
+        let resume = ["raytracer", "render", "scene.json", "--workers", "a:7878", "--checkpoint", "c.json", "--resume"];
+        assert!(Cli::try_parse_from(resume).is_err());
+        assert!(Cli::try_parse_from(["raytracer", "render", "scene.json", "--workers", "a:7878", "--pass-spp", "4"]).is_err());
+        // progressive settings only make sense for a render in passes, and need a number of seconds
+        assert!(Cli::try_parse_from(["raytracer", "render", "scene.json", "--time-limit", "30"]).is_err());
+        assert!(Cli::try_parse_from(["raytracer", "render", "scene.json", "--snapshot-interval", "5"]).is_err());
+        for seconds in ["--time-limit=-1", "--time-limit=nan", "--snapshot-interval=inf"] {
+            assert!(Cli::try_parse_from(["raytracer", "render", "scene.json", "--pass-spp", "4", seconds]).is_err(), "{}", seconds);
+        }
+        let progressive = ["raytracer", "render", "scene.json", "--pass-spp", "4", "--time-limit", "30", "--snapshot-interval", "0.5"];
+        let Command::Render(args) = Cli::try_parse_from(progressive).unwrap().command else {
+            panic!("expected a render command");
+        };
+        assert_eq!((args.time_limit, args.snapshot_interval), (Some(30.0), Some(0.5)));
+        for frames in ["0", "5-2", "1-", "x"] {
+            assert!(Cli::try_parse_from(["raytracer", "render", "scene.json", "--frames", frames]).is_err(), "{}", frames);
         }
     }
 }
-
-fn main() {
-    env::set_var("RUST_BACKTRACE", "1");
-    // create_test("./test-texture.json");
-    // create("test-texture.json");
-    // create_checker_test("test-checker-texture.json");
-    create_checker_spheres_test("two-spheres-checker-texture.json");
-    let start = Instant::now();
-    render_multi_threaded("scenes/two-spheres-checker-texture.json", 10);
-    // render("scenes/test.json");
-    let elapsed = start.elapsed().as_secs_f32();
-    eprintln_f!("scene rendered in {elapsed}");
-    /*
-    render : single thread -
-    render : multi-thread (5 threads) - 
-
-    render : bvh : single thread - 
-    render : bvh : multi-thread (5 threads) - 111.0266s
-     */
-}
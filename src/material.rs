@@ -2,11 +2,12 @@ use serde::{Deserialize, Serialize};
 
 use crate::ray::Ray;
 use crate::renderable::HitRecord;
-use crate::texture::{RenderableTexture, SolidColor, Texture};
-use crate::util::{random_between_0_1, Color, Point, Vec3};
+use crate::texture::{RenderableTexture, Texture};
+use crate::sampler::{sample_unit_ball, sample_unit_vector, Sampler};
+use crate::util::{Color, Vec3};
 use std::fmt;
 pub trait Material {
-    fn scatter(&self, r_in: &Ray, hit_record: &HitRecord) -> (bool, Color, Ray);
+    fn scatter(&self, r_in: &Ray, hit_record: &HitRecord, sampler: &mut dyn Sampler) -> (bool, Color, Ray);
 }
 
 #[derive(Debug, Serialize, Deserialize, Copy, Clone)]
@@ -18,18 +19,18 @@ pub enum RenderableMaterial {
 }
 
 impl Material for RenderableMaterial {
-    fn scatter(&self, r_in: &Ray, hit_record: &HitRecord) -> (bool, Color, Ray) {
+    fn scatter(&self, r_in: &Ray, hit_record: &HitRecord, sampler: &mut dyn Sampler) -> (bool, Color, Ray) {
         match self {
-            RenderableMaterial::Lambertian(lm) => lm.scatter(r_in, hit_record),
-            RenderableMaterial::Metal(m) => m.scatter(r_in, hit_record),
-            RenderableMaterial::Dielectric(d) => d.scatter(r_in, hit_record),
+            RenderableMaterial::Lambertian(lm) => lm.scatter(r_in, hit_record, sampler),
+            RenderableMaterial::Metal(m) => m.scatter(r_in, hit_record, sampler),
+            RenderableMaterial::Dielectric(d) => d.scatter(r_in, hit_record, sampler),
         }
     }
 }
 
 #[derive(Debug, Copy, Clone, Serialize, Deserialize)]
 pub struct LambertianMaterial {
-    albedo: RenderableTexture,
+    pub(crate) albedo: RenderableTexture,
 }
 
 // default material
@@ -40,13 +41,13 @@ impl LambertianMaterial {
 }
 
 impl Material for LambertianMaterial {
-    fn scatter(&self, r_in: &Ray, hit_record: &HitRecord) -> (bool, Color, Ray) {
-        let mut scatter_direction = hit_record.normal + Vec3::random_unit_vector();
+    fn scatter(&self, r_in: &Ray, hit_record: &HitRecord, sampler: &mut dyn Sampler) -> (bool, Color, Ray) {
+        let mut scatter_direction = hit_record.normal + sample_unit_vector(sampler.get_2d());
         if scatter_direction.near_zero() {
             scatter_direction = hit_record.normal;
         }
         let scattered = Ray::new_with_time(hit_record.point, scatter_direction, r_in.time);
-        (true, self.albedo.value(hit_record.u, hit_record.v, &hit_record.point), scattered)
+        (true, self.albedo.value(hit_record.u, hit_record.v, &hit_record.texture_point), scattered)
     }
 }
 
@@ -68,22 +69,22 @@ impl fmt::Display for LambertianMaterial {
 
 #[derive(Debug, Copy, Clone, Serialize, Deserialize)]
 pub struct Metal {
-    albedo: RenderableTexture,
-    fuzziness: f32,
+    pub(crate) albedo: RenderableTexture,
+    pub(crate) fuzziness: f32,
 }
 
 impl Metal {
-    pub fn new(RenderableTexture: RenderableTexture, fuzziness: Option<f32>) -> Self {
+    pub fn new(albedo: RenderableTexture, fuzziness: Option<f32>) -> Self {
         let f = fuzziness.unwrap_or(0.0);
         Self {
-            albedo: RenderableTexture,
+            albedo,
             fuzziness: if f < 1.0 { f } else { 0.0 },
         }
     }
 }
 
 impl Material for Metal {
-    fn scatter(&self, r_in: &Ray, hit_record: &HitRecord) -> (bool, Color, Ray) {
+    fn scatter(&self, r_in: &Ray, hit_record: &HitRecord, sampler: &mut dyn Sampler) -> (bool, Color, Ray) {
         let reflected = Vec3::reflect(r_in.direction.unit_vector(), hit_record.normal);
         /*
          * if there is any fuzziness ( > 0.0) then it will add some offset in a unit sphere
@@ -91,14 +92,14 @@ impl Material for Metal {
          */
         let scattered = Ray::new_with_time(
             hit_record.point,
-            reflected + (self.fuzziness * Point::random_in_unit_sphere()),
+            reflected + (self.fuzziness * sample_unit_ball(sampler.get_2d(), sampler.get_1d())),
             r_in.time
         );
-        return (
+        (
             scattered.direction.dot(hit_record.normal) > 0.0,
-            self.albedo.value(hit_record.u, hit_record.v, &hit_record.point),
+            self.albedo.value(hit_record.u, hit_record.v, &hit_record.texture_point),
             scattered,
-        );
+        )
     }
 }
 
@@ -121,7 +122,7 @@ impl fmt::Display for Metal {
 #[derive(Debug, Serialize, Deserialize, Copy, Clone)]
 pub struct Dielectric {
     // index of refraction (𝜂')
-    ir: f32,
+    pub(crate) ir: f32,
 }
 
 impl Dielectric {
@@ -138,7 +139,7 @@ impl Dielectric {
     fn reflectance(cosine: f32, ref_idx: f32) -> f32 {
         let r0 = (1.0 - ref_idx) / (1.0 + ref_idx);
         let r0 = r0 * r0;
-        return r0 * (1.0 - r0) * (f32::powi(1.0 - cosine, 5));
+        r0 * (1.0 - r0) * (f32::powi(1.0 - cosine, 5))
     }
 }
 
@@ -159,7 +160,7 @@ impl Material for Dielectric {
      * And we know that cos(theta) is equal to R * n, given R and n are both unit vectors
      * so our final solution for sin(theta) = sqrt(1 - |R * n|^2)
      */
-    fn scatter(&self, r_in: &Ray, hit_record: &HitRecord) -> (bool, Color, Ray) {
+    fn scatter(&self, r_in: &Ray, hit_record: &HitRecord, sampler: &mut dyn Sampler) -> (bool, Color, Ray) {
         // doesn't dim the reflection at all, full brightness and full RenderableTexture
         let attenuation = Color::new(1.0, 1.0, 1.0);
         let refraction_ratio = if hit_record.front_face {
@@ -175,7 +176,7 @@ impl Material for Dielectric {
         // assume by default that it won't refract, so reflect it instead
         let mut direction = Vec3::reflect(unit_direction, hit_record.normal);
         // some angles of viewing produce higher reflection than others
-        if cannot_refract || Self::reflectance(cos_theta, self.ir) > random_between_0_1() {
+        if cannot_refract || Self::reflectance(cos_theta, self.ir) > sampler.get_1d() {
             let refracted = Vec3::refract(unit_direction, hit_record.normal, refraction_ratio);
             direction = refracted;
         }
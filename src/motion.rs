@@ -0,0 +1,272 @@
+use serde::{Deserialize, Serialize};
+
+use crate::aabb::AABB;
+use crate::util::{degrees_to_radians, Point, Vec3};
+
+// the most a rotation turns between two of the positions `Motion::sphere_bounds` looks at
+const BOUNDS_STEP_DEGREES: f32 = 10.0;
+
+/**
+ * Where an object is over time, as keyframes. Between two keys the translation is interpolated linearly and the
+ * rotation with slerp (a constant turning speed along the shortest way round), so a path curves when the object is
+ * away from the point it rotates around. Before the first key and after the last one the object stays put.
+ *
+ * Key times are in the same units as the camera's shutter and have to be in order. A rotation of more than 180
+ * degrees between two keys takes the short way round, spins need a key at least every half turn.
+ */
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Motion {
+    pub keys: Vec<Keyframe>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Keyframe {
+    pub time: f32,
+    #[serde(default = "Vec3::zero")]
+    pub translation: Vec3,
+    // around the origin of the object's own coordinates, applied before the translation
+    #[serde(default)]
+    pub rotation: Rotation,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Rotation {
+    pub axis: Vec3,
+    pub degrees: f32,
+}
+
+impl Default for Rotation {
+    fn default() -> Self {
+        Self { axis: Vec3::new(0.0, 1.0, 0.0), degrees: 0.0 }
+    }
+}
+
+// a rigid transform from an object's own coordinates into the world
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Transform {
+    pub rotation: Quaternion,
+    pub translation: Vec3,
+}
+
+impl Transform {
+    pub fn identity() -> Self {
+        Self { rotation: Quaternion::identity(), translation: Vec3::zero() }
+    }
+
+    pub fn point(&self, p: Point) -> Point {
+        self.rotation.rotate(p) + self.translation
+    }
+
+    pub fn vector(&self, v: Vec3) -> Vec3 {
+        self.rotation.rotate(v)
+    }
+
+    pub fn inverse_point(&self, p: Point) -> Point {
+        self.rotation.conjugate().rotate(p - self.translation)
+    }
+
+    pub fn inverse_vector(&self, v: Vec3) -> Vec3 {
+        self.rotation.conjugate().rotate(v)
+    }
+}
+
+// unit quaternion, w + xi + yj + zk with (x, y, z) kept as a vector
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Quaternion {
+    w: f32,
+    v: Vec3,
+}
+
+impl Quaternion {
+    pub fn identity() -> Self {
+        Self { w: 1.0, v: Vec3::zero() }
+    }
+
+    pub fn from_rotation(rotation: &Rotation) -> Self {
+        if rotation.degrees == 0.0 || rotation.axis.len_squared() == 0.0 {
+            return Self::identity();
+        }
+        let half_angle = degrees_to_radians(rotation.degrees) / 2.0;
+        Self { w: f32::cos(half_angle), v: f32::sin(half_angle) * rotation.axis.unit_vector() }
+    }
+
+    fn dot(&self, other: &Quaternion) -> f32 {
+        self.w * other.w + self.v.dot(other.v)
+    }
+
+    fn scale(&self, s: f32) -> Quaternion {
+        Quaternion { w: self.w * s, v: s * self.v }
+    }
+
+    fn add(&self, other: &Quaternion) -> Quaternion {
+        Quaternion { w: self.w + other.w, v: self.v + other.v }
+    }
+
+    pub fn conjugate(&self) -> Quaternion {
+        Quaternion { w: self.w, v: -self.v }
+    }
+
+    pub fn rotate(&self, p: Vec3) -> Vec3 {
+        // q p q* expanded, which saves building the quaternion products
+        let t = 2.0 * Vec3::cross(self.v, p);
+        p + self.w * t + Vec3::cross(self.v, t)
+    }
+
+    // how far (in radians) the shortest rotation from this orientation to `other` turns
+    pub fn angle_to(&self, other: &Quaternion) -> f32 {
+        2.0 * f32::acos(f32::min(f32::abs(self.dot(other)), 1.0))
+    }
+
+    pub fn slerp(&self, other: &Quaternion, s: f32) -> Quaternion {
+        // q and -q are the same rotation, the one closer to self is the short way round
+        let mut cos_theta = self.dot(other);
+        let other = if cos_theta < 0.0 {
+            cos_theta = -cos_theta;
+            other.scale(-1.0)
+        } else {
+            *other
+        };
+        if cos_theta > 0.9995 {
+            // too close together for sin(theta) to divide by, a normalized lerp is just as good here
+            let lerp = self.scale(1.0 - s).add(&other.scale(s));
+            return lerp.scale(1.0 / f32::sqrt(lerp.dot(&lerp)));
+        }
+        let theta = f32::acos(cos_theta);
+        let sin_theta = f32::sin(theta);
+        self.scale(f32::sin((1.0 - s) * theta) / sin_theta).add(&other.scale(f32::sin(s * theta) / sin_theta))
+    }
+}
+
+impl Keyframe {
+    fn transform(&self) -> Transform {
+        Transform { rotation: Quaternion::from_rotation(&self.rotation), translation: self.translation }
+    }
+}
+
+// `s` goes from 0 at `a` to 1 at `b`
+fn interpolate(a: &Transform, b: &Transform, s: f32) -> Transform {
+    Transform {
+        rotation: a.rotation.slerp(&b.rotation, s),
+        translation: a.translation + s * (b.translation - a.translation),
+    }
+}
+
+impl Motion {
+    pub fn transform_at(&self, time: f32) -> Transform {
+        let (first, last) = match (self.keys.first(), self.keys.last()) {
+            (Some(first), Some(last)) => (first, last),
+            _ => return Transform::identity(),
+        };
+        if time <= first.time {
+            return first.transform();
+        }
+        if time >= last.time {
+            return last.transform();
+        }
+        // the first key after `time`, there is one since time is before the last key
+        let next = self.keys.iter().position(|key| key.time > time).unwrap_or(self.keys.len() - 1);
+        let (a, b) = (&self.keys[next - 1], &self.keys[next]);
+        interpolate(&a.transform(), &b.transform(), (time - a.time) / (b.time - a.time))
+    }
+
+    /**
+     * A box around a sphere at `center` (in the object's own coordinates) everywhere the motion takes it. Positions
+     * are looked at every few degrees of rotation, the bit the arc between two of them bulges out is added on.
+     */
+    pub fn sphere_bounds(&self, center: Point, radius: f32) -> AABB {
+        let transforms: Vec<Transform> = match self.keys.is_empty() {
+            true => vec![Transform::identity()],
+            false => self.keys.iter().map(Keyframe::transform).collect(),
+        };
+        sphere_bounds_along(&transforms, center, radius)
+    }
+
+    // like `sphere_bounds`, for only the part of the motion between `open` and `close`
+    pub fn sphere_bounds_between(&self, center: Point, radius: f32, open: f32, close: f32) -> AABB {
+        let mut transforms = vec![self.transform_at(open)];
+        transforms.extend(self.keys.iter().filter(|key| key.time > open && key.time < close).map(Keyframe::transform));
+        transforms.push(self.transform_at(close));
+        sphere_bounds_along(&transforms, center, radius)
+    }
+}
+
+// a box around a sphere going through `transforms` in turn, interpolated between them the way keys are
+fn sphere_bounds_along(transforms: &[Transform], center: Point, radius: f32) -> AABB {
+    let radius_vec = Vec3::new(radius, radius, radius);
+    let sphere_box = |transform: &Transform, padding: f32| {
+        let c = transform.point(center);
+        let extent = radius_vec + Vec3::new(padding, padding, padding);
+        AABB::new_from_pts(c - extent, c + extent)
+    };
+    let mut bounds = sphere_box(&transforms[0], 0.0);
+    for pair in transforms.windows(2) {
+        let (a, b) = (pair[0], pair[1]);
+        let angle = a.rotation.angle_to(&b.rotation);
+        let steps = f32::max(f32::ceil(angle / degrees_to_radians(BOUNDS_STEP_DEGREES)), 1.0) as usize;
+        // sagitta of the arc `center` sweeps between two steps
+        let padding = center.len() * (1.0 - f32::cos(angle / steps as f32 / 2.0));
+        for step in 0..=steps {
+            let transform = interpolate(&a, &b, step as f32 / steps as f32);
+            bounds = AABB::new_from_bbox(bounds, sphere_box(&transform, padding));
+        }
+    }
+    bounds
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(time: f32, translation: Vec3, degrees: f32) -> Keyframe {
+        Keyframe { time, translation, rotation: Rotation { axis: Vec3::new(0.0, 1.0, 0.0), degrees } }
+    }
+
+    fn close(a: Vec3, b: Vec3) -> bool {
+        (a - b).len() < 1e-5
+    }
+
+    #[test]
+    fn keys_are_interpolated_and_held() {
+        let motion = Motion {
+            keys: vec![key(0.0, Vec3::zero(), 0.0), key(1.0, Vec3::new(2.0, 0.0, 0.0), 90.0), key(3.0, Vec3::new(2.0, 4.0, 0.0), 90.0)],
+        };
+        let p = Point::new(1.0, 0.0, 0.0);
+        assert!(close(motion.transform_at(-1.0).point(p), p));
+        // halfway through the first key pair, rotated 45 degrees about y and moved halfway along x
+        let half = f32::sqrt(0.5);
+        assert!(close(motion.transform_at(0.5).point(p), Point::new(half + 1.0, 0.0, -half)));
+        assert!(close(motion.transform_at(2.0).point(p), Point::new(2.0, 2.0, -1.0)));
+        assert!(close(motion.transform_at(5.0).point(p), Point::new(2.0, 4.0, -1.0)));
+
+        let transform = motion.transform_at(0.7);
+        assert!(close(transform.inverse_point(transform.point(p)), p));
+    }
+
+    #[test]
+    fn slerp_turns_at_a_constant_speed() {
+        let a = Quaternion::from_rotation(&Rotation { axis: Vec3::new(0.0, 0.0, 1.0), degrees: 0.0 });
+        let b = Quaternion::from_rotation(&Rotation { axis: Vec3::new(0.0, 0.0, 1.0), degrees: 120.0 });
+        for s in [0.25, 0.5, 0.75] {
+            let angle = a.angle_to(&a.slerp(&b, s)).to_degrees();
+            assert!(f32::abs(angle - 120.0 * s) < 1e-2, "{} degrees at {}", angle, s);
+        }
+    }
+
+    #[test]
+    fn sphere_bounds_cover_the_whole_arc() {
+        // a quarter turn around the y axis at a distance of 5, the arc bulges out past both ends
+        let motion = Motion { keys: vec![key(0.0, Vec3::zero(), 0.0), key(1.0, Vec3::zero(), 90.0)] };
+        let center = Point::new(5.0, 0.0, 0.0);
+        let bounds = motion.sphere_bounds(center, 1.0);
+        for i in 0..=100 {
+            let c = motion.transform_at(i as f32 / 100.0).point(center);
+            let fits = |interval: &crate::util::Interval, x: f32| interval.min <= x - 1.0 && x + 1.0 <= interval.max;
+            assert!(fits(&bounds.x_interval, c.x()) && fits(&bounds.y_interval, c.y()) && fits(&bounds.z_interval, c.z()));
+        }
+
+        // the first half of the turn only gets 45 degrees of the way round to -z
+        let first_half = motion.sphere_bounds_between(center, 1.0, 0.0, 0.5);
+        assert!(first_half.z_interval.min > bounds.z_interval.min + 1.0);
+        assert!(f32::abs(first_half.x_interval.max - bounds.x_interval.max) < 0.01);
+    }
+}
@@ -0,0 +1,594 @@
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use crate::aov::Aov;
+use crate::framebuffer::FrameBuffer;
+use crate::renderer::RenderOutput;
+use crate::tonemap::ToneMapping;
+use crate::util::Color;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImageFormat {
+    Png,
+    Ppm,
+    // the formats below store linear, unclamped radiance
+    Exr,
+    Hdr,
+    Pfm,
+}
+
+impl ImageFormat {
+    // picks the format from the file extension, e.g. "out.png" or "out.ppm"
+    pub fn from_path<P: AsRef<Path>>(path: P) -> Option<Self> {
+        let extension = path.as_ref().extension()?.to_str()?.to_ascii_lowercase();
+        match extension.as_str() {
+            "png" => Some(ImageFormat::Png),
+            "ppm" | "pnm" => Some(ImageFormat::Ppm),
+            "exr" => Some(ImageFormat::Exr),
+            "hdr" => Some(ImageFormat::Hdr),
+            "pfm" => Some(ImageFormat::Pfm),
+            _ => None,
+        }
+    }
+
+    pub fn is_high_dynamic_range(&self) -> bool {
+        matches!(self, ImageFormat::Exr | ImageFormat::Hdr | ImageFormat::Pfm)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BitDepth {
+    Eight,
+    Sixteen,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PpmEncoding {
+    // P6, raw bytes
+    Binary,
+    // P3, one "r g b" triple of decimal numbers per line
+    Ascii,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExrPrecision {
+    Half,
+    Float,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct OutputOptions {
+    // only used for PNG, PPM is always 8 bits per channel
+    pub bit_depth: BitDepth,
+    pub ppm_encoding: PpmEncoding,
+    pub exr_precision: ExrPrecision,
+    // applied when writing PNG and PPM, HDR formats always get the untouched radiance
+    pub tone_mapping: ToneMapping,
+}
+
+impl Default for OutputOptions {
+    fn default() -> Self {
+        Self {
+            bit_depth: BitDepth::Eight,
+            ppm_encoding: PpmEncoding::Binary,
+            exr_precision: ExrPrecision::Half,
+            tone_mapping: ToneMapping::default(),
+        }
+    }
+}
+
+fn to_8_bit(color: Color, tone_mapping: &ToneMapping) -> [u8; 3] {
+    let display = tone_mapping.apply(color);
+    [
+        (255.0 * display.x()).round() as u8,
+        (255.0 * display.y()).round() as u8,
+        (255.0 * display.z()).round() as u8,
+    ]
+}
+
+fn to_16_bit(color: Color, tone_mapping: &ToneMapping) -> [u16; 3] {
+    let display = tone_mapping.apply(color);
+    [
+        (65535.0 * display.x()).round() as u16,
+        (65535.0 * display.y()).round() as u16,
+        (65535.0 * display.z()).round() as u16,
+    ]
+}
+
+/**
+ * Image files are stored top row first while the framebuffer is bottom row first, this is the one place
+ * where that gets flipped.
+ */
+fn pixels_top_down(framebuffer: &FrameBuffer) -> impl Iterator<Item = Color> + '_ {
+    (0..framebuffer.height())
+        .rev()
+        .flat_map(move |y| (0..framebuffer.width()).map(move |x| framebuffer.get(x, y)))
+}
+
+fn format_for_path(path: &Path) -> io::Result<ImageFormat> {
+    ImageFormat::from_path(path).ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("unsupported image extension: {}", path.display()),
+        )
+    })
+}
+
+pub fn write_image<P: AsRef<Path>>(path: P, framebuffer: &FrameBuffer, options: &OutputOptions) -> io::Result<()> {
+    let path = path.as_ref();
+    let format = format_for_path(path)?;
+    let mut writer = BufWriter::new(File::create(path)?);
+    write_image_to(&mut writer, framebuffer, format, options)?;
+    writer.flush()
+}
+
+/**
+ * Writes the beauty pass and every AOV of a render. EXR gets all of them as layers of a single file,
+ * any other format gets one extra file per AOV next to the beauty image, e.g. "out.albedo.png".
+ */
+pub fn write_render_output<P: AsRef<Path>>(path: P, output: &RenderOutput, options: &OutputOptions) -> io::Result<()> {
+    let path = path.as_ref();
+    let format = format_for_path(path)?;
+    if format == ImageFormat::Exr {
+        let mut layers = vec![ExrLayer { name: "", framebuffer: &output.beauty }];
+        layers.extend(output.aovs.iter().map(|(aov, buffer)| ExrLayer { name: aov.name(), framebuffer: buffer }));
+        let mut writer = BufWriter::new(File::create(path)?);
+        write_exr(&mut writer, &layers, options.exr_precision)?;
+        return writer.flush();
+    }
+
+    write_image(path, &output.beauty, options)?;
+    for (aov, buffer) in &output.aovs {
+        if format.is_high_dynamic_range() {
+            write_image(aov_path(path, *aov), buffer, options)?;
+        } else {
+            // data passes are only visualized, so the scene's tone mapping would just distort them
+            let tone_mapping = if aov.is_lighting() { options.tone_mapping } else { ToneMapping::default() };
+            let aov_options = OutputOptions { tone_mapping, ..*options };
+            write_image(aov_path(path, *aov), &aov.visualize(buffer), &aov_options)?;
+        }
+    }
+    Ok(())
+}
+
+// "renders/out.png" -> "renders/out.albedo.png"
+pub fn aov_path(path: &Path, aov: Aov) -> PathBuf {
+    let stem = path.file_stem().and_then(|s| s.to_str()).unwrap_or("");
+    let mut file_name = format!("{}.{}", stem, aov.name());
+    if let Some(extension) = path.extension().and_then(|e| e.to_str()) {
+        file_name = format!("{}.{}", file_name, extension);
+    }
+    path.with_file_name(file_name)
+}
+
+// "renders/frame.png" -> "renders/frame_0001.png" for frame 1 of an animation
+pub fn frame_path(path: &Path, frame: u32) -> PathBuf {
+    let stem = path.file_stem().and_then(|s| s.to_str()).unwrap_or("");
+    let mut file_name = format!("{}_{:04}", stem, frame);
+    if let Some(extension) = path.extension().and_then(|e| e.to_str()) {
+        file_name = format!("{}.{}", file_name, extension);
+    }
+    path.with_file_name(file_name)
+}
+
+pub fn write_image_to<W: Write>(
+    writer: W,
+    framebuffer: &FrameBuffer,
+    format: ImageFormat,
+    options: &OutputOptions,
+) -> io::Result<()> {
+    match format {
+        ImageFormat::Png => write_png(writer, framebuffer, options.bit_depth, &options.tone_mapping),
+        ImageFormat::Ppm => write_ppm(writer, framebuffer, options.ppm_encoding, &options.tone_mapping),
+        ImageFormat::Exr => write_exr(writer, &[ExrLayer { name: "", framebuffer }], options.exr_precision),
+        ImageFormat::Hdr => write_hdr(writer, framebuffer),
+        ImageFormat::Pfm => write_pfm(writer, framebuffer),
+    }
+}
+
+fn write_png<W: Write>(
+    writer: W,
+    framebuffer: &FrameBuffer,
+    bit_depth: BitDepth,
+    tone_mapping: &ToneMapping,
+) -> io::Result<()> {
+    let mut encoder = png::Encoder::new(writer, framebuffer.width() as u32, framebuffer.height() as u32);
+    encoder.set_color(png::ColorType::Rgb);
+    let data: Vec<u8> = match bit_depth {
+        BitDepth::Eight => {
+            encoder.set_depth(png::BitDepth::Eight);
+            pixels_top_down(framebuffer).flat_map(|c| to_8_bit(c, tone_mapping)).collect()
+        }
+        BitDepth::Sixteen => {
+            encoder.set_depth(png::BitDepth::Sixteen);
+            // PNG stores 16 bit samples big endian
+            pixels_top_down(framebuffer)
+                .flat_map(|c| to_16_bit(c, tone_mapping))
+                .flat_map(u16::to_be_bytes)
+                .collect()
+        }
+    };
+    let mut png_writer = encoder.write_header().map_err(io::Error::other)?;
+    png_writer.write_image_data(&data).map_err(io::Error::other)?;
+    png_writer.finish().map_err(io::Error::other)
+}
+
+fn write_ppm<W: Write>(
+    mut writer: W,
+    framebuffer: &FrameBuffer,
+    encoding: PpmEncoding,
+    tone_mapping: &ToneMapping,
+) -> io::Result<()> {
+    match encoding {
+        PpmEncoding::Binary => {
+            write!(writer, "P6\n{} {}\n255\n", framebuffer.width(), framebuffer.height())?;
+            let data: Vec<u8> = pixels_top_down(framebuffer).flat_map(|c| to_8_bit(c, tone_mapping)).collect();
+            writer.write_all(&data)
+        }
+        PpmEncoding::Ascii => {
+            writeln!(writer, "P3\n{} {}\n255", framebuffer.width(), framebuffer.height())?;
+            for color in pixels_top_down(framebuffer) {
+                let [r, g, b] = to_8_bit(color, tone_mapping);
+                writeln!(writer, "{} {} {}", r, g, b)?;
+            }
+            Ok(())
+        }
+    }
+}
+
+/**
+ * Radiance RGBE: the three channels share one exponent byte, giving float range at 4 bytes per pixel.
+ * Scanlines are written flat (uncompressed), which every reader accepts.
+ */
+fn write_hdr<W: Write>(mut writer: W, framebuffer: &FrameBuffer) -> io::Result<()> {
+    write!(
+        writer,
+        "#?RADIANCE\nFORMAT=32-bit_rle_rgbe\n\n-Y {} +X {}\n",
+        framebuffer.height(),
+        framebuffer.width()
+    )?;
+    let data: Vec<u8> = pixels_top_down(framebuffer).flat_map(to_rgbe).collect();
+    writer.write_all(&data)
+}
+
+fn to_rgbe(color: Color) -> [u8; 4] {
+    let brightest = f32::max(color.x(), f32::max(color.y(), color.z()));
+    if brightest.is_nan() || brightest < 1e-32 {
+        return [0, 0, 0, 0];
+    }
+    // brightest = mantissa * 2^exponent with mantissa in [0.5, 1)
+    let mut exponent = brightest.log2().floor() as i32 + 1;
+    if brightest / f32::powi(2.0, exponent) >= 1.0 {
+        exponent += 1;
+    }
+    let exponent = exponent.clamp(-128, 127);
+    let scale = 256.0 / f32::powi(2.0, exponent);
+    [
+        (f32::max(color.x(), 0.0) * scale).min(255.0) as u8,
+        (f32::max(color.y(), 0.0) * scale).min(255.0) as u8,
+        (f32::max(color.z(), 0.0) * scale).min(255.0) as u8,
+        (exponent + 128) as u8,
+    ]
+}
+
+// portable float map, rows are stored bottom to top just like the framebuffer; a negative scale marks little endian
+fn write_pfm<W: Write>(mut writer: W, framebuffer: &FrameBuffer) -> io::Result<()> {
+    write!(writer, "PF\n{} {}\n-1.0\n", framebuffer.width(), framebuffer.height())?;
+    let data: Vec<u8> = framebuffer
+        .pixels()
+        .iter()
+        .flat_map(|color| [color.x(), color.y(), color.z()])
+        .flat_map(f32::to_le_bytes)
+        .collect();
+    writer.write_all(&data)
+}
+
+// one named group of R, G, B channels in an EXR file, the beauty pass uses the empty name
+pub struct ExrLayer<'a> {
+    pub name: &'a str,
+    pub framebuffer: &'a FrameBuffer,
+}
+
+impl ExrLayer<'_> {
+    fn channel_name(&self, channel: &str) -> String {
+        if self.name.is_empty() {
+            channel.to_string()
+        } else {
+            format!("{}.{}", self.name, channel)
+        }
+    }
+}
+
+fn write_exr_attribute<W: Write>(writer: &mut W, name: &str, kind: &str, value: &[u8]) -> io::Result<()> {
+    writer.write_all(name.as_bytes())?;
+    writer.write_all(&[0])?;
+    writer.write_all(kind.as_bytes())?;
+    writer.write_all(&[0])?;
+    writer.write_all(&(value.len() as i32).to_le_bytes())?;
+    writer.write_all(value)
+}
+
+/**
+ * Writes an uncompressed scanline OpenEXR file. All layers must be the same size, and every layer
+ * contributes an R, G and B channel (named "layer.R" etc. for anything but the beauty pass).
+ */
+pub fn write_exr<W: Write>(mut writer: W, layers: &[ExrLayer], precision: ExrPrecision) -> io::Result<()> {
+    let (width, height) = match layers.first() {
+        Some(layer) => (layer.framebuffer.width(), layer.framebuffer.height()),
+        None => return Err(io::Error::new(io::ErrorKind::InvalidInput, "EXR needs at least one layer")),
+    };
+    if layers.iter().any(|l| l.framebuffer.width() != width || l.framebuffer.height() != height) {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "EXR layers differ in size"));
+    }
+
+    // EXR readers expect the channel list sorted by name, which is also the order of the pixel data
+    let mut channels: Vec<(String, &FrameBuffer, usize)> = layers
+        .iter()
+        .flat_map(|layer| {
+            ["R", "G", "B"]
+                .iter()
+                .enumerate()
+                .map(move |(component, channel)| (layer.channel_name(channel), layer.framebuffer, component))
+        })
+        .collect();
+    channels.sort_by(|a, b| a.0.cmp(&b.0));
+
+    let (pixel_type, bytes_per_value): (i32, usize) = match precision {
+        ExrPrecision::Half => (1, 2),
+        ExrPrecision::Float => (2, 4),
+    };
+
+    let mut header = vec![];
+    // magic number, then version 2 for a single part scanline file
+    header.extend_from_slice(&[0x76, 0x2f, 0x31, 0x01, 2, 0, 0, 0]);
+
+    let mut channel_list = vec![];
+    for (name, _, _) in &channels {
+        channel_list.extend_from_slice(name.as_bytes());
+        channel_list.push(0);
+        channel_list.extend_from_slice(&pixel_type.to_le_bytes());
+        // pLinear and three reserved bytes, then x and y sampling
+        channel_list.extend_from_slice(&[0, 0, 0, 0]);
+        channel_list.extend_from_slice(&1i32.to_le_bytes());
+        channel_list.extend_from_slice(&1i32.to_le_bytes());
+    }
+    channel_list.push(0);
+    write_exr_attribute(&mut header, "channels", "chlist", &channel_list)?;
+    write_exr_attribute(&mut header, "compression", "compression", &[0])?;
+    let window: Vec<u8> = [0, 0, width - 1, height - 1].iter().flat_map(|v| v.to_le_bytes()).collect();
+    write_exr_attribute(&mut header, "dataWindow", "box2i", &window)?;
+    write_exr_attribute(&mut header, "displayWindow", "box2i", &window)?;
+    write_exr_attribute(&mut header, "lineOrder", "lineOrder", &[0])?;
+    write_exr_attribute(&mut header, "pixelAspectRatio", "float", &1.0f32.to_le_bytes())?;
+    write_exr_attribute(&mut header, "screenWindowCenter", "v2f", &[0; 8])?;
+    write_exr_attribute(&mut header, "screenWindowWidth", "float", &1.0f32.to_le_bytes())?;
+    header.push(0);
+
+    // one scanline per chunk, each chunk is its y coordinate, its byte count and then the channel data
+    let line_size = (width as usize) * channels.len() * bytes_per_value;
+    let chunk_size = (8 + line_size) as u64;
+    let first_chunk = (header.len() + 8 * height as usize) as u64;
+    writer.write_all(&header)?;
+    for line in 0..height as u64 {
+        writer.write_all(&(first_chunk + line * chunk_size).to_le_bytes())?;
+    }
+
+    let mut chunk = Vec::with_capacity(line_size);
+    for line in 0..height {
+        chunk.clear();
+        // EXR stores the top row first
+        let y = height - 1 - line;
+        for (_, framebuffer, component) in &channels {
+            for x in 0..width {
+                let value = framebuffer.get(x, y)[*component];
+                match precision {
+                    ExrPrecision::Half => chunk.extend_from_slice(&f32_to_f16_bits(value).to_le_bytes()),
+                    ExrPrecision::Float => chunk.extend_from_slice(&value.to_le_bytes()),
+                }
+            }
+        }
+        writer.write_all(&line.to_le_bytes())?;
+        writer.write_all(&(line_size as i32).to_le_bytes())?;
+        writer.write_all(&chunk)?;
+    }
+    Ok(())
+}
+
+// IEEE 754 half precision, rounding to nearest even
+fn f32_to_f16_bits(value: f32) -> u16 {
+    let bits = value.to_bits();
+    let sign = ((bits >> 16) & 0x8000) as u16;
+    let exponent = ((bits >> 23) & 0xff) as i32;
+    let mantissa = bits & 0x007f_ffff;
+
+    if exponent == 0xff {
+        // infinity stays infinity, NaN stays NaN
+        return sign | 0x7c00 | if mantissa != 0 { 0x0200 } else { 0 };
+    }
+    let half_exponent = exponent - 127 + 15;
+    if half_exponent >= 0x1f {
+        // too large, overflow to infinity
+        return sign | 0x7c00;
+    }
+    if half_exponent <= 0 {
+        // too small for a normal half, so produce a subnormal (or zero)
+        if half_exponent < -10 {
+            return sign;
+        }
+        let mantissa = mantissa | 0x0080_0000;
+        let shift = (14 - half_exponent) as u32;
+        let mut half_mantissa = mantissa >> shift;
+        if rounds_up(mantissa, shift) {
+            half_mantissa += 1;
+        }
+        return sign | half_mantissa as u16;
+    }
+    let mut half = ((half_exponent as u32) << 10) | (mantissa >> 13);
+    if rounds_up(mantissa, 13) {
+        // a carry out of the mantissa correctly bumps the exponent
+        half += 1;
+    }
+    sign | half as u16
+}
+
+// round to nearest when the low `shift` bits of `mantissa` are dropped, ties going to the even result
+fn rounds_up(mantissa: u32, shift: u32) -> bool {
+    let round_bit = 1 << (shift - 1);
+    let sticky_bits = mantissa & (round_bit - 1);
+    let result_is_odd = (mantissa >> shift) & 1 != 0;
+    (mantissa & round_bit) != 0 && (sticky_bits != 0 || result_is_odd)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 2x2 image with a white bottom left pixel and a red top left pixel
+    fn test_framebuffer() -> FrameBuffer {
+        let mut framebuffer = FrameBuffer::new(2, 2);
+        framebuffer.set(0, 0, Color::new(1.0, 1.0, 1.0));
+        framebuffer.set(0, 1, Color::new(1.0, 0.0, 0.0));
+        framebuffer
+    }
+
+    #[test]
+    fn format_is_chosen_by_extension() {
+        assert_eq!(ImageFormat::from_path("out.png"), Some(ImageFormat::Png));
+        assert_eq!(ImageFormat::from_path("dir/out.PPM"), Some(ImageFormat::Ppm));
+        assert_eq!(ImageFormat::from_path("out.jpg"), None);
+        assert_eq!(ImageFormat::from_path("out"), None);
+    }
+
+    #[test]
+    fn aov_files_are_named_after_the_pass() {
+        let path = Path::new("renders/out.png");
+        assert_eq!(aov_path(path, Aov::Albedo), PathBuf::from("renders/out.albedo.png"));
+        assert_eq!(aov_path(Path::new("out.exr"), Aov::ObjectId), PathBuf::from("out.object_id.exr"));
+    }
+
+    #[test]
+    fn frames_are_numbered() {
+        assert_eq!(frame_path(Path::new("renders/frame.png"), 7), PathBuf::from("renders/frame_0007.png"));
+        assert_eq!(frame_path(Path::new("turntable.exr"), 12345), PathBuf::from("turntable_12345.exr"));
+        // AOVs of a frame go next to it
+        let frame = frame_path(Path::new("frame.png"), 1);
+        assert_eq!(aov_path(&frame, Aov::Depth), PathBuf::from("frame_0001.depth.png"));
+    }
+
+    #[test]
+    fn ascii_ppm_starts_with_top_row() {
+        let mut out = vec![];
+        let options = OutputOptions { ppm_encoding: PpmEncoding::Ascii, ..Default::default() };
+        write_image_to(&mut out, &test_framebuffer(), ImageFormat::Ppm, &options).unwrap();
+        let text = String::from_utf8(out).unwrap();
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(lines[..3], ["P3", "2 2", "255"]);
+        assert_eq!(lines[3], "255 0 0");
+        assert_eq!(lines[5], "255 255 255");
+        assert_eq!(lines.len(), 7);
+    }
+
+    #[test]
+    fn binary_ppm_has_three_bytes_per_pixel() {
+        let mut out = vec![];
+        write_image_to(&mut out, &test_framebuffer(), ImageFormat::Ppm, &OutputOptions::default()).unwrap();
+        let header = b"P6\n2 2\n255\n";
+        assert_eq!(&out[..header.len()], header);
+        assert_eq!(&out[header.len()..], [255, 0, 0, 0, 0, 0, 255, 255, 255, 0, 0, 0]);
+    }
+
+    #[test]
+    fn hdr_formats_are_detected() {
+        assert_eq!(ImageFormat::from_path("beauty.exr"), Some(ImageFormat::Exr));
+        assert!(ImageFormat::from_path("beauty.hdr").unwrap().is_high_dynamic_range());
+        assert!(!ImageFormat::from_path("beauty.png").unwrap().is_high_dynamic_range());
+    }
+
+    #[test]
+    fn half_conversion_is_correct() {
+        assert_eq!(f32_to_f16_bits(0.0), 0x0000);
+        assert_eq!(f32_to_f16_bits(1.0), 0x3c00);
+        assert_eq!(f32_to_f16_bits(-2.0), 0xc000);
+        assert_eq!(f32_to_f16_bits(0.5), 0x3800);
+        assert_eq!(f32_to_f16_bits(65504.0), 0x7bff);
+        assert_eq!(f32_to_f16_bits(1.0e6), 0x7c00);
+        // smallest half subnormal
+        assert_eq!(f32_to_f16_bits(5.960_464_5e-8), 0x0001);
+        // exactly halfway between two halves rounds to the even one, anything past halfway rounds up
+        let ulp = f32::powi(2.0, -10);
+        assert_eq!(f32_to_f16_bits(1.0 + 0.5 * ulp), 0x3c00);
+        assert_eq!(f32_to_f16_bits(1.0 + 1.5 * ulp), 0x3c02);
+        assert_eq!(f32_to_f16_bits(1.0 + 0.5 * ulp + f32::powi(2.0, -20)), 0x3c01);
+        assert_eq!(f32_to_f16_bits(-(1.0 + 0.5 * ulp)), 0xbc00);
+        // the same for subnormals, in steps of the smallest one
+        let subnormal = f32::powi(2.0, -24);
+        assert_eq!(f32_to_f16_bits(0.5 * subnormal), 0x0000);
+        assert_eq!(f32_to_f16_bits(1.5 * subnormal), 0x0002);
+        assert_eq!(f32_to_f16_bits(2.5 * subnormal), 0x0002);
+        assert_eq!(f32_to_f16_bits(2.5 * subnormal + f32::powi(2.0, -30)), 0x0003);
+    }
+
+    #[test]
+    fn rgbe_keeps_values_above_one() {
+        assert_eq!(to_rgbe(Color::new(1.0, 1.0, 1.0)), [128, 128, 128, 129]);
+        assert_eq!(to_rgbe(Color::new(4.0, 2.0, 0.0)), [128, 64, 0, 131]);
+        assert_eq!(to_rgbe(Color::zero()), [0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn pfm_stores_unclamped_floats_bottom_row_first() {
+        let mut framebuffer = test_framebuffer();
+        framebuffer.set(1, 0, Color::new(8.0, 0.0, 0.0));
+        let mut out = vec![];
+        write_image_to(&mut out, &framebuffer, ImageFormat::Pfm, &OutputOptions::default()).unwrap();
+        let header = b"PF\n2 2\n-1.0\n";
+        assert_eq!(&out[..header.len()], header);
+        let floats: Vec<f32> = out[header.len()..]
+            .chunks(4)
+            .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+            .collect();
+        assert_eq!(floats.len(), 12);
+        assert_eq!(floats[..6], [1.0, 1.0, 1.0, 8.0, 0.0, 0.0]);
+    }
+
+    #[test]
+    fn exr_layout_matches_channels_and_size() {
+        let framebuffer = test_framebuffer();
+        let layers = [
+            ExrLayer { name: "", framebuffer: &framebuffer },
+            ExrLayer { name: "albedo", framebuffer: &framebuffer },
+        ];
+        let mut out = vec![];
+        write_exr(&mut out, &layers, ExrPrecision::Float).unwrap();
+        assert_eq!(out[..4], [0x76, 0x2f, 0x31, 0x01]);
+
+        let text = String::from_utf8_lossy(&out);
+        let albedo_r = text.find("albedo.R").unwrap();
+        let beauty_b = text.find("B\0").unwrap();
+        assert!(beauty_b < albedo_r, "channels must be sorted by name");
+
+        // 2 scanlines of 6 float channels, each chunk prefixed by its y and size
+        let chunks = 2 * (8 + 2 * 6 * 4);
+        let header_end = out.len() - chunks - 2 * 8;
+        let first_offset = u64::from_le_bytes(out[header_end..header_end + 8].try_into().unwrap());
+        assert_eq!(first_offset as usize, header_end + 16);
+    }
+
+    #[test]
+    fn png_round_trips_at_sixteen_bits() {
+        let mut out = vec![];
+        let options = OutputOptions { bit_depth: BitDepth::Sixteen, ..Default::default() };
+        write_image_to(&mut out, &test_framebuffer(), ImageFormat::Png, &options).unwrap();
+
+        let decoder = png::Decoder::new(&out[..]);
+        let mut reader = decoder.read_info().unwrap();
+        let mut data = vec![0; reader.output_buffer_size()];
+        let info = reader.next_frame(&mut data).unwrap();
+        assert_eq!((info.width, info.height), (2, 2));
+        assert_eq!(info.bit_depth, png::BitDepth::Sixteen);
+        // first pixel is the red top left one
+        assert_eq!(u16::from_be_bytes([data[0], data[1]]), 65535);
+        assert_eq!(u16::from_be_bytes([data[2], data[3]]), 0);
+    }
+}
@@ -0,0 +1,97 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+// snapshot of how far along a render is, handed to the progress callback every time a tile finishes
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RenderProgress {
+    // progressive renders go over every tile once per pass, the tile counts cover all passes
+    pub tiles_done: usize,
+    pub tiles_total: usize,
+    pub passes_done: usize,
+    pub passes_total: usize,
+    pub samples_done: u64,
+    pub samples_total: u64,
+    pub rays_traced: u64,
+    pub elapsed: Duration,
+}
+
+impl RenderProgress {
+    pub fn fraction_done(&self) -> f32 {
+        if self.samples_total == 0 {
+            return 1.0;
+        }
+        self.samples_done as f32 / self.samples_total as f32
+    }
+
+    // linear extrapolation from the samples completed so far
+    pub fn eta(&self) -> Option<Duration> {
+        if self.samples_done == 0 {
+            return None;
+        }
+        let remaining = self.samples_total.saturating_sub(self.samples_done) as f64;
+        Some(self.elapsed.mul_f64(remaining / self.samples_done as f64))
+    }
+
+    pub fn rays_per_second(&self) -> f64 {
+        let seconds = self.elapsed.as_secs_f64();
+        if seconds <= 0.0 {
+            return 0.0;
+        }
+        self.rays_traced as f64 / seconds
+    }
+}
+
+/**
+ * Shared flag used to stop a running render. Clones share the same flag, so one can be handed to the
+ * renderer while another is kept by whatever decides the render should stop (a UI, a job runner, ...).
+ */
+#[derive(Debug, Clone, Default)]
+pub struct CancellationToken {
+    cancelled: Arc<AtomicBool>,
+}
+
+impl CancellationToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cancelling_a_clone_cancels_the_original() {
+        let token = CancellationToken::new();
+        let clone = token.clone();
+        assert!(!token.is_cancelled());
+        clone.cancel();
+        assert!(token.is_cancelled());
+    }
+
+    #[test]
+    fn eta_extrapolates_from_samples_done() {
+        let progress = RenderProgress {
+            tiles_done: 1,
+            tiles_total: 4,
+            passes_done: 0,
+            passes_total: 1,
+            samples_done: 25,
+            samples_total: 100,
+            rays_traced: 50,
+            elapsed: Duration::from_secs(2),
+        };
+        assert_eq!(progress.eta(), Some(Duration::from_secs(6)));
+        assert_eq!(progress.fraction_done(), 0.25);
+        assert_eq!(progress.rays_per_second(), 25.0);
+    }
+}
@@ -1,5 +1,6 @@
 use crate::util::{Point, Vec3};
 
+#[derive(Debug, Clone, Copy)]
 pub struct Ray {
     pub origin: Point,
     pub direction: Vec3,
@@ -1,4 +1,3 @@
-use std::fmt;
 // use std::rc::Rc;
 
 use crate::aabb::AABB;
@@ -6,7 +5,7 @@ use crate::material::{LambertianMaterial, RenderableMaterial};
 use crate::ray::Ray;
 use crate::sphere::Sphere;
 use crate::texture::SolidColor;
-use crate::util::{Color, Point, Vec3, Interval};
+use crate::util::{Point, Vec3, Interval};
 
 use serde::{Deserialize, Serialize};
 
@@ -20,6 +19,10 @@ pub struct HitRecord {
     pub material_ptr: RenderableMaterial,
 
     pub front_face: bool,
+    // where textures are looked up, the point itself unless the object moves its own coordinates around
+    pub texture_point: Point,
+    // index into the world's object list of whatever was hit, filled in by the list/BVH rather than the object itself
+    pub object_id: usize,
 }
 
 impl HitRecord {
@@ -32,7 +35,9 @@ impl HitRecord {
             u: 0.0,
             v: 0.0,
             front_face: true,
-            material_ptr: default_material_ptr
+            material_ptr: default_material_ptr,
+            texture_point: Point::zero(),
+            object_id: 0,
         }
     }
 
@@ -53,6 +58,8 @@ impl HitRecord {
             v,
             front_face,
             material_ptr,
+            texture_point: point,
+            object_id: 0,
         }
     }
 
@@ -89,7 +96,7 @@ impl Copy for HitRecord {}
 
 impl Clone for HitRecord {
     fn clone(&self) -> Self {
-        HitRecord { point: self.point, normal: self.normal, t: self.t, u: self.u, v: self.v, material_ptr: self.material_ptr, front_face: self.front_face }
+        *self
     }
 }
 
@@ -100,7 +107,7 @@ pub trait Renderable {
     }
 }
 
-#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(tag = "type")] // will expect { type: "Sphere", ... } in JSON format
 pub enum Object {
     Sphere(Sphere),
@@ -127,6 +134,28 @@ impl Object {
     pub fn empty() -> Object {
         Object::AABB(AABB::empty())
     }
+
+    pub fn is_static(&self) -> bool {
+        match self {
+            Object::Sphere(s) => s.is_static(),
+            Object::AABB(_) => true
+        }
+    }
+
+    // the object with its bounding box only around where it is between `open` and `close`
+    pub fn for_shutter(&self, open: f32, close: f32) -> Object {
+        match self {
+            Object::Sphere(s) => Object::Sphere(Sphere { bbox: s.bounds_between(open, close), ..s.clone() }),
+            Object::AABB(aabb) => Object::AABB(*aabb)
+        }
+    }
+
+    pub fn material(&self) -> Option<RenderableMaterial> {
+        match self {
+            Object::Sphere(s) => Some(s.material),
+            Object::AABB(_) => None
+        }
+    }
 }
 
 impl PartialEq for Object {
@@ -151,8 +180,14 @@ impl RenderableList {
     }
 
     pub fn add(&mut self, to_render: Object) {
+        self.bbox = AABB::new_from_bbox(self.bbox, to_render.bounding_box());
         self.objects.push(to_render);
-        self.bbox = AABB::new_from_bbox(self.bbox, to_render.bounding_box())
+    }
+}
+
+impl Default for RenderableList {
+    fn default() -> Self {
+        Self::new()
     }
 }
 
@@ -162,13 +197,14 @@ impl Renderable for RenderableList {
         let mut hit_anything: bool = false;
         let mut closest_yet = interval.max;
 
-        for object in &(self.objects) {
+        for (object_id, object) in self.objects.iter().enumerate() {
             let new_interval = Interval {min: interval.min, max: closest_yet};
             let (object_did_hit, hit_record) = object.hit(ray, new_interval);
             if object_did_hit {
                 hit_anything = true;
                 closest_yet = hit_record.t;
                 final_rec = hit_record;
+                final_rec.object_id = object_id;
             }
         }
 
@@ -0,0 +1,1385 @@
+use std::io;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+
+use crate::aabb::{BvhNode, AABB};
+use crate::adaptive::{AdaptiveSampling, PixelEstimate};
+use crate::aov::{Aov, AovAccumulator, PathSample};
+use crate::camera::{Camera, CameraDescription};
+use crate::checkpoint::{scene_hash, Checkpoint, CheckpointOptions, CheckpointSettings};
+use crate::denoise::{denoise, DenoiseFeatures, DenoiseOptions};
+use crate::film::{CropWindow, Film, PixelBounds};
+use crate::filter::Filter;
+use crate::framebuffer::FrameBuffer;
+use crate::material::{Material, RenderableMaterial};
+use crate::progress::{CancellationToken, RenderProgress};
+use crate::ray::Ray;
+use crate::renderable::{HitRecord, Object, Renderable, RenderableList};
+use crate::sampler::{Sampler, SamplerType};
+use crate::scene::Scene;
+use crate::tonemap::luminance;
+use crate::util::{Color, Interval, Vec3};
+
+pub const DEFAULT_BOUNCE_DEPTH: i32 = 50;
+const TILE_SIZE: i32 = 16;
+
+// which structure rays are intersected against
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum AccelStructure {
+    List,
+    Bvh,
+}
+
+// how the color of a camera ray is computed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Integrator {
+    // full path tracing against the skybox
+    PathTracer,
+    // shading normal of the first hit, useful for debugging geometry
+    Normals,
+}
+
+#[derive(Debug, Clone)]
+pub struct RenderOptions {
+    pub threads: usize,
+    pub accel_structure: AccelStructure,
+    pub integrator: Integrator,
+    // overrides the scene's samples_per_pixel when set, anything below 1 counts as 1
+    pub samples_per_pixel: Option<i32>,
+    // overrides the scene's adaptive sampling when set, a samples_per_pixel override alone turns it off
+    pub adaptive_sampling: Option<AdaptiveSampling>,
+    pub max_depth: i32,
+    // where the random numbers for pixel jitter, the lens, ray times and scattering come from
+    pub sampler: SamplerType,
+    // overrides the scene's reconstruction filter when set
+    pub filter: Option<Filter>,
+    // overrides the scene's seed when set
+    pub seed: Option<u64>,
+    // render in passes over the whole image instead of finishing one tile at a time
+    pub progressive: Option<ProgressiveOptions>,
+    // only render part of the image
+    pub crop: Option<CropOptions>,
+    // periodically save what's been rendered so far, so the render can be resumed after a crash
+    pub checkpoint: Option<CheckpointOptions>,
+    // extra passes to fill in next to the beauty pass
+    pub aovs: Vec<Aov>,
+    // denoise the beauty pass once rendering is done, the feature passes it needs are rendered automatically
+    pub denoise: Option<DenoiseOptions>,
+}
+
+impl Default for RenderOptions {
+    fn default() -> Self {
+        Self {
+            threads: thread::available_parallelism().map(|n| n.get()).unwrap_or(1),
+            accel_structure: AccelStructure::Bvh,
+            integrator: Integrator::PathTracer,
+            samples_per_pixel: None,
+            adaptive_sampling: None,
+            max_depth: DEFAULT_BOUNCE_DEPTH,
+            sampler: SamplerType::default(),
+            filter: None,
+            seed: None,
+            progressive: None,
+            aovs: vec![],
+            denoise: None,
+            checkpoint: None,
+            crop: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ProgressiveOptions {
+    // samples every pixel gets per pass
+    pub samples_per_pass: i32,
+    // no new pass is started once the render has been going for this long
+    pub time_limit: Option<Duration>,
+    // by default there's a snapshot after every pass, with this set passes in between are skipped
+    pub snapshot_interval: Option<Duration>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct CropOptions {
+    pub window: CropWindow,
+    // keep the output at the full image size with everything outside the window black, instead of cutting
+    // the window out
+    pub full_frame: bool,
+}
+
+enum World {
+    List(RenderableList),
+    Bvh(BvhNode),
+    // for animations, a BVH over everything that stays put which every frame shares and one over the moving
+    // objects that's rebuilt for each frame (either is None when there's nothing in it)
+    Animated { fixed: Option<BvhNode>, moving: Option<BvhNode> },
+}
+
+impl World {
+    fn new(scene: &Scene, accel_structure: AccelStructure) -> Self {
+        match accel_structure {
+            // a BVH over nothing has no leaves to bottom out on, so fall back to the plain list
+            AccelStructure::Bvh if scene.world.objects.is_empty() => World::List(scene.world.clone()),
+            AccelStructure::Bvh if scene.animation.is_some() => {
+                let mut fixed: Vec<(usize, Object)> =
+                    scene.world.objects.iter().cloned().enumerate().filter(|(_, object)| object.is_static()).collect();
+                World::Animated { fixed: BvhNode::new_from_indexed(&mut fixed), moving: moving_objects(scene) }
+            }
+            AccelStructure::Bvh => World::Bvh(BvhNode::new_from_renderables(&scene.world.objects)),
+            AccelStructure::List => World::List(scene.world.clone()),
+        }
+    }
+}
+
+// a BVH over the objects that move, boxed in only for the part of their motion the camera's shutter sees
+fn moving_objects(scene: &Scene) -> Option<BvhNode> {
+    let shutter = scene.camera.description().shutter;
+    let mut moving: Vec<(usize, Object)> = scene
+        .world
+        .objects
+        .iter()
+        .enumerate()
+        .filter(|(_, object)| !object.is_static())
+        .map(|(object_id, object)| (object_id, object.for_shutter(shutter.open, shutter.close)))
+        .collect();
+    BvhNode::new_from_indexed(&mut moving)
+}
+
+impl Renderable for World {
+    fn hit(&self, ray: &Ray, interval: Interval) -> (bool, HitRecord) {
+        match self {
+            World::List(list) => list.hit(ray, interval),
+            World::Bvh(root) => root.hit(ray, interval),
+            World::Animated { fixed, moving } => {
+                let mut hit = (false, HitRecord::nothing());
+                for root in [fixed, moving].into_iter().flatten() {
+                    let max = if hit.0 { hit.1.t } else { interval.max };
+                    let (did_hit, hit_record) = root.hit(ray, Interval { min: interval.min, max });
+                    if did_hit {
+                        hit = (did_hit, hit_record);
+                    }
+                }
+                hit
+            }
+        }
+    }
+
+    fn bounding_box(&self) -> AABB {
+        match self {
+            World::List(list) => list.bounding_box(),
+            World::Bvh(root) => root.bounding_box(),
+            World::Animated { fixed, moving } => {
+                let mut boxes = [fixed, moving].into_iter().flatten().map(|root| root.bounding_box());
+                let first = boxes.next().unwrap_or_else(AABB::empty);
+                boxes.fold(first, AABB::new_from_bbox)
+            }
+        }
+    }
+}
+
+// what a worker thread (or worker process, see `distributed`) sends back for each tile it picked up
+#[derive(Debug, Serialize, Deserialize)]
+pub(crate) struct TileResult {
+    // position in the list of tiles
+    pub(crate) index: usize,
+    pub(crate) tile: PixelBounds,
+    // beauty pass and filtered AOVs, covering the tile plus however far the filter reaches around it
+    pub(crate) film: Film,
+    // the state of every pixel the tile got to, row by row, which is fewer than all of them if the render
+    // was cancelled part way through
+    pub(crate) estimates: Vec<PixelEstimate>,
+    // `Renderer::pixel_aovs.len()` per pixel
+    pub(crate) accumulators: Vec<AovAccumulator>,
+    pub(crate) samples: u64,
+    // samples no longer needed because pixels converged early
+    pub(crate) samples_saved: u64,
+    pub(crate) rays: u64,
+}
+
+/**
+ * Everything a render has accumulated so far. Progressive passes keep adding to it, and since every
+ * sample's random numbers only depend on the seed, pixel and sample index, it's all that's needed to pick a
+ * render back up where it stopped.
+ */
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct Accumulation {
+    // beauty pass and filtered AOVs over the whole image
+    pub(crate) film: Film,
+    // one per pixel, row by row
+    pub(crate) estimates: Vec<PixelEstimate>,
+    // one per pixel and unfiltered AOV
+    pub(crate) accumulators: Vec<AovAccumulator>,
+    aovs_per_pixel: usize,
+}
+
+impl Accumulation {
+    pub(crate) fn new(image: PixelBounds, filtered_aovs: &[Aov], pixel_aovs: &[Aov]) -> Self {
+        let mut accumulators = Vec::with_capacity(image.area() * pixel_aovs.len());
+        for _ in 0..image.area() {
+            accumulators.extend(pixel_aovs.iter().map(|aov| AovAccumulator::new(*aov)));
+        }
+        Self {
+            film: Film::new(image, 1 + filtered_aovs.len()),
+            estimates: vec![PixelEstimate::new(); image.area()],
+            accumulators,
+            aovs_per_pixel: pixel_aovs.len(),
+        }
+    }
+
+    // whether it's what a render of `image` with these AOVs accumulates, every pixel with every AOV in order
+    pub(crate) fn fits(&self, image: PixelBounds, filtered_aovs: &[Aov], pixel_aovs: &[Aov]) -> bool {
+        self.film.has_shape(image, 1 + filtered_aovs.len())
+            && self.estimates.len() == image.area()
+            && self.aovs_per_pixel == pixel_aovs.len()
+            && self.accumulators.len() == image.area() * pixel_aovs.len()
+            && self
+                .accumulators
+                .chunks_exact(usize::max(pixel_aovs.len(), 1))
+                .all(|pixel| pixel.iter().map(|accumulator| accumulator.aov()).eq(pixel_aovs.iter().copied()))
+    }
+
+    // index of a pixel within `estimates`, or within `accumulators` after multiplying by `aovs_per_pixel`
+    fn pixel_index(&self, x: i32, y: i32) -> usize {
+        let bounds = self.film.bounds();
+        ((y - bounds.y0) * bounds.width() + (x - bounds.x0)) as usize
+    }
+
+    pub(crate) fn merge(&mut self, result: &TileResult) {
+        self.film.merge(&result.film);
+        let aov_count = self.aovs_per_pixel;
+        let tile = result.tile;
+        for (index, estimate) in result.estimates.iter().enumerate() {
+            let x = tile.x0 + (index as i32) % tile.width();
+            let y = tile.y0 + (index as i32) / tile.width();
+            let pixel = self.pixel_index(x, y);
+            self.estimates[pixel] = *estimate;
+            self.accumulators[pixel * aov_count..(pixel + 1) * aov_count]
+                .copy_from_slice(&result.accumulators[index * aov_count..(index + 1) * aov_count]);
+        }
+    }
+}
+
+// when the last checkpoint of a render was written (or tried to be), and every one that failed
+struct CheckpointLog {
+    last_saved: Instant,
+    errors: Vec<io::Error>,
+}
+
+pub struct RenderOutput {
+    pub beauty: FrameBuffer,
+    pub aovs: Vec<(Aov, FrameBuffer)>,
+    // every checkpoint that couldn't be written, the render carries on without them (always empty in snapshots)
+    pub checkpoint_errors: Vec<io::Error>,
+}
+
+impl RenderOutput {
+    pub fn aov(&self, aov: Aov) -> Option<&FrameBuffer> {
+        self.aovs.iter().find(|(kind, _)| *kind == aov).map(|(_, buffer)| buffer)
+    }
+}
+
+// gives every distinct material in the world an id, indexed by object id
+fn material_ids(objects: &[Object]) -> Vec<Option<usize>> {
+    let mut materials: Vec<RenderableMaterial> = vec![];
+    objects
+        .iter()
+        .map(|object| {
+            let material = object.material()?;
+            match materials.iter().position(|m| *m == material) {
+                Some(id) => Some(id),
+                None => {
+                    materials.push(material);
+                    Some(materials.len() - 1)
+                }
+            }
+        })
+        .collect()
+}
+
+/**
+ * A framebuffer the size of `frame` holding the pixels of `window` taken from `source` (which covers
+ * `source_bounds`), every other pixel black.
+ */
+fn copy_window(source: &FrameBuffer, source_bounds: PixelBounds, window: PixelBounds, frame: PixelBounds) -> FrameBuffer {
+    let mut buffer = FrameBuffer::new(frame.width(), frame.height());
+    for y in window.y0..window.y1 {
+        for x in window.x0..window.x1 {
+            buffer.set(x - frame.x0, y - frame.y0, source.get(x - source_bounds.x0, y - source_bounds.y0));
+        }
+    }
+    buffer
+}
+
+// rectangles of pixels rendered as one unit of work each
+pub(crate) fn tiles_for_bounds(bounds: &PixelBounds) -> Vec<PixelBounds> {
+    let mut tiles = vec![];
+    for y0 in (bounds.y0..bounds.y1).step_by(TILE_SIZE as usize) {
+        for x0 in (bounds.x0..bounds.x1).step_by(TILE_SIZE as usize) {
+            tiles.push(PixelBounds {
+                x0,
+                y0,
+                x1: i32::min(x0 + TILE_SIZE, bounds.x1),
+                y1: i32::min(y0 + TILE_SIZE, bounds.y1),
+            });
+        }
+    }
+    tiles
+}
+
+pub struct Renderer {
+    scene: Scene,
+    // the scene's camera before an animation frame's keys are applied to it
+    camera: CameraDescription,
+    options: RenderOptions,
+    world: World,
+    material_ids: Vec<Option<usize>>,
+    // the requested AOVs plus whatever the denoiser needs
+    sampled_aovs: Vec<Aov>,
+    // the sampled lighting AOVs, which go through the reconstruction filter along with the beauty pass
+    filtered_aovs: Vec<Aov>,
+    // every other sampled AOV, data like depth or ids can't be blended between pixels
+    pixel_aovs: Vec<Aov>,
+    // what a resumed render starts from
+    resume_from: Option<Accumulation>,
+}
+
+impl Renderer {
+    pub fn new(scene: Scene, options: RenderOptions) -> Self {
+        let world = World::new(&scene, options.accel_structure);
+        let camera = scene.camera.description().clone();
+        let material_ids = material_ids(&scene.world.objects);
+        let mut sampled_aovs = options.aovs.clone();
+        if options.denoise.is_some() {
+            for feature in [Aov::Albedo, Aov::Normal, Aov::Depth, Aov::Variance] {
+                if !sampled_aovs.contains(&feature) {
+                    sampled_aovs.push(feature);
+                }
+            }
+        }
+        let (filtered_aovs, pixel_aovs) = sampled_aovs.iter().partition(|aov| aov.is_lighting());
+        Self {
+            scene,
+            camera,
+            options,
+            world,
+            material_ids,
+            sampled_aovs,
+            filtered_aovs,
+            pixel_aovs,
+            resume_from: None,
+        }
+    }
+
+    pub fn scene(&self) -> &Scene {
+        &self.scene
+    }
+
+    /**
+     * Moves the camera and every moving object to where they are in `frame` (counting from 1) of the scene's
+     * animation, for the renders after this. With a BVH only the moving objects' one is rebuilt, the one over
+     * everything that stays put is the same for every frame. Does nothing for scenes without an animation.
+     */
+    pub fn set_frame(&mut self, frame: u32) {
+        let Some(animation) = &self.scene.animation else {
+            return;
+        };
+        let description = animation.camera_at(&self.camera, frame);
+        self.scene.camera = Camera::from_description(description, self.scene.aspect_ratio());
+        if let World::Animated { moving, .. } = &mut self.world {
+            *moving = moving_objects(&self.scene);
+        }
+    }
+
+    pub fn options(&self) -> &RenderOptions {
+        &self.options
+    }
+
+    // the requested AOVs plus whatever the denoiser needs
+    pub fn sampled_aovs(&self) -> &[Aov] {
+        &self.sampled_aovs
+    }
+
+    // at least 1, even if the options or an unvalidated scene ask for fewer
+    pub fn samples_per_pixel(&self) -> i32 {
+        i32::max(self.options.samples_per_pixel.unwrap_or(self.scene.samples_per_pixel), 1)
+    }
+
+    pub fn filter(&self) -> Filter {
+        self.options.filter.unwrap_or(self.scene.filter)
+    }
+
+    pub fn seed(&self) -> u64 {
+        self.options.seed.unwrap_or(self.scene.seed)
+    }
+
+    pub fn adaptive_sampling(&self) -> Option<AdaptiveSampling> {
+        match (self.options.adaptive_sampling, self.options.samples_per_pixel) {
+            (Some(adaptive_sampling), _) => Some(adaptive_sampling),
+            (None, Some(_)) => None,
+            (None, None) => self.scene.adaptive_sampling,
+        }
+    }
+
+    // the most samples any one pixel can get
+    pub fn max_samples_per_pixel(&self) -> i32 {
+        match self.adaptive_sampling() {
+            Some(adaptive_sampling) => adaptive_sampling.sample_range().1,
+            None => self.samples_per_pixel(),
+        }
+    }
+
+    // what every sample of this render depends on, a checkpoint can only be resumed with the same settings
+    pub fn checkpoint_settings(&self) -> CheckpointSettings {
+        CheckpointSettings {
+            image_width: self.scene.image_width,
+            image_height: self.scene.image_height,
+            region: self.sample_bounds(),
+            seed: self.seed(),
+            sampler: self.options.sampler,
+            filter: self.filter(),
+            integrator: self.options.integrator,
+            max_depth: self.options.max_depth,
+            filtered_aovs: self.filtered_aovs.clone(),
+            pixel_aovs: self.pixel_aovs.clone(),
+            scene_hash: scene_hash(&self.scene),
+        }
+    }
+
+    /**
+     * Makes the next render carry on from `checkpoint` instead of starting from scratch. Pixels that already
+     * have the target number of samples are left alone, so this finishes an interrupted render, or adds to a
+     * finished one when the target samples per pixel has been raised since.
+     *
+     * Fails with `InvalidInput` for a checkpoint of a different render and with `InvalidData` for one whose
+     * pixels don't add up to what its settings say (a damaged or edited file).
+     */
+    pub fn resume(&mut self, checkpoint: Checkpoint) -> io::Result<()> {
+        if let Some(setting) = self.checkpoint_settings().mismatch(&checkpoint.settings) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("the checkpoint was rendered with a different {}", setting),
+            ));
+        }
+        if !checkpoint.accumulation.fits(self.sample_bounds(), &self.filtered_aovs, &self.pixel_aovs) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "the checkpoint's pixels don't match the image and AOVs it was saved for",
+            ));
+        }
+        self.resume_from = Some(checkpoint.accumulation);
+        Ok(())
+    }
+
+    // whether `resume` was called, the next render carries on from a checkpoint
+    pub fn is_resumed(&self) -> bool {
+        self.resume_from.is_some()
+    }
+
+    pub fn render(&self) -> RenderOutput {
+        self.render_with_progress(|_| {}, &CancellationToken::new())
+    }
+
+    // renders without snapshots, see `render_progressive`
+    pub fn render_with_progress<F>(&self, on_progress: F, cancel: &CancellationToken) -> RenderOutput
+    where
+        F: FnMut(&RenderProgress),
+    {
+        self.render_progressive(on_progress, |_, _| {}, cancel)
+    }
+
+    /**
+     * Renders the scene, splitting the image into tiles that are handed out to `options.threads` worker threads.
+     * Each thread samples every pixel of the tile it picked up, splatting its samples through the
+     * reconstruction filter into a film for the tile, which is sent back to this thread. Once all tiles are
+     * in, their films are merged in tile order (not the order they finished in, so overlapping splats always
+     * add up the same way) and resolved into the beauty framebuffer and one framebuffer per AOV.
+     *
+     * With `options.progressive` set this happens in passes of `samples_per_pass` samples per pixel, adding
+     * onto what the previous passes accumulated, until the target samples per pixel or the time limit is
+     * reached. `on_snapshot` gets the image as it stands after every pass but the last (or after a pass once
+     * `snapshot_interval` has gone by since the last one), the last one is returned. Without it the whole
+     * render is a single pass.
+     *
+     * With `options.checkpoint` set everything accumulated so far is saved between passes, while a pass is
+     * running whenever the checkpoint interval has gone by, and once more at the end. A render set up with
+     * `resume` starts from such a checkpoint.
+     *
+     * `on_progress` is called on this thread each time a tile comes back. Once `cancel` is triggered the
+     * workers stop at the next pixel, and whatever has been rendered up to that point is returned (pixels
+     * that were never reached stay black).
+     *
+     * With adaptive sampling, pixels stop getting samples as soon as they're converged, so
+     * `samples_total` starts out assuming the maximum and shrinks as tiles come back.
+     *
+     * With `options.denoise` set, the beauty pass (and every snapshot) is denoised.
+     *
+     * With `options.crop` set only the pixels in and around the crop window are sampled. Camera rays are
+     * still spread over the whole frame, so those pixels come out the same as in a render of the whole image.
+     */
+    pub fn render_progressive<F, S>(&self, mut on_progress: F, mut on_snapshot: S, cancel: &CancellationToken) -> RenderOutput
+    where
+        F: FnMut(&RenderProgress),
+        S: FnMut(&RenderOutput, &RenderProgress),
+    {
+        let start = Instant::now();
+        let tiles = tiles_for_bounds(&self.sample_bounds());
+        let max_samples = self.max_samples_per_pixel();
+        let samples_per_pass = match &self.options.progressive {
+            Some(progressive) => i32::max(progressive.samples_per_pass, 1),
+            None => i32::max(max_samples, 1),
+        };
+        let passes_total = usize::max(((max_samples + samples_per_pass - 1) / samples_per_pass) as usize, 1);
+
+        let mut accumulation = match &self.resume_from {
+            Some(accumulation) => accumulation.clone(),
+            None => self.new_accumulation(),
+        };
+        // a resumed render skips the passes every pixel that still needs samples is already through
+        let pending_samples = accumulation
+            .estimates
+            .iter()
+            .filter(|estimate| !self.is_converged_early(estimate))
+            .map(|estimate| estimate.count() as i32)
+            .min();
+        let first_pass = match pending_samples {
+            Some(samples) => usize::min((samples / samples_per_pass) as usize, passes_total),
+            None => passes_total,
+        };
+        let samples_done: u64 = accumulation.estimates.iter().map(|estimate| estimate.count() as u64).sum();
+        let samples_total: u64 = accumulation
+            .estimates
+            .iter()
+            .map(|estimate| match self.is_converged_early(estimate) {
+                true => estimate.count() as u64,
+                false => u32::max(estimate.count(), max_samples as u32) as u64,
+            })
+            .sum();
+        let mut progress = RenderProgress {
+            tiles_done: 0,
+            tiles_total: tiles.len() * (passes_total - first_pass),
+            passes_done: first_pass,
+            passes_total,
+            samples_done,
+            samples_total,
+            rays_traced: 0,
+            elapsed: start.elapsed(),
+        };
+        let mut last_snapshot = Instant::now();
+        let mut checkpoints = CheckpointLog { last_saved: Instant::now(), errors: vec![] };
+
+        for pass in first_pass..passes_total {
+            let target = i32::min(max_samples, (pass as i32 + 1) * samples_per_pass);
+            let results = self.render_pass(&tiles, target, &accumulation, cancel, &mut checkpoints, |result| {
+                progress.tiles_done += 1;
+                if progress.tiles_done == (pass + 1 - first_pass) * tiles.len() {
+                    progress.passes_done = pass + 1;
+                }
+                progress.samples_done += result.samples;
+                // pixels that converged early won't need the rest of their samples
+                progress.samples_total -= result.samples_saved;
+                progress.rays_traced += result.rays;
+                progress.elapsed = start.elapsed();
+                on_progress(&progress);
+            });
+            for result in results.iter().flatten() {
+                accumulation.merge(result);
+            }
+
+            let out_of_time = match &self.options.progressive {
+                Some(ProgressiveOptions { time_limit: Some(limit), .. }) => start.elapsed() >= *limit,
+                _ => false,
+            };
+            if pass + 1 == passes_total || out_of_time || cancel.is_cancelled() {
+                break;
+            }
+            let snapshot_due = match &self.options.progressive {
+                Some(ProgressiveOptions { snapshot_interval: Some(interval), .. }) => last_snapshot.elapsed() >= *interval,
+                _ => true,
+            };
+            if snapshot_due {
+                on_snapshot(&self.resolve(&accumulation), &progress);
+                last_snapshot = Instant::now();
+            }
+            if self.is_checkpoint_due(&checkpoints, true) {
+                self.save_checkpoint(&accumulation, &mut checkpoints);
+            }
+        }
+        if self.options.checkpoint.is_some() {
+            self.save_checkpoint(&accumulation, &mut checkpoints);
+        }
+        let mut output = self.resolve(&accumulation);
+        output.checkpoint_errors = checkpoints.errors;
+        output
+    }
+
+    // nothing rendered yet
+    pub(crate) fn new_accumulation(&self) -> Accumulation {
+        Accumulation::new(self.sample_bounds(), &self.filtered_aovs, &self.pixel_aovs)
+    }
+
+    // without an interval there's a checkpoint after every pass, but none while a pass is running
+    fn is_checkpoint_due(&self, checkpoints: &CheckpointLog, between_passes: bool) -> bool {
+        match &self.options.checkpoint {
+            Some(CheckpointOptions { interval: Some(interval), .. }) => checkpoints.last_saved.elapsed() >= *interval,
+            Some(CheckpointOptions { interval: None, .. }) => between_passes,
+            None => false,
+        }
+    }
+
+    // a checkpoint that can't be written shouldn't take the render down with it, the next one may work
+    fn save_checkpoint(&self, accumulation: &Accumulation, checkpoints: &mut CheckpointLog) {
+        let Some(options) = &self.options.checkpoint else {
+            return;
+        };
+        let checkpoint = Checkpoint { settings: self.checkpoint_settings(), accumulation: accumulation.clone() };
+        if let Err(error) = checkpoint.save(&options.path) {
+            checkpoints.errors.push(error);
+        }
+        checkpoints.last_saved = Instant::now();
+    }
+
+    /**
+     * Brings every pixel up to `target` samples (or until it's converged), returning what each tile rendered
+     * by tile index. Tiles are handed out to the worker threads as they free up, `on_tile` is called on this
+     * thread each time one comes back. Once the checkpoint interval has gone by since the last checkpoint, the
+     * tiles that are back so far are saved on top of `accumulation`, so a crash doesn't lose the whole pass.
+     */
+    fn render_pass<F>(
+        &self,
+        tiles: &[PixelBounds],
+        target: i32,
+        accumulation: &Accumulation,
+        cancel: &CancellationToken,
+        checkpoints: &mut CheckpointLog,
+        mut on_tile: F,
+    ) -> Vec<Option<TileResult>>
+    where
+        F: FnMut(&TileResult),
+    {
+        let mut results: Vec<Option<TileResult>> = (0..tiles.len()).map(|_| None).collect();
+        let next_tile = AtomicUsize::new(0);
+        let num_threads = usize::max(self.options.threads, 1);
+
+        thread::scope(|s| {
+            let (tile_transmitter, tile_receiver) = mpsc::channel();
+            for _ in 0..num_threads {
+                let tile_transmitter = tile_transmitter.clone();
+                let next_tile = &next_tile;
+                s.spawn(move || loop {
+                    let index = next_tile.fetch_add(1, Ordering::Relaxed);
+                    if index >= tiles.len() || cancel.is_cancelled() {
+                        break;
+                    }
+                    let result = self.render_tile(index, tiles[index], target, accumulation, cancel);
+                    tile_transmitter.send(result).unwrap();
+                });
+            }
+            // only the worker threads hold transmitters now, so the receiver finishes once they all do
+            drop(tile_transmitter);
+
+            for result in tile_receiver {
+                on_tile(&result);
+                let index = result.index;
+                results[index] = Some(result);
+                if self.is_checkpoint_due(checkpoints, false) {
+                    // merged in tile order, like at the end of the pass
+                    let mut so_far = accumulation.clone();
+                    for result in results.iter().flatten() {
+                        so_far.merge(result);
+                    }
+                    self.save_checkpoint(&so_far, checkpoints);
+                }
+            }
+        });
+        results
+    }
+
+    // the image as accumulated so far
+    pub(crate) fn resolve(&self, accumulation: &Accumulation) -> RenderOutput {
+        let bounds = accumulation.film.bounds();
+        let mut output = RenderOutput {
+            beauty: accumulation.film.resolve(0),
+            aovs: self
+                .sampled_aovs
+                .iter()
+                .map(|aov| match self.filtered_aovs.iter().position(|filtered| filtered == aov) {
+                    Some(layer) => (*aov, accumulation.film.resolve(1 + layer)),
+                    None => {
+                        let offset = self.pixel_aovs.iter().position(|pixel_aov| pixel_aov == aov).unwrap();
+                        let mut buffer = FrameBuffer::new(bounds.width(), bounds.height());
+                        for y in bounds.y0..bounds.y1 {
+                            for x in bounds.x0..bounds.x1 {
+                                let pixel = accumulation.pixel_index(x, y);
+                                let value = accumulation.accumulators[pixel * self.pixel_aovs.len() + offset].value();
+                                buffer.set(x - bounds.x0, y - bounds.y0, value);
+                            }
+                        }
+                        (*aov, buffer)
+                    }
+                })
+                .collect(),
+            checkpoint_errors: vec![],
+        };
+
+        if let Some(denoise_options) = &self.options.denoise {
+            let features = DenoiseFeatures {
+                albedo: output.aov(Aov::Albedo).unwrap(),
+                normal: output.aov(Aov::Normal).unwrap(),
+                depth: output.aov(Aov::Depth).unwrap(),
+                variance: output.aov(Aov::Variance).unwrap(),
+            };
+            output.beauty = denoise(&output.beauty, &features, denoise_options);
+        }
+        // drop the feature passes that were only rendered for the denoiser
+        output.aovs.retain(|(aov, _)| self.options.aovs.contains(aov));
+
+        // cut the crop window out of the pixels rendered around it, onto a black frame if asked for
+        let window = self.crop_window();
+        let frame = match &self.options.crop {
+            Some(CropOptions { full_frame: false, .. }) => window,
+            _ => self.image_bounds(),
+        };
+        if (bounds, window) != (frame, frame) {
+            output.beauty = copy_window(&output.beauty, bounds, window, frame);
+            for (_, buffer) in output.aovs.iter_mut() {
+                *buffer = copy_window(buffer, bounds, window, frame);
+            }
+        }
+        output
+    }
+
+    fn image_bounds(&self) -> PixelBounds {
+        PixelBounds { x0: 0, y0: 0, x1: self.scene.image_width, y1: self.scene.image_height }
+    }
+
+    // the pixels that end up in the output, the whole image unless cropped
+    pub fn crop_window(&self) -> PixelBounds {
+        match &self.options.crop {
+            Some(crop) => crop.window.pixel_bounds(self.scene.image_width, self.scene.image_height),
+            None => self.image_bounds(),
+        }
+    }
+
+    /**
+     * The pixels that get samples: the crop window plus however far the filter reaches around it, so the
+     * window's edge pixels get the same samples from their neighbours as in a render of the whole image.
+     */
+    pub(crate) fn sample_bounds(&self) -> PixelBounds {
+        self.crop_window().expanded(Film::filter_margin(&self.filter()), &self.image_bounds())
+    }
+
+    pub(crate) fn render_tile(
+        &self,
+        index: usize,
+        tile: PixelBounds,
+        target: i32,
+        accumulation: &Accumulation,
+        cancel: &CancellationToken,
+    ) -> TileResult {
+        let width = self.scene.image_width as f32;
+        let height = self.scene.image_height as f32;
+        let filter = self.filter();
+        let max_samples = self.max_samples_per_pixel();
+        let aov_count = self.pixel_aovs.len();
+
+        let film_bounds = tile.expanded(Film::filter_margin(&filter), &accumulation.film.bounds());
+        let mut film = Film::new(film_bounds, 1 + self.filtered_aovs.len());
+        let mut film_values = vec![Color::zero(); 1 + self.filtered_aovs.len()];
+        let mut estimates = Vec::with_capacity(tile.area());
+        let mut accumulators = Vec::with_capacity(tile.area() * aov_count);
+        let mut rays = 0;
+        let mut samples = 0;
+        let mut samples_saved = 0;
+        let mut sampler = self.options.sampler.create(max_samples, self.seed());
+        let radiance_scale = self.scene.camera.radiance_scale();
+        'tile: for j in tile.y0..tile.y1 {
+            for i in tile.x0..tile.x1 {
+                if cancel.is_cancelled() {
+                    break 'tile;
+                }
+                // carry on from wherever the previous passes left this pixel
+                let pixel = accumulation.pixel_index(i, j);
+                let mut estimate = accumulation.estimates[pixel];
+                let mut pixel_accumulators = accumulation.accumulators[pixel * aov_count..(pixel + 1) * aov_count].to_vec();
+                let converged_before = self.is_converged_early(&estimate);
+                while !self.is_pixel_done(&estimate, target) {
+                    sampler.start_pixel_sample((i, j), estimate.count());
+                    // random point for ray to shoot at within this pixel
+                    let (jitter_x, jitter_y) = sampler.get_2d();
+                    let position = ((i as f32) + jitter_x, (j as f32) + jitter_y);
+                    let sample = match self.scene.camera.get_ray(position.0 / width, position.1 / height, &mut sampler) {
+                        Some(r) => self.trace(&r, &mut sampler, &mut rays).exposed(radiance_scale),
+                        // the camera doesn't see anything here, like outside of a fisheye's image circle
+                        None => PathSample::new(),
+                    };
+                    film_values[0] = sample.radiance;
+                    for (value, aov) in film_values[1..].iter_mut().zip(&self.filtered_aovs) {
+                        *value = aov.sample_value(&sample).unwrap_or(Color::zero());
+                    }
+                    film.add_sample(&filter, position, &film_values);
+                    estimate.add(luminance(sample.radiance));
+                    for accumulator in pixel_accumulators.iter_mut() {
+                        accumulator.add(&sample);
+                    }
+                    samples += 1;
+                }
+                if !converged_before && self.is_converged_early(&estimate) {
+                    samples_saved += (max_samples - estimate.count() as i32) as u64;
+                }
+                estimates.push(estimate);
+                accumulators.extend(pixel_accumulators);
+            }
+        }
+        TileResult {
+            index,
+            tile,
+            film,
+            estimates,
+            accumulators,
+            samples,
+            samples_saved,
+            rays,
+        }
+    }
+
+    fn is_pixel_done(&self, estimate: &PixelEstimate, target: i32) -> bool {
+        estimate.count() as i32 >= target || self.is_converged_early(estimate)
+    }
+
+    // adaptive sampling has decided the pixel doesn't need any more samples
+    fn is_converged_early(&self, estimate: &PixelEstimate) -> bool {
+        self.adaptive_sampling().is_some_and(|adaptive_sampling| adaptive_sampling.is_converged(estimate))
+    }
+
+    fn trace(&self, ray: &Ray, sampler: &mut dyn Sampler, rays: &mut u64) -> PathSample {
+        match self.options.integrator {
+            Integrator::PathTracer => self.path_trace(ray, sampler, rays),
+            Integrator::Normals => {
+                *rays += 1;
+                let mut sample = PathSample::new();
+                let (did_hit, hit_rec) = self.world.hit(ray, Interval { min: 0.001, max: f32::INFINITY });
+                if did_hit {
+                    self.record_first_hit(&mut sample, ray, &hit_rec);
+                    sample.radiance = 0.5 * (hit_rec.normal + Color::new(1.0, 1.0, 1.0));
+                    sample.albedo = sample.radiance;
+                }
+                sample
+            }
+        }
+    }
+
+    fn record_first_hit(&self, sample: &mut PathSample, ray: &Ray, hit_rec: &HitRecord) {
+        sample.normal = hit_rec.normal;
+        sample.depth = Some(hit_rec.t * ray.direction.len());
+        sample.object_id = Some(hit_rec.object_id);
+        sample.material_id = self.material_ids.get(hit_rec.object_id).copied().flatten();
+    }
+
+    fn path_trace(&self, camera_ray: &Ray, sampler: &mut dyn Sampler, rays: &mut u64) -> PathSample {
+        let mut sample = PathSample::new();
+        let mut throughput = Color::new(1.0, 1.0, 1.0);
+        let mut ray = *camera_ray;
+        for call_depth in 0..self.options.max_depth {
+            *rays += 1;
+            let (did_hit, hit_rec) = self.world.hit(&ray, Interval { min: 0.001, max: f32::INFINITY });
+            if did_hit {
+                // if we hit something, determine how this ray scatters (if at all)
+                let (did_scatter, scatter_color, scatter_ray) = hit_rec.material_ptr.scatter(&ray, &hit_rec, sampler);
+                if call_depth == 0 {
+                    self.record_first_hit(&mut sample, &ray, &hit_rec);
+                    sample.albedo = scatter_color;
+                }
+                if did_scatter {
+                    throughput = throughput * scatter_color;
+                    ray = scatter_ray;
+                    continue;
+                }
+            } else if call_depth == 0 {
+                sample.albedo = skybox(&ray);
+            }
+            // the path ends at the sky, which is the only thing emitting light
+            let light = throughput * skybox(&ray);
+            match call_depth {
+                0 => sample.emission = light,
+                1 => sample.direct = light,
+                _ => sample.indirect = light,
+            }
+            sample.radiance = light;
+            return sample;
+        }
+        // ran out of bounces without reaching the sky
+        sample
+    }
+}
+
+fn skybox(ray: &Ray) -> Color {
+    let unit_direction: Vec3 = ray.direction.unit_vector();
+    let t: f32 = 0.5 * (unit_direction.y() + 1.0);
+    (Color::new(1.0, 1.0, 1.0) * (1.0 - t)) + (Color::new(0.5, 0.7, 1.0) * t)
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::camera::Camera;
+    use crate::material::LambertianMaterial;
+    use crate::sphere::Sphere;
+    use crate::texture::{RenderableTexture, SolidColor};
+    use crate::tonemap::ToneMapping;
+    use crate::util::Point;
+
+    pub(crate) fn empty_scene(image_width: i32, image_height: i32) -> Scene {
+        let aspect_ratio = image_width as f32 / image_height as f32;
+        Scene {
+            image_width,
+            image_height,
+            samples_per_pixel: 2,
+            camera: Camera::new(
+                Point::new(0.0, 0.0, 0.0),
+                Point::new(0.0, 0.0, -1.0),
+                Vec3::new(0.0, 1.0, 0.0),
+                90.0,
+                aspect_ratio,
+                0.0,
+                1.0,
+            ),
+            world: RenderableList::new(),
+            tone_mapping: ToneMapping::default(),
+            adaptive_sampling: None,
+            seed: 0,
+            filter: Filter::default(),
+            animation: None,
+        }
+    }
+
+    pub(crate) fn sphere_scene() -> Scene {
+        let mut scene = empty_scene(16, 16);
+        let material = RenderableMaterial::Lambertian(LambertianMaterial::new(RenderableTexture::SolidColor(
+            SolidColor::from_values(0.5, 0.5, 0.5),
+        )));
+        scene.world.add(Object::Sphere(Sphere::new(Point::new(0.0, 0.0, -2.0), 1.0, material)));
+        scene
+    }
+
+    #[test]
+    fn tiles_cover_image_exactly_once() {
+        let tiles = tiles_for_bounds(&PixelBounds { x0: 0, y0: 0, x1: 37, y1: 20 });
+        let covered: i32 = tiles.iter().map(|t| (t.x1 - t.x0) * (t.y1 - t.y0)).sum();
+        assert_eq!(covered, 37 * 20);
+        assert!(tiles.iter().all(|t| t.x1 <= 37 && t.y1 <= 20));
+    }
+
+    #[test]
+    fn render_returns_framebuffer_of_scene_size() {
+        let options = RenderOptions { threads: 3, ..Default::default() };
+        let framebuffer = Renderer::new(empty_scene(20, 10), options).render().beauty;
+        assert_eq!(framebuffer.width(), 20);
+        assert_eq!(framebuffer.height(), 10);
+    }
+
+    #[test]
+    fn progress_reports_every_tile_and_sample() {
+        let options = RenderOptions { threads: 2, ..Default::default() };
+        let mut last_progress = None;
+        let mut calls = 0;
+        Renderer::new(empty_scene(40, 20), options).render_with_progress(
+            |progress| {
+                calls += 1;
+                last_progress = Some(*progress);
+            },
+            &CancellationToken::new(),
+        );
+        let last_progress = last_progress.unwrap();
+        assert_eq!(calls, last_progress.tiles_total);
+        assert_eq!(last_progress.tiles_done, last_progress.tiles_total);
+        assert_eq!(last_progress.samples_done, 40 * 20 * 2);
+        assert_eq!(last_progress.samples_done, last_progress.samples_total);
+    }
+
+    #[test]
+    fn cancelled_render_stops_without_rendering() {
+        let options = RenderOptions { threads: 2, ..Default::default() };
+        let cancel = CancellationToken::new();
+        cancel.cancel();
+        let mut calls = 0;
+        let framebuffer = Renderer::new(empty_scene(32, 32), options).render_with_progress(|_| calls += 1, &cancel).beauty;
+        assert_eq!(calls, 0);
+        assert!(framebuffer.pixels().iter().all(|p| *p == Color::zero()));
+    }
+
+    #[test]
+    fn lighting_aovs_add_up_to_beauty() {
+        let options = RenderOptions {
+            threads: 2,
+            aovs: vec![Aov::Emission, Aov::Direct, Aov::Indirect, Aov::ObjectId],
+            ..Default::default()
+        };
+        let output = Renderer::new(sphere_scene(), options).render();
+        let emission = output.aov(Aov::Emission).unwrap();
+        let direct = output.aov(Aov::Direct).unwrap();
+        let indirect = output.aov(Aov::Indirect).unwrap();
+        for y in 0..16 {
+            for x in 0..16 {
+                let sum = emission.get(x, y) + direct.get(x, y) + indirect.get(x, y);
+                assert!((sum - output.beauty.get(x, y)).len() < 1e-4);
+            }
+        }
+        // the sphere sits in the middle of the frame, the corners only see sky
+        let object_id = output.aov(Aov::ObjectId).unwrap();
+        assert_eq!(object_id.get(8, 8), Color::new(1.0, 1.0, 1.0));
+        assert_eq!(object_id.get(0, 0), Color::zero());
+        assert!(output.aov(Aov::Albedo).is_none());
+    }
+
+    #[test]
+    fn denoising_only_returns_requested_aovs() {
+        let options = RenderOptions {
+            threads: 2,
+            aovs: vec![Aov::Normal],
+            denoise: Some(DenoiseOptions::default()),
+            ..Default::default()
+        };
+        let output = Renderer::new(sphere_scene(), options).render();
+        assert_eq!(output.aovs.len(), 1);
+        assert!(output.aov(Aov::Normal).is_some());
+        assert!(output.beauty.pixels().iter().all(|p| p.x().is_finite()));
+    }
+
+    #[test]
+    fn adaptive_sampling_spends_samples_on_noisy_pixels() {
+        let mut scene = sphere_scene();
+        scene.adaptive_sampling = Some(AdaptiveSampling { min_samples: 4, max_samples: 64, threshold: 0.01 });
+        let options = RenderOptions { threads: 2, aovs: vec![Aov::SampleCount], ..Default::default() };
+        let mut last_progress = None;
+        let output = Renderer::new(scene, options)
+            .render_with_progress(|progress| last_progress = Some(*progress), &CancellationToken::new());
+        let counts = output.aov(Aov::SampleCount).unwrap();
+        // the sky is noise free, the diffuse sphere is not
+        assert_eq!(counts.get(0, 0), Color::new(4.0, 4.0, 4.0));
+        assert!(counts.get(8, 8).x() > 4.0);
+        let last_progress = last_progress.unwrap();
+        assert_eq!(last_progress.samples_done, last_progress.samples_total);
+        assert_eq!(last_progress.samples_done, counts.pixels().iter().map(|c| c.x() as u64).sum::<u64>());
+    }
+
+    #[test]
+    fn renders_are_reproducible_across_thread_counts() {
+        for sampler in [SamplerType::Independent, SamplerType::Sobol] {
+            let render = |threads: usize, seed: u64| {
+                let options = RenderOptions { threads, sampler, seed: Some(seed), ..Default::default() };
+                Renderer::new(sphere_scene(), options).render().beauty
+            };
+            assert_eq!(render(1, 3), render(4, 3));
+            assert_ne!(render(1, 3), render(1, 4));
+        }
+    }
+
+    #[test]
+    fn animated_frames_render_the_same_with_either_accel_structure() {
+        use crate::animation::{Animation, CameraKeyframe, Interpolation};
+        use crate::motion::{Keyframe, Motion, Rotation};
+
+        let mut scene = sphere_scene();
+        let material = RenderableMaterial::Lambertian(LambertianMaterial::new(RenderableTexture::SolidColor(
+            SolidColor::from_values(0.8, 0.2, 0.2),
+        )));
+        let key = |time: f32, x: f32| Keyframe { time, translation: Vec3::new(x, 0.0, 0.0), rotation: Rotation::default() };
+        let motion = Motion { keys: vec![key(0.0, -3.0), key(8.0, 3.0)] };
+        scene.world.add(Object::Sphere(Sphere::new_keyframed(Point::new(0.0, 0.5, -1.5), 0.3, material, motion)));
+        let camera_key = |frame: f32, y: f32| CameraKeyframe {
+            frame,
+            look_from: Some(Point::new(0.0, y, 0.0)),
+            look_at: None,
+            vfov: None,
+            focus_distance: None,
+        };
+        scene.animation = Some(Animation {
+            frames: 8,
+            frame_time: 1.0,
+            interpolation: Interpolation::Linear,
+            camera_keys: vec![camera_key(1.0, 0.0), camera_key(8.0, 0.7)],
+        });
+        let renderer = |accel_structure: AccelStructure| {
+            let options = RenderOptions { threads: 2, accel_structure, seed: Some(5), ..Default::default() };
+            Renderer::new(scene.clone(), options)
+        };
+        // the BVH over the static sphere is kept from frame 1 to frame 4, only the keyframed one's is rebuilt
+        let mut bvh = renderer(AccelStructure::Bvh);
+        bvh.set_frame(1);
+        let first = bvh.render().beauty;
+        bvh.set_frame(4);
+        let fourth = bvh.render().beauty;
+        let mut list = renderer(AccelStructure::List);
+        list.set_frame(4);
+        assert_eq!(fourth, list.render().beauty);
+        assert_ne!(first, fourth);
+    }
+
+    #[test]
+    fn filtered_renders_keep_lighting_aovs_consistent() {
+        let render = |threads: usize| {
+            let options = RenderOptions {
+                threads,
+                filter: Some(Filter::Mitchell { radius: 2.0, b: 1.0 / 3.0, c: 1.0 / 3.0 }),
+                aovs: vec![Aov::Emission, Aov::Direct, Aov::Indirect],
+                ..Default::default()
+            };
+            // several tiles across
+            let mut scene = sphere_scene();
+            scene.image_width = 40;
+            scene.image_height = 40;
+            Renderer::new(scene, options).render()
+        };
+        let output = render(3);
+        for y in 0..40 {
+            for x in 0..40 {
+                let sum = output.aovs.iter().fold(Color::zero(), |sum, (_, buffer)| sum + buffer.get(x, y));
+                assert!((sum - output.beauty.get(x, y)).len() < 1e-4);
+            }
+        }
+        // tiles overlap where the filter reaches across their edges, that mustn't depend on which finished first
+        assert_eq!(output.beauty, render(1).beauty);
+    }
+
+    #[test]
+    fn progressive_passes_add_up_to_a_full_render() {
+        let mut scene = sphere_scene();
+        scene.samples_per_pixel = 5;
+        let options = RenderOptions {
+            threads: 2,
+            aovs: vec![Aov::Depth],
+            progressive: Some(ProgressiveOptions { samples_per_pass: 2, time_limit: None, snapshot_interval: None }),
+            ..Default::default()
+        };
+        let mut snapshots = vec![];
+        let mut last_progress = None;
+        let output = Renderer::new(scene, options.clone()).render_progressive(
+            |progress| last_progress = Some(*progress),
+            |snapshot, progress| snapshots.push((snapshot.beauty.clone(), progress.passes_done)),
+            &CancellationToken::new(),
+        );
+        // passes of 2, 2 and 1 samples, the last one is returned instead of snapshotted
+        assert_eq!(snapshots.len(), 2);
+        assert_eq!(snapshots[1].1, 2);
+        assert_ne!(snapshots[0].0, output.beauty);
+        let last_progress = last_progress.unwrap();
+        assert_eq!(last_progress.passes_done, 3);
+        assert_eq!(last_progress.tiles_done, last_progress.tiles_total);
+        assert_eq!(last_progress.samples_done, 16 * 16 * 5);
+
+        let mut scene = sphere_scene();
+        scene.samples_per_pixel = 5;
+        let single_pass = Renderer::new(scene, RenderOptions { progressive: None, ..options }).render();
+        // the same samples, only summed up in a different order
+        for (a, b) in output.beauty.pixels().iter().zip(single_pass.beauty.pixels()) {
+            assert!((*a - *b).len() < 1e-5);
+        }
+        assert_eq!(output.aov(Aov::Depth), single_pass.aov(Aov::Depth));
+    }
+
+    #[test]
+    fn progressive_render_stops_at_time_limit() {
+        let mut scene = sphere_scene();
+        scene.adaptive_sampling = Some(AdaptiveSampling { min_samples: 2, max_samples: 64, threshold: 0.01 });
+        let options = RenderOptions {
+            threads: 2,
+            progressive: Some(ProgressiveOptions {
+                samples_per_pass: 4,
+                time_limit: Some(Duration::ZERO),
+                snapshot_interval: None,
+            }),
+            ..Default::default()
+        };
+        let mut last_progress = None;
+        let mut snapshots = 0;
+        Renderer::new(scene, options).render_progressive(
+            |progress| last_progress = Some(*progress),
+            |_, _| snapshots += 1,
+            &CancellationToken::new(),
+        );
+        let last_progress = last_progress.unwrap();
+        assert_eq!(snapshots, 0);
+        assert_eq!((last_progress.passes_done, last_progress.passes_total), (1, 16));
+        assert!(last_progress.samples_done <= 16 * 16 * 4);
+    }
+
+    #[test]
+    fn sample_counts_below_one_render_one_sample() {
+        for samples_per_pixel in [0, -3] {
+            let options = RenderOptions { threads: 1, samples_per_pixel: Some(samples_per_pixel), ..Default::default() };
+            let mut samples_done = 0;
+            let output = Renderer::new(sphere_scene(), options)
+                .render_with_progress(|progress| samples_done = progress.samples_done, &CancellationToken::new());
+            assert_eq!(samples_done, 16 * 16);
+            assert!(output.beauty.pixels().iter().all(|p| *p != Color::zero()));
+        }
+    }
+
+    #[test]
+    fn resumed_render_continues_where_checkpoint_left_off() {
+        let path = std::env::temp_dir().join(format!("raytracer-resume-{}.json", std::process::id()));
+        let options = RenderOptions {
+            threads: 2,
+            aovs: vec![Aov::Depth, Aov::SampleCount],
+            checkpoint: Some(CheckpointOptions { path: path.clone(), interval: None }),
+            ..Default::default()
+        };
+        let mut scene = sphere_scene();
+        scene.samples_per_pixel = 3;
+        Renderer::new(scene, options.clone()).render();
+
+        // raising the target of a finished render only takes the samples that are missing
+        let checkpoint = Checkpoint::load(&path).unwrap();
+        assert_eq!(checkpoint.samples_per_pixel(), 3);
+        let mut scene = sphere_scene();
+        scene.samples_per_pixel = 5;
+        let mut resumed = Renderer::new(scene, RenderOptions { checkpoint: None, ..options.clone() });
+        resumed.resume(checkpoint).unwrap();
+        let mut last_progress = None;
+        let output = resumed.render_with_progress(|progress| last_progress = Some(*progress), &CancellationToken::new());
+        let last_progress = last_progress.unwrap();
+        assert_eq!(last_progress.samples_done, 16 * 16 * 5);
+        std::fs::remove_file(&path).unwrap();
+
+        let mut scene = sphere_scene();
+        scene.samples_per_pixel = 5;
+        let direct = Renderer::new(scene, RenderOptions { checkpoint: None, ..options }).render();
+        for (a, b) in output.beauty.pixels().iter().zip(direct.beauty.pixels()) {
+            assert!((*a - *b).len() < 1e-5);
+        }
+        assert_eq!(output.aov(Aov::Depth), direct.aov(Aov::Depth));
+        assert_eq!(output.aov(Aov::SampleCount).unwrap().get(3, 3), Color::new(5.0, 5.0, 5.0));
+    }
+
+    #[test]
+    fn crashed_render_resumes_from_checkpoint_saved_mid_pass() {
+        let path = std::env::temp_dir().join(format!("raytracer-mid-pass-{}.json", std::process::id()));
+        let crashed_path = std::env::temp_dir().join(format!("raytracer-mid-pass-crashed-{}.json", std::process::id()));
+        // three tiles by two, a single pass with a checkpoint every time a tile comes back
+        let mut scene = empty_scene(48, 32);
+        let material = RenderableMaterial::Lambertian(LambertianMaterial::new(RenderableTexture::SolidColor(
+            SolidColor::from_values(0.5, 0.5, 0.5),
+        )));
+        scene.world.add(Object::Sphere(Sphere::new(Point::new(0.0, 0.0, -2.0), 1.0, material)));
+        let options = RenderOptions {
+            threads: 1,
+            aovs: vec![Aov::Depth],
+            checkpoint: Some(CheckpointOptions { path: path.clone(), interval: Some(Duration::ZERO) }),
+            ..Default::default()
+        };
+        // when the third tile comes back the file has the first two, keep that as what a crash would leave behind
+        let cancel = CancellationToken::new();
+        Renderer::new(scene.clone(), options.clone()).render_with_progress(
+            |progress| {
+                if progress.tiles_done == 3 {
+                    std::fs::copy(&path, &crashed_path).unwrap();
+                    cancel.cancel();
+                }
+            },
+            &cancel,
+        );
+        std::fs::remove_file(&path).unwrap();
+
+        let checkpoint = Checkpoint::load(&crashed_path).unwrap();
+        std::fs::remove_file(&crashed_path).unwrap();
+        let counts: Vec<u32> = checkpoint.accumulation.estimates.iter().map(|estimate| estimate.count()).collect();
+        assert_eq!(counts.iter().filter(|count| **count == 2).count(), 2 * 16 * 16);
+        assert_eq!(counts.iter().filter(|count| **count == 0).count(), 4 * 16 * 16);
+
+        let mut resumed = Renderer::new(scene.clone(), RenderOptions { checkpoint: None, ..options.clone() });
+        resumed.resume(checkpoint).unwrap();
+        let mut samples_done = 0;
+        let output = resumed.render_with_progress(|progress| samples_done = progress.samples_done, &CancellationToken::new());
+        assert_eq!(samples_done, 48 * 32 * 2);
+        let direct = Renderer::new(scene, RenderOptions { checkpoint: None, ..options }).render();
+        for (a, b) in output.beauty.pixels().iter().zip(direct.beauty.pixels()) {
+            assert!((*a - *b).len() < 1e-5);
+        }
+        assert_eq!(output.aov(Aov::Depth), direct.aov(Aov::Depth));
+    }
+
+    #[test]
+    fn resuming_a_damaged_checkpoint_fails() {
+        let options = RenderOptions { threads: 1, aovs: vec![Aov::Depth], ..Default::default() };
+        let mut renderer = Renderer::new(sphere_scene(), options);
+        let bounds = renderer.sample_bounds();
+        let settings = renderer.checkpoint_settings();
+        // a row short, and the right size without the depth pass
+        let short = PixelBounds { y1: bounds.y1 - 1, ..bounds };
+        for accumulation in [Accumulation::new(short, &[], &[Aov::Depth]), Accumulation::new(bounds, &[], &[])] {
+            let checkpoint = Checkpoint { settings: settings.clone(), accumulation };
+            assert_eq!(renderer.resume(checkpoint).unwrap_err().kind(), io::ErrorKind::InvalidData);
+        }
+        let checkpoint = Checkpoint { settings, accumulation: Accumulation::new(bounds, &[], &[Aov::Depth]) };
+        renderer.resume(checkpoint).unwrap();
+    }
+
+    #[test]
+    fn unwritable_checkpoints_are_reported() {
+        let path = std::env::temp_dir().join(format!("raytracer-missing-{}", std::process::id())).join("checkpoint.json");
+        let checkpoint = Some(CheckpointOptions { path, interval: None });
+        let output = Renderer::new(sphere_scene(), RenderOptions { threads: 1, checkpoint, ..Default::default() }).render();
+        assert_eq!(output.checkpoint_errors.len(), 1);
+        assert_eq!(output.checkpoint_errors[0].kind(), io::ErrorKind::NotFound);
+    }
+
+    #[test]
+    fn resuming_with_different_settings_fails() {
+        let renderer = Renderer::new(sphere_scene(), RenderOptions { threads: 1, ..Default::default() });
+        let checkpoint = Checkpoint {
+            settings: renderer.checkpoint_settings(),
+            accumulation: Accumulation::new(renderer.image_bounds(), &[], &[]),
+        };
+        let mut reseeded = Renderer::new(sphere_scene(), RenderOptions { threads: 1, seed: Some(7), ..Default::default() });
+        let error = reseeded.resume(checkpoint).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+        assert!(error.to_string().contains("seed"));
+    }
+
+    #[test]
+    fn crop_window_matches_full_frame() {
+        let options = RenderOptions {
+            threads: 2,
+            filter: Some(Filter::Tent { radius: 1.5 }),
+            aovs: vec![Aov::Depth],
+            ..Default::default()
+        };
+        let full = Renderer::new(sphere_scene(), options.clone()).render();
+        // rows 2 to 8 from the top are rows 8 to 14 from the bottom
+        let window = CropWindow::Pixels { x0: 5, y0: 2, x1: 12, y1: 8 };
+        let crop = |full_frame: bool| {
+            let crop = Some(CropOptions { window, full_frame });
+            Renderer::new(sphere_scene(), RenderOptions { crop, ..options.clone() }).render()
+        };
+
+        let cropped = crop(false);
+        assert_eq!((cropped.beauty.width(), cropped.beauty.height()), (7, 6));
+        for y in 0..6 {
+            for x in 0..7 {
+                assert!((cropped.beauty.get(x, y) - full.beauty.get(x + 5, y + 8)).len() < 1e-5);
+                assert_eq!(cropped.aov(Aov::Depth).unwrap().get(x, y), full.aov(Aov::Depth).unwrap().get(x + 5, y + 8));
+            }
+        }
+
+        let framed = crop(true);
+        assert_eq!((framed.beauty.width(), framed.beauty.height()), (16, 16));
+        assert!((framed.beauty.get(5, 8) - full.beauty.get(5, 8)).len() < 1e-5);
+        assert_eq!(framed.beauty.get(4, 8), Color::zero());
+        assert_eq!(framed.beauty.get(5, 14), Color::zero());
+        assert_eq!(framed.aov(Aov::Depth).unwrap().get(12, 10), Color::zero());
+    }
+
+    #[test]
+    fn empty_scene_renders_skybox() {
+        let options = RenderOptions { threads: 2, accel_structure: AccelStructure::List, ..Default::default() };
+        let framebuffer = Renderer::new(empty_scene(8, 8), options).render().beauty;
+        for pixel in framebuffer.pixels() {
+            // sky blends between white and (0.5, 0.7, 1.0)
+            assert!(pixel.x() >= 0.5 && pixel.x() <= 1.0);
+            assert!(pixel.z() > 0.999 && pixel.z() < 1.001);
+        }
+    }
+}
@@ -0,0 +1,554 @@
+use std::f32::consts::PI;
+
+use serde::{Deserialize, Serialize};
+
+use crate::util::{Point, Vec3};
+
+// largest f32 below 1.0, samples are kept in [0, 1)
+const ONE_MINUS_EPSILON: f32 = 1.0 - f32::EPSILON / 2.0;
+
+// first 64 primes, one Halton base per dimension
+const PRIMES: [u32; 64] = [
+    2, 3, 5, 7, 11, 13, 17, 19, 23, 29, 31, 37, 41, 43, 47, 53, 59, 61, 67, 71, 73, 79, 83, 89, 97, 101, 103,
+    107, 109, 113, 127, 131, 137, 139, 149, 151, 157, 163, 167, 173, 179, 181, 191, 193, 197, 199, 211, 223,
+    227, 229, 233, 239, 241, 251, 257, 263, 269, 271, 277, 281, 283, 293, 307, 311,
+];
+
+/**
+ * Source of the random numbers used to render one camera sample: pixel jitter, lens position, ray time and
+ * every scattering decision along the path.
+ *
+ * Each call to `get_1d`/`get_2d` consumes the next dimension of the current sample, so as long as the
+ * camera, integrator and materials ask for their numbers in the same order, dimension `d` of sample `i`
+ * means the same thing in every pixel, which is what lets the low discrepancy samplers spread samples
+ * out evenly.
+ *
+ * Every number is a pure function of the seed, the pixel, the sample index and the dimension, so a render
+ * comes out the same no matter how many threads it's split over or in which order tiles are picked up.
+ */
+pub trait Sampler {
+    // moves on to sample `sample_index` of pixel (x, y), starting again from the first dimension
+    fn start_pixel_sample(&mut self, pixel: (i32, i32), sample_index: u32);
+    fn get_1d(&mut self) -> f32;
+    fn get_2d(&mut self) -> (f32, f32);
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Default)]
+pub enum SamplerType {
+    // every number is drawn independently
+    #[default]
+    Independent,
+    // each dimension is split into samples_per_pixel strata, with the strata shuffled between dimensions
+    Stratified,
+    // Halton sequence, Owen scrambled per pixel
+    Halton,
+    // Owen scrambled (0, 2) Sobol sequence, with every pair of dimensions shuffled independently
+    Sobol,
+}
+
+impl SamplerType {
+    // `samples_per_pixel` is the most samples any pixel will take, only the stratified sampler needs it
+    pub fn create(&self, samples_per_pixel: i32, seed: u64) -> RenderableSampler {
+        match self {
+            SamplerType::Independent => RenderableSampler::Independent(IndependentSampler::new(seed)),
+            SamplerType::Stratified => RenderableSampler::Stratified(StratifiedSampler::new(samples_per_pixel, seed)),
+            SamplerType::Halton => RenderableSampler::Halton(HaltonSampler::new(seed)),
+            SamplerType::Sobol => RenderableSampler::Sobol(SobolSampler::new(seed)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum RenderableSampler {
+    Independent(IndependentSampler),
+    Stratified(StratifiedSampler),
+    Halton(HaltonSampler),
+    Sobol(SobolSampler),
+}
+
+impl Sampler for RenderableSampler {
+    fn start_pixel_sample(&mut self, pixel: (i32, i32), sample_index: u32) {
+        match self {
+            RenderableSampler::Independent(s) => s.start_pixel_sample(pixel, sample_index),
+            RenderableSampler::Stratified(s) => s.start_pixel_sample(pixel, sample_index),
+            RenderableSampler::Halton(s) => s.start_pixel_sample(pixel, sample_index),
+            RenderableSampler::Sobol(s) => s.start_pixel_sample(pixel, sample_index),
+        }
+    }
+
+    fn get_1d(&mut self) -> f32 {
+        match self {
+            RenderableSampler::Independent(s) => s.get_1d(),
+            RenderableSampler::Stratified(s) => s.get_1d(),
+            RenderableSampler::Halton(s) => s.get_1d(),
+            RenderableSampler::Sobol(s) => s.get_1d(),
+        }
+    }
+
+    fn get_2d(&mut self) -> (f32, f32) {
+        match self {
+            RenderableSampler::Independent(s) => s.get_2d(),
+            RenderableSampler::Stratified(s) => s.get_2d(),
+            RenderableSampler::Halton(s) => s.get_2d(),
+            RenderableSampler::Sobol(s) => s.get_2d(),
+        }
+    }
+}
+
+/**
+ * PCG32 (O'Neill, "PCG: A Family of Simple Fast Space-Efficient Statistically Good Algorithms for Random
+ * Number Generation"). Small enough to reseed for every camera sample, and unlike the generators in `rand`
+ * its output is never going to change between versions.
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Pcg32 {
+    state: u64,
+    increment: u64,
+}
+
+impl Pcg32 {
+    pub fn new(seed: u64, stream: u64) -> Self {
+        let mut rng = Self { state: 0, increment: (stream << 1) | 1 };
+        rng.next_u32();
+        rng.state = rng.state.wrapping_add(seed);
+        rng.next_u32();
+        rng
+    }
+
+    pub fn next_u32(&mut self) -> u32 {
+        let old_state = self.state;
+        self.state = old_state.wrapping_mul(0x5851_f42d_4c95_7f2d).wrapping_add(self.increment);
+        let xor_shifted = (((old_state >> 18) ^ old_state) >> 27) as u32;
+        xor_shifted.rotate_right((old_state >> 59) as u32)
+    }
+
+    // uniform in [0, 1)
+    pub fn next_f32(&mut self) -> f32 {
+        to_unit_float(self.next_u32())
+    }
+}
+
+// where in the image and the sample sequence a sampler currently is
+#[derive(Debug, Clone, Copy)]
+struct SampleState {
+    seed: u64,
+    pixel: (i32, i32),
+    sample_index: u32,
+    dimension: u32,
+    // stream of numbers private to the current sample, for whatever a sampler doesn't derive from a sequence
+    rng: Pcg32,
+}
+
+impl SampleState {
+    fn new(seed: u64) -> Self {
+        Self { seed, pixel: (0, 0), sample_index: 0, dimension: 0, rng: Pcg32::new(seed, 0) }
+    }
+
+    fn start(&mut self, pixel: (i32, i32), sample_index: u32) {
+        self.pixel = pixel;
+        self.sample_index = sample_index;
+        self.dimension = 0;
+        let sample_hash = hash(&[self.seed, pixel.0 as u64, pixel.1 as u64, sample_index as u64]);
+        self.rng = Pcg32::new(sample_hash, 0);
+    }
+
+    // hash of the seed, pixel and dimension about to be used, then moves on to the next dimension
+    fn next_dimension(&mut self) -> u64 {
+        let hash = hash(&[self.seed, self.pixel.0 as u64, self.pixel.1 as u64, self.dimension as u64]);
+        self.dimension += 1;
+        hash
+    }
+
+    fn random(&mut self) -> f32 {
+        self.rng.next_f32()
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct IndependentSampler {
+    state: SampleState,
+}
+
+impl IndependentSampler {
+    pub fn new(seed: u64) -> Self {
+        Self { state: SampleState::new(seed) }
+    }
+}
+
+impl Sampler for IndependentSampler {
+    fn start_pixel_sample(&mut self, pixel: (i32, i32), sample_index: u32) {
+        self.state.start(pixel, sample_index);
+    }
+
+    fn get_1d(&mut self) -> f32 {
+        self.state.random()
+    }
+
+    fn get_2d(&mut self) -> (f32, f32) {
+        (self.state.random(), self.state.random())
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct StratifiedSampler {
+    samples_per_pixel: u32,
+    state: SampleState,
+}
+
+impl StratifiedSampler {
+    pub fn new(samples_per_pixel: i32, seed: u64) -> Self {
+        Self {
+            samples_per_pixel: i32::max(samples_per_pixel, 1) as u32,
+            state: SampleState::new(seed),
+        }
+    }
+}
+
+impl Sampler for StratifiedSampler {
+    fn start_pixel_sample(&mut self, pixel: (i32, i32), sample_index: u32) {
+        self.state.start(pixel, sample_index);
+    }
+
+    fn get_1d(&mut self) -> f32 {
+        let hash = self.state.next_dimension();
+        let n = self.samples_per_pixel;
+        if self.state.sample_index >= n {
+            return self.state.random();
+        }
+        // which stratum a sample lands in is shuffled per pixel and dimension, so dimensions don't correlate
+        let stratum = permutation_element(self.state.sample_index, n, hash as u32);
+        f32::min((stratum as f32 + self.state.random()) / n as f32, ONE_MINUS_EPSILON)
+    }
+
+    fn get_2d(&mut self) -> (f32, f32) {
+        let hash = self.state.next_dimension();
+        let n = self.samples_per_pixel;
+        if self.state.sample_index >= n {
+            return (self.state.random(), self.state.random());
+        }
+        // smallest grid with at least n cells, when n isn't a product of the two a few cells stay empty
+        let x_strata = f32::ceil(f32::sqrt(n as f32)) as u32;
+        let y_strata = n.div_ceil(x_strata);
+        let stratum = permutation_element(self.state.sample_index, x_strata * y_strata, hash as u32);
+        (
+            f32::min(((stratum % x_strata) as f32 + self.state.random()) / x_strata as f32, ONE_MINUS_EPSILON),
+            f32::min(((stratum / x_strata) as f32 + self.state.random()) / y_strata as f32, ONE_MINUS_EPSILON),
+        )
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct HaltonSampler {
+    state: SampleState,
+}
+
+impl HaltonSampler {
+    pub fn new(seed: u64) -> Self {
+        Self { state: SampleState::new(seed) }
+    }
+}
+
+impl Sampler for HaltonSampler {
+    fn start_pixel_sample(&mut self, pixel: (i32, i32), sample_index: u32) {
+        self.state.start(pixel, sample_index);
+    }
+
+    fn get_1d(&mut self) -> f32 {
+        let dimension = self.state.dimension as usize;
+        let hash = self.state.next_dimension();
+        // past the last prime the sequence isn't worth much anyway, deep bounces just get random numbers
+        if dimension >= PRIMES.len() {
+            return self.state.random();
+        }
+        owen_scrambled_radical_inverse(PRIMES[dimension], self.state.sample_index as u64, hash)
+    }
+
+    fn get_2d(&mut self) -> (f32, f32) {
+        (self.get_1d(), self.get_1d())
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct SobolSampler {
+    state: SampleState,
+}
+
+impl SobolSampler {
+    pub fn new(seed: u64) -> Self {
+        Self { state: SampleState::new(seed) }
+    }
+}
+
+impl Sampler for SobolSampler {
+    fn start_pixel_sample(&mut self, pixel: (i32, i32), sample_index: u32) {
+        self.state.start(pixel, sample_index);
+    }
+
+    fn get_1d(&mut self) -> f32 {
+        let hash = self.state.next_dimension();
+        let index = nested_uniform_scramble(self.state.sample_index, hash as u32);
+        to_unit_float(nested_uniform_scramble(index.reverse_bits(), (hash >> 32) as u32))
+    }
+
+    fn get_2d(&mut self) -> (f32, f32) {
+        let hash = self.state.next_dimension();
+        // both halves of the pair share the shuffled index so they stay a (0, 2) sequence together
+        let index = nested_uniform_scramble(self.state.sample_index, hash as u32);
+        let second_seed = mix_bits(hash) as u32;
+        (
+            to_unit_float(nested_uniform_scramble(index.reverse_bits(), (hash >> 32) as u32)),
+            to_unit_float(nested_uniform_scramble(sobol_second_dimension(index), second_seed)),
+        )
+    }
+}
+
+// the top 24 bits of `bits` as a float in [0, 1)
+fn to_unit_float(bits: u32) -> f32 {
+    (bits >> 8) as f32 / (1 << 24) as f32
+}
+
+/**
+ * Mirrors the digits of `index` in the given base around the decimal point, with the digits randomly
+ * permuted along the way. The permutation used for each digit depends on all the digits before it.
+ * Keeps going past the last non zero digit so the trailing zeros get scrambled too, otherwise every
+ * pixel's first sample would be 0.
+ */
+fn owen_scrambled_radical_inverse(base: u32, mut index: u64, hash: u64) -> f32 {
+    let base = base as u64;
+    let inverse_base = 1.0 / base as f64;
+    let mut reversed_digits: u64 = 0;
+    let mut inverse_base_power = 1.0;
+    // enough digits for full f32 precision
+    while inverse_base_power > f32::EPSILON as f64 / 2.0 {
+        let digit = index % base;
+        index /= base;
+        let digit_hash = mix_bits(hash ^ reversed_digits) as u32;
+        let digit = permutation_element(digit as u32, base as u32, digit_hash) as u64;
+        reversed_digits = reversed_digits * base + digit;
+        inverse_base_power *= inverse_base;
+    }
+    f32::min((reversed_digits as f64 * inverse_base_power) as f32, ONE_MINUS_EPSILON)
+}
+
+// second dimension of the Sobol sequence, the first one is just `index.reverse_bits()`
+fn sobol_second_dimension(mut index: u32) -> u32 {
+    let mut direction = 1 << 31;
+    let mut value = 0;
+    while index != 0 {
+        if index & 1 != 0 {
+            value ^= direction;
+        }
+        index >>= 1;
+        direction ^= direction >> 1;
+    }
+    value
+}
+
+// Owen scrambling of a 32 bit fixed point number, using the hash based permutation from Laine and Karras
+fn nested_uniform_scramble(x: u32, seed: u32) -> u32 {
+    let mut x = x.reverse_bits().wrapping_add(seed);
+    x ^= x.wrapping_mul(0x6c50_b47c);
+    x ^= x.wrapping_mul(0xb82f_1e52);
+    x ^= x.wrapping_mul(0xc7af_e638);
+    x ^= x.wrapping_mul(0x8d22_f6e6);
+    x.reverse_bits()
+}
+
+/**
+ * Element `index` of a pseudo random permutation of 0..length chosen by `seed`, without having to build the
+ * permutation (Kensler, "Correlated Multi-Jittered Sampling").
+ */
+fn permutation_element(mut index: u32, length: u32, seed: u32) -> u32 {
+    // mask covering every bit that can be set in a number below length
+    let mut mask = length - 1;
+    mask |= mask >> 1;
+    mask |= mask >> 2;
+    mask |= mask >> 4;
+    mask |= mask >> 8;
+    mask |= mask >> 16;
+    // permutes 0..=mask, and keeps permuting until the result lands inside 0..length
+    loop {
+        index ^= seed;
+        index = index.wrapping_mul(0xe170_893d);
+        index ^= seed >> 16;
+        index ^= (index & mask) >> 4;
+        index ^= seed >> 8;
+        index = index.wrapping_mul(0x0929_eb3f);
+        index ^= seed >> 23;
+        index ^= (index & mask) >> 1;
+        index = index.wrapping_mul(1 | seed >> 27);
+        index = index.wrapping_mul(0x6935_fa69);
+        index ^= (index & mask) >> 11;
+        index = index.wrapping_mul(0x74dc_b303);
+        index ^= (index & mask) >> 2;
+        index = index.wrapping_mul(0x9e50_1cc3);
+        index ^= (index & mask) >> 2;
+        index = index.wrapping_mul(0xc860_a3df);
+        index &= mask;
+        index ^= index >> 5;
+        if index < length {
+            return (index + seed) % length;
+        }
+    }
+}
+
+// the splitmix64 finalizer, turns similar inputs into unrelated outputs
+fn mix_bits(mut v: u64) -> u64 {
+    v ^= v >> 31;
+    v = v.wrapping_mul(0x7fb5_d329_728e_a185);
+    v ^= v >> 27;
+    v = v.wrapping_mul(0x81da_def4_bc2d_d44d);
+    v ^= v >> 33;
+    v
+}
+
+pub fn hash(values: &[u64]) -> u64 {
+    values.iter().fold(0x9e37_79b9_7f4a_7c15, |hash, value| mix_bits(hash ^ mix_bits(*value)))
+}
+
+/*
+ * Warps from the unit square onto other shapes. Unlike the rejection sampling in `Vec3::random_*` these
+ * use exactly the numbers they're given, so stratification carries over to the shape.
+ */
+
+// Shirley-Chiu concentric mapping onto the unit disk in the xy plane
+pub fn sample_unit_disk(u: (f32, f32)) -> Point {
+    let offset_x = 2.0 * u.0 - 1.0;
+    let offset_y = 2.0 * u.1 - 1.0;
+    if offset_x == 0.0 && offset_y == 0.0 {
+        return Point::zero();
+    }
+    let (radius, theta) = if f32::abs(offset_x) > f32::abs(offset_y) {
+        (offset_x, PI / 4.0 * (offset_y / offset_x))
+    } else {
+        (offset_y, PI / 2.0 - PI / 4.0 * (offset_x / offset_y))
+    };
+    Point::new(radius * f32::cos(theta), radius * f32::sin(theta), 0.0)
+}
+
+// uniformly distributed unit vector
+pub fn sample_unit_vector(u: (f32, f32)) -> Vec3 {
+    let z = 1.0 - 2.0 * u.0;
+    let r = f32::sqrt(f32::max(1.0 - z * z, 0.0));
+    let phi = 2.0 * PI * u.1;
+    Vec3::new(r * f32::cos(phi), r * f32::sin(phi), z)
+}
+
+// uniformly distributed point inside the unit sphere, `u` picks the direction and `r` the distance
+pub fn sample_unit_ball(u: (f32, f32), r: f32) -> Point {
+    f32::cbrt(r) * sample_unit_vector(u)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ALL_TYPES: [SamplerType; 4] =
+        [SamplerType::Independent, SamplerType::Stratified, SamplerType::Halton, SamplerType::Sobol];
+
+    #[test]
+    fn samples_stay_in_unit_interval() {
+        for sampler_type in ALL_TYPES {
+            let mut sampler = sampler_type.create(16, 0);
+            for index in 0..32 {
+                sampler.start_pixel_sample((3, 7), index);
+                for _ in 0..100 {
+                    let value = sampler.get_1d();
+                    let (x, y) = sampler.get_2d();
+                    for v in [value, x, y] {
+                        assert!((0.0..1.0).contains(&v), "{:?} gave {}", sampler_type, v);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn samples_depend_only_on_seed_pixel_and_index() {
+        for sampler_type in ALL_TYPES {
+            let draw = |seed: u64, pixel: (i32, i32), index: u32| {
+                let mut sampler = sampler_type.create(16, seed);
+                // whatever the sampler did before shouldn't matter
+                sampler.start_pixel_sample((0, 0), 3);
+                sampler.get_2d();
+                sampler.start_pixel_sample(pixel, index);
+                (0..8).map(|_| sampler.get_1d()).collect::<Vec<f32>>()
+            };
+            assert_eq!(draw(7, (4, 5), 2), draw(7, (4, 5), 2));
+            assert_ne!(draw(7, (4, 5), 2), draw(8, (4, 5), 2));
+            assert_ne!(draw(7, (4, 5), 2), draw(7, (5, 4), 2));
+        }
+    }
+
+    #[test]
+    fn pcg32_matches_reference_output() {
+        // first outputs of the reference implementation's pcg32-demo, seeded with (42, 54)
+        let mut rng = Pcg32::new(42, 54);
+        let expected = [0xa15c_02b7, 0x7b47_f409, 0xba1d_3330, 0x83d2_f293, 0xbfa4_784b, 0xcbed_606e];
+        for value in expected {
+            assert_eq!(rng.next_u32(), value);
+        }
+    }
+
+    #[test]
+    fn stratified_puts_one_sample_in_every_stratum() {
+        let mut sampler = SamplerType::Stratified.create(8, 0);
+        let mut strata = [0; 8];
+        for index in 0..8 {
+            sampler.start_pixel_sample((1, 2), index);
+            sampler.get_2d();
+            strata[(sampler.get_1d() * 8.0) as usize] += 1;
+        }
+        assert_eq!(strata, [1; 8]);
+    }
+
+    #[test]
+    fn sobol_pairs_are_stratified_in_both_dimensions() {
+        let mut sampler = SamplerType::Sobol.create(16, 0);
+        for dimension in 0..4 {
+            let mut cells = [0; 16];
+            for index in 0..16 {
+                sampler.start_pixel_sample((5, 9), index);
+                for _ in 0..dimension {
+                    sampler.get_2d();
+                }
+                let (x, y) = sampler.get_2d();
+                cells[(y * 4.0) as usize * 4 + (x * 4.0) as usize] += 1;
+            }
+            assert_eq!(cells, [1; 16]);
+        }
+    }
+
+    #[test]
+    fn scrambled_radical_inverse_keeps_stratification() {
+        for (base, count) in [(2, 8), (3, 9), (5, 25)] {
+            let mut intervals = vec![0; count];
+            for index in 0..count {
+                let value = owen_scrambled_radical_inverse(base, index as u64, 0x1234_5678);
+                intervals[(value * count as f32) as usize] += 1;
+            }
+            assert_eq!(intervals, vec![1; count]);
+        }
+    }
+
+    #[test]
+    fn permutation_element_is_a_permutation() {
+        for length in [1, 5, 16, 33] {
+            let mut seen = vec![false; length as usize];
+            for index in 0..length {
+                let element = permutation_element(index, length, 0xdead_beef) as usize;
+                assert!(!seen[element]);
+                seen[element] = true;
+            }
+        }
+    }
+
+    #[test]
+    fn warps_land_on_their_shapes() {
+        for (x, y) in [(0.0, 0.0), (0.999, 0.2), (0.5, 0.5), (0.1, 0.9)] {
+            assert!(sample_unit_disk((x, y)).len() <= 1.0 + 1e-6);
+            assert!(f32::abs(sample_unit_vector((x, y)).len() - 1.0) < 1e-5);
+            assert!(sample_unit_ball((x, y), 0.5).len() < 1.0);
+        }
+    }
+}
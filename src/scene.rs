@@ -1,8 +1,10 @@
 use serde::{Deserialize, Serialize};
 
+use std::error::Error;
+use std::fmt;
 use std::fs::File;
-use std::io::BufReader;
-use std::path::Path;
+use std::io::{self, BufReader};
+use std::path::{Path, PathBuf};
 
 use std::fs;
 use std::env;
@@ -10,17 +12,22 @@ use std::env;
 // use std::io::Write;
 // use std::rc::Rc;
 
-use crate::aabb::{AABB, BvhNode};
-use crate::camera::Camera;
+use crate::aabb::AABB;
+use crate::adaptive::AdaptiveSampling;
+use crate::animation::Animation;
+use crate::aperture::with_scene_directory;
+use crate::camera::{Camera, CameraFile};
+use crate::filter::Filter;
 use crate::material::{Dielectric, LambertianMaterial, Metal, RenderableMaterial};
 use crate::renderable::{Object, RenderableList, Renderable};
 use crate::sphere::Sphere;
 use crate::util::{random_between_0_1, random_in_range, Color, Point, Vec3};
 use crate::texture::{CheckerTexture, SolidColor, RenderableTexture};
+use crate::tonemap::ToneMapping;
+use crate::validate::{validate_scene, Issue};
 
 pub struct SceneMetaData {
     pub file_name: String,
-    pub aspect_ratio: f32,
     pub image_width: i32,
     pub image_height: i32,
     pub samples_per_pixel: i32,
@@ -28,12 +35,16 @@ pub struct SceneMetaData {
 
 pub fn save_scene(scene_metadata: SceneMetaData, camera: Camera, world: RenderableList) {
     let scene = Scene {
-        aspect_ratio: scene_metadata.aspect_ratio,
         image_width: scene_metadata.image_width,
         image_height: scene_metadata.image_height,
         samples_per_pixel: scene_metadata.samples_per_pixel,
         camera,
         world,
+        tone_mapping: ToneMapping::default(),
+        adaptive_sampling: None,
+        seed: 0,
+        filter: Filter::default(),
+        animation: None,
     };
     let serialized = serde_json::to_string(&scene).unwrap();
 
@@ -50,14 +61,90 @@ pub fn save_scene(scene_metadata: SceneMetaData, camera: Camera, world: Renderab
     fs::write(scene_metadata.file_name, serialized).expect("Unable to write to file?");
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(from = "SceneFile", into = "SceneFile")]
 pub struct Scene {
-    pub aspect_ratio: f32,
     pub image_width: i32,
     pub image_height: i32,
     pub samples_per_pixel: i32,
     pub camera: Camera,
     pub world: RenderableList,
+    // how the render is brought into display range when written to PNG/PPM
+    pub tone_mapping: ToneMapping,
+    // when set, samples_per_pixel is ignored and every pixel is sampled until it's converged
+    pub adaptive_sampling: Option<AdaptiveSampling>,
+    // renders with the same seed come out exactly the same
+    pub seed: u64,
+    // how samples are blended into pixels, a plain per pixel average by default
+    pub filter: Filter,
+    // renders a sequence of frames instead of a single image when set
+    pub animation: Option<Animation>,
+}
+
+impl Scene {
+    pub fn aspect_ratio(&self) -> f32 {
+        self.image_width as f32 / self.image_height as f32
+    }
+}
+
+/**
+ * `Scene` as it's laid out in JSON. The camera is stored as a description and only turned into a `Camera` here,
+ * once the image size it's for is known. The `aspect_ratio` older files have is left out, it's the image size's.
+ */
+#[derive(Serialize, Deserialize)]
+struct SceneFile {
+    image_width: i32,
+    image_height: i32,
+    samples_per_pixel: i32,
+    camera: CameraFile,
+    world: RenderableList,
+    // older scene files leave out everything from here on
+    #[serde(default)]
+    tone_mapping: ToneMapping,
+    #[serde(default)]
+    adaptive_sampling: Option<AdaptiveSampling>,
+    #[serde(default)]
+    seed: u64,
+    #[serde(default)]
+    filter: Filter,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    animation: Option<Animation>,
+}
+
+impl From<SceneFile> for Scene {
+    fn from(file: SceneFile) -> Self {
+        let aspect_ratio = file.image_width as f32 / file.image_height as f32;
+        Scene {
+            image_width: file.image_width,
+            image_height: file.image_height,
+            samples_per_pixel: file.samples_per_pixel,
+            camera: file.camera.into_camera(aspect_ratio),
+            world: file.world,
+            tone_mapping: file.tone_mapping,
+            adaptive_sampling: file.adaptive_sampling,
+            seed: file.seed,
+            filter: file.filter,
+            animation: file.animation,
+        }
+    }
+}
+
+impl From<Scene> for SceneFile {
+    fn from(scene: Scene) -> Self {
+        let aspect_ratio = scene.aspect_ratio();
+        SceneFile {
+            image_width: scene.image_width,
+            image_height: scene.image_height,
+            samples_per_pixel: scene.samples_per_pixel,
+            camera: scene.camera.to_file(aspect_ratio),
+            world: scene.world,
+            tone_mapping: scene.tone_mapping,
+            adaptive_sampling: scene.adaptive_sampling,
+            seed: scene.seed,
+            filter: scene.filter,
+            animation: scene.animation,
+        }
+    }
 }
 
 pub fn default_scene() -> Scene {
@@ -82,31 +169,90 @@ pub fn default_scene() -> Scene {
     let default_sphere: Sphere = Sphere::new(Point::new(4.0, 1.0, 0.0), 1.0, default_material);
     let default_obj = Object::Sphere(default_sphere);
     let default_world: RenderableList = RenderableList {
+        bbox: AABB::new_from_bbox(AABB::empty(), default_obj.bounding_box()),
         objects: vec![default_obj],
-        bbox: AABB::new_from_bbox(AABB::empty(), default_obj.bounding_box())
     };
 
     let default_scene: Scene = Scene {
-        aspect_ratio: 1.0,
         image_width: 400,
         image_height: 400,
         samples_per_pixel: 100,
         camera: default_camera,
         world: default_world,
+        tone_mapping: ToneMapping::default(),
+        adaptive_sampling: None,
+        seed: 0,
+        filter: Filter::default(),
+        animation: None,
     };
     default_scene
 }
 
-pub fn load_scene<P: AsRef<Path>>(path: P) -> Scene {
-    // Open the file in read-only mode with buffer.
-    let file = File::open(path).unwrap();
-    let reader = BufReader::new(file);
+// why a scene file couldn't be loaded
+#[derive(Debug)]
+pub enum SceneError {
+    // the file couldn't be read at all
+    Io { path: PathBuf, source: io::Error },
+    // not JSON, or JSON that doesn't have the shape of a scene, line and column are 1 based
+    Parse { path: PathBuf, line: usize, column: usize, source: serde_json::Error },
+    // a well formed scene that can't be rendered, only the issues that are errors
+    Invalid { path: PathBuf, problems: Vec<Issue> },
+}
+
+impl fmt::Display for SceneError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SceneError::Io { path, source } => write!(f, "unable to read {}: {}", path.display(), source),
+            SceneError::Parse { path, line, column, source } => {
+                // serde_json puts its own " at line x column y" at the end of the message
+                let message = source.to_string();
+                let message = message.split(" at line ").next().unwrap_or_default();
+                write!(f, "{}:{}:{}: {}", path.display(), line, column, message)
+            }
+            SceneError::Invalid { path, problems } => {
+                write!(f, "{} is not a valid scene:", path.display())?;
+                for problem in problems {
+                    write!(f, "\n  {}", problem)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
 
-    // Read the JSON contents of the file as an instance of `User`.
-    let s = serde_json::from_reader(reader).unwrap_or(default_scene());
+impl Error for SceneError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            SceneError::Io { source, .. } => Some(source),
+            SceneError::Parse { source, .. } => Some(source),
+            SceneError::Invalid { .. } => None,
+        }
+    }
+}
 
-    // Return the `Scene`.
-    s
+// parses a scene file without checking whether the scene makes sense, see `validate_scene` for that. Files the scene
+// refers to (aperture images) are looked for next to it, through an absolute path so workers get the same one
+pub fn load_scene_unchecked<P: AsRef<Path>>(path: P) -> Result<Scene, SceneError> {
+    let path = path.as_ref();
+    let file = File::open(path).map_err(|source| SceneError::Io { path: path.to_path_buf(), source })?;
+    let absolute = std::path::absolute(path).map_err(|source| SceneError::Io { path: path.to_path_buf(), source })?;
+    let directory = absolute.parent().unwrap_or(Path::new(""));
+    with_scene_directory(directory, || serde_json::from_reader(BufReader::new(file))).map_err(|source| match source.classify() {
+        // a read error part way through the file is still an IO problem
+        serde_json::error::Category::Io => SceneError::Io { path: path.to_path_buf(), source: source.into() },
+        _ => SceneError::Parse { path: path.to_path_buf(), line: source.line(), column: source.column(), source },
+    })
+}
+
+// loads a scene that is ready to render, errors make it invalid and the warnings come back with the scene
+pub fn load_scene<P: AsRef<Path>>(path: P) -> Result<(Scene, Vec<Issue>), SceneError> {
+    let path = path.as_ref();
+    let scene = load_scene_unchecked(path)?;
+    let (problems, warnings): (Vec<Issue>, Vec<Issue>) = validate_scene(&scene).into_iter().partition(Issue::is_error);
+    if !problems.is_empty() {
+        return Err(SceneError::Invalid { path: path.to_path_buf(), problems });
+    }
+    Ok((scene, warnings))
 }
 
 pub fn two_spheres() -> RenderableList {
@@ -307,3 +453,93 @@ pub fn random_scene_checker() -> RenderableList {
 
     world
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // writes `contents` to a scene file of its own in the temp directory
+    fn scene_file(name: &str, contents: &str) -> PathBuf {
+        let path = env::temp_dir().join(format!("raytracer-{}-{}.json", name, std::process::id()));
+        fs::write(&path, contents).unwrap();
+        path
+    }
+
+    #[test]
+    fn missing_file_is_an_io_error() {
+        let error = load_scene("/nonexistent/scene.json").unwrap_err();
+        assert!(matches!(error, SceneError::Io { .. }));
+        assert!(error.to_string().starts_with("unable to read /nonexistent/scene.json"));
+    }
+
+    #[test]
+    fn syntax_errors_report_line_and_column() {
+        let path = scene_file("syntax", "{\n  \"aspect_ratio\": 1.5,\n  \"image_width\" 300\n}");
+        let error = load_scene(&path).unwrap_err();
+        fs::remove_file(&path).unwrap();
+        match &error {
+            SceneError::Parse { line, column, .. } => assert_eq!((*line, *column), (3, 17)),
+            _ => panic!("expected a parse error, got {:?}", error),
+        }
+        assert!(error.to_string().ends_with(":3:17: expected `:`"));
+    }
+
+    #[test]
+    fn unrenderable_scenes_are_invalid() {
+        let mut scene = default_scene();
+        scene.image_width = 0;
+        scene.samples_per_pixel = 0;
+        let path = scene_file("invalid", &serde_json::to_string(&scene).unwrap());
+        let error = load_scene(&path).unwrap_err();
+        fs::remove_file(&path).unwrap();
+        match error {
+            SceneError::Invalid { problems, .. } => assert_eq!(problems.len(), 2),
+            _ => panic!("expected the scene to be invalid, got {:?}", error),
+        }
+    }
+
+    #[test]
+    fn saved_scenes_load_back() {
+        let path = scene_file("roundtrip", &serde_json::to_string(&default_scene()).unwrap());
+        let (scene, warnings) = load_scene(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(scene.image_width, 400);
+        assert_eq!(scene.world.objects.len(), 1);
+        assert!(warnings.is_empty());
+    }
+
+    #[test]
+    fn aperture_images_are_found_next_to_the_scene() {
+        let directory = env::temp_dir().join(format!("raytracer-aperture-scene-{}", std::process::id()));
+        fs::create_dir_all(&directory).unwrap();
+        {
+            let file = io::BufWriter::new(File::create(directory.join("star.png")).unwrap());
+            let mut encoder = png::Encoder::new(file, 1, 1);
+            encoder.set_color(png::ColorType::Grayscale);
+            encoder.write_header().unwrap().write_image_data(&[255]).unwrap();
+        }
+        let mut json = serde_json::to_value(default_scene()).unwrap();
+        json["camera"]["aperture_shape"] = serde_json::json!({ "type": "Image", "path": "star.png" });
+        let path = directory.join("scene.json");
+        fs::write(&path, json.to_string()).unwrap();
+        // read from anywhere else, the image is still the one next to the scene
+        let result = load_scene_unchecked(&path);
+        fs::remove_dir_all(&directory).unwrap();
+        let scene = result.unwrap();
+        match &scene.camera.description().aperture_shape {
+            crate::aperture::ApertureShape::Image(image) => assert_eq!(image.path(), directory.join("star.png")),
+            shape => panic!("expected an aperture image, got {:?}", shape),
+        }
+    }
+
+    #[test]
+    fn warnings_come_back_with_the_scene() {
+        let mut scene = default_scene();
+        scene.filter = Filter::Box { radius: 0.25 };
+        let path = scene_file("warnings", &serde_json::to_string(&scene).unwrap());
+        let (_, warnings) = load_scene(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert!(!warnings.is_empty());
+        assert!(warnings.iter().all(|warning| !warning.is_error()));
+    }
+}
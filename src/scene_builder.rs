@@ -2,7 +2,7 @@ use crate::scene::{random_scene, random_scene_checker, two_spheres, save_scene,
 use crate::camera::Camera;
 use crate::util::{Point, Vec3};
 
-pub fn create_checker_spheres_test(destination_file_name: &str) {
+pub fn create_checker_spheres_test(destination: &str) {
     const ASPECT_RATIO: f32 = 4.0 / 3.0;
     const IMAGE_WIDTH: i32 = 400;
     const IMAGE_HEIGHT: i32 = ((IMAGE_WIDTH as f32) / ASPECT_RATIO) as i32;
@@ -21,8 +21,7 @@ pub fn create_checker_spheres_test(destination_file_name: &str) {
     let world = two_spheres();
 
     let scene_metadata = SceneMetaData {
-        file_name: String::from(format!("./scenes/{}", destination_file_name)),
-        aspect_ratio: ASPECT_RATIO,
+        file_name: destination.to_string(),
         image_width: IMAGE_WIDTH,
         image_height: IMAGE_HEIGHT,
         samples_per_pixel: SAMPLES_PER_PIXEL,
@@ -31,7 +30,7 @@ pub fn create_checker_spheres_test(destination_file_name: &str) {
     save_scene(scene_metadata, camera, world)
 }
 
-pub fn create_checker_test(destination_file_name: &str) {
+pub fn create_checker_test(destination: &str) {
     const ASPECT_RATIO: f32 = 3.0 / 2.0;
     const IMAGE_WIDTH: i32 = 300;
     const IMAGE_HEIGHT: i32 = ((IMAGE_WIDTH as f32) / ASPECT_RATIO) as i32;
@@ -59,8 +58,7 @@ pub fn create_checker_test(destination_file_name: &str) {
     );
 
     let scene_metadata = SceneMetaData {
-        file_name: String::from(format!("./scenes/{}", destination_file_name)),
-        aspect_ratio: ASPECT_RATIO,
+        file_name: destination.to_string(),
         image_width: IMAGE_WIDTH,
         image_height: IMAGE_HEIGHT,
         samples_per_pixel: SAMPLES_PER_PIXEL,
@@ -69,7 +67,7 @@ pub fn create_checker_test(destination_file_name: &str) {
     save_scene(scene_metadata, cam, world);
 }
 
-pub fn create_test(destination_file_name: &str) {
+pub fn create_test(destination: &str) {
     const ASPECT_RATIO: f32 = 3.0 / 2.0;
     const IMAGE_WIDTH: i32 = 300;
     const IMAGE_HEIGHT: i32 = ((IMAGE_WIDTH as f32) / ASPECT_RATIO) as i32;
@@ -97,8 +95,7 @@ pub fn create_test(destination_file_name: &str) {
     );
 
     let scene_metadata = SceneMetaData {
-        file_name: String::from(format!("./scenes/{}", destination_file_name)),
-        aspect_ratio: ASPECT_RATIO,
+        file_name: destination.to_string(),
         image_width: IMAGE_WIDTH,
         image_height: IMAGE_HEIGHT,
         samples_per_pixel: SAMPLES_PER_PIXEL,
@@ -108,7 +105,7 @@ pub fn create_test(destination_file_name: &str) {
 }
 
 // TODO: add support for BVH
-pub fn create(destination_file_name: &str) {
+pub fn create(destination: &str) {
     const ASPECT_RATIO: f32 = 3.0 / 2.0;
     const IMAGE_WIDTH: i32 = 300;
     const IMAGE_HEIGHT: i32 = ((IMAGE_WIDTH as f32) / ASPECT_RATIO) as i32;
@@ -136,8 +133,7 @@ pub fn create(destination_file_name: &str) {
     );
 
     let scene_metadata = SceneMetaData {
-        file_name: String::from(format!("./scenes/{}", destination_file_name)),
-        aspect_ratio: ASPECT_RATIO,
+        file_name: destination.to_string(),
         image_width: IMAGE_WIDTH,
         image_height: IMAGE_HEIGHT,
         samples_per_pixel: SAMPLES_PER_PIXEL,
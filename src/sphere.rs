@@ -4,13 +4,13 @@ use serde::{Deserialize, Serialize};
 
 use crate::aabb::AABB;
 use crate::material::RenderableMaterial;
+use crate::motion::{Motion, Transform};
 use crate::ray::Ray;
 use crate::renderable::{HitRecord, Renderable};
 use crate::util::{Point, Vec3, Interval};
-use crate::texture::{RenderableTexture, SolidColor};
 // use std::fmt;
 
-#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Sphere {
     pub center: Point,
     pub r: f32,
@@ -18,6 +18,9 @@ pub struct Sphere {
     pub is_moving: bool,
     pub center_vec: Vec3,
     pub bbox: AABB,
+    // keyframed placement over time, `center` is then in the motion's own coordinates (and is_moving is ignored)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub motion: Option<Motion>,
 }
 
 impl Sphere {
@@ -30,7 +33,8 @@ impl Sphere {
             material,
             is_moving: false,
             center_vec: Vec3::zero(),
-            bbox: AABB::new_from_pts(center - radius_vec, center + radius_vec)
+            bbox: AABB::new_from_pts(center - radius_vec, center + radius_vec),
+            motion: None,
         }
     }
 
@@ -44,12 +48,63 @@ impl Sphere {
             material,
             is_moving: true,
             center_vec: center2 - center,
-            bbox: AABB::new_from_bbox(start_box, end_box)
+            bbox: AABB::new_from_bbox(start_box, end_box),
+            motion: None,
         }
     }
 
+    pub fn new_keyframed(center: Point, radius: f32, material: RenderableMaterial, motion: Motion) -> Sphere {
+        Self {
+            center,
+            r: radius,
+            material,
+            is_moving: false,
+            center_vec: Vec3::zero(),
+            bbox: motion.sphere_bounds(center, radius),
+            motion: Some(motion),
+        }
+    }
+
+    // whether it's in the same place all the time
+    pub fn is_static(&self) -> bool {
+        self.motion.is_none() && !self.is_moving
+    }
+
+    // a box around everywhere the sphere is between `open` and `close`, `bbox` covers all of its motion
+    pub fn bounds_between(&self, open: f32, close: f32) -> AABB {
+        match &self.motion {
+            Some(motion) => motion.sphere_bounds_between(self.center, self.r, open, close),
+            None if self.is_moving => {
+                let radius_vec = Vec3::new(self.r, self.r, self.r);
+                let (start, end) = (self.sphere_center(open), self.sphere_center(close));
+                AABB::new_from_bbox(
+                    AABB::new_from_pts(start - radius_vec, start + radius_vec),
+                    AABB::new_from_pts(end - radius_vec, end + radius_vec),
+                )
+            }
+            None => self.bbox,
+        }
+    }
+
+    // the center moves from `center` at time 0 to `center + center_vec` at time 1 and stays there
     pub fn sphere_center(&self, time: f32) -> Point {
-        self.center + (time * self.center_vec) 
+        self.center + (time.clamp(0.0, 1.0) * self.center_vec)
+    }
+
+    // textures are looked up with the hit point in the sphere's own coordinates, so they move along with it
+    fn hit_record(&self, ray: &Ray, local_ray: &Ray, root: f32, center: Point, transform: Option<Transform>) -> HitRecord {
+        let local_point = local_ray.at(root);
+        let local_normal = (local_point - center) / self.r;
+        let (point, normal) = match transform {
+            Some(transform) => (transform.point(local_point), transform.vector(local_normal)),
+            None => (local_point, local_normal),
+        };
+        // u, v come from the direction on the unit sphere, so they don't depend on where the sphere is or how big it is
+        let (u, v) = Sphere::get_sphere_uv(&local_normal);
+        let mut hit_record = HitRecord::new(point, normal, root, u, v, false, self.material);
+        hit_record.texture_point = local_point;
+        hit_record.set_face_normal(ray, &normal);
+        hit_record
     }
 
     fn get_sphere_uv(p: &Point) -> (f32, f32) {
@@ -110,10 +165,16 @@ impl Renderable for Sphere {
      * pointing directly at the center, and then spinning 180 degrees to point the exact opposite way.
      */
     fn hit(&self, ray: &Ray, interval: Interval) -> (bool, HitRecord) {
-        let center = if self.is_moving { self.sphere_center(ray.time) } else {self.center};
-        let oc = ray.origin - center;
-        let a = ray.direction.len_squared();
-        let half_b = oc.dot(ray.direction);
+        // keyframed spheres are hit in their own coordinates, rigid transforms keep the ray's t the same
+        let transform = self.motion.as_ref().map(|motion| motion.transform_at(ray.time));
+        let local_ray = match &transform {
+            Some(transform) => Ray::new_with_time(transform.inverse_point(ray.origin), transform.inverse_vector(ray.direction), ray.time),
+            None => *ray,
+        };
+        let center = if self.is_moving && self.motion.is_none() { self.sphere_center(ray.time) } else { self.center };
+        let oc = local_ray.origin - center;
+        let a = local_ray.direction.len_squared();
+        let half_b = oc.dot(local_ray.direction);
         let c = oc.len_squared() - (self.r * self.r);
         let discriminant = (half_b * half_b) - (a * c);
         if discriminant < 0.0 {
@@ -130,21 +191,10 @@ impl Renderable for Sphere {
                 // no solution within t bounds
                 return (false, HitRecord::nothing());
             }
-            let hr_point = ray.at(root);
-            let normal = (hr_point - center) / self.r;
-            // TODO: actually set correct u, v values
-            let (u, v) = Sphere::get_sphere_uv(&hr_point);
-            let mut hit_record = HitRecord::new(hr_point, normal, root, u, v, false, self.material);
-            hit_record.set_face_normal(ray, &normal);
-            return (true, hit_record);
+            return (true, self.hit_record(ray, &local_ray, root, center, transform));
         }
 
-        let hr_point = ray.at(root);
-        let normal = (hr_point - center) / self.r;
-        let (u, v) = Sphere::get_sphere_uv(&hr_point);
-        let mut hit_record = HitRecord::new(hr_point, normal, root, u, v, false, self.material);
-        hit_record.set_face_normal(ray, &normal);
-        return (true, hit_record);
+        (true, self.hit_record(ray, &local_ray, root, center, transform))
     }
 
     fn bounding_box(&self) -> AABB {
@@ -154,13 +204,13 @@ impl Renderable for Sphere {
 
 impl PartialEq for Sphere {
     fn eq(&self, other: &Self) -> bool {
-        self.center == other.center && self.is_moving && other.is_moving && self.center_vec == other.center_vec && self.bbox == other.bbox && self.r == other.r && self.material == other.material
+        self.center == other.center && self.is_moving && other.is_moving && self.center_vec == other.center_vec && self.bbox == other.bbox && self.r == other.r && self.material == other.material && self.motion == other.motion
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::{util::Color, material::LambertianMaterial};
+    use crate::{material::LambertianMaterial, motion::{Keyframe, Rotation}, texture::{RenderableTexture, SolidColor}};
 
     use super::*;
 
@@ -168,10 +218,52 @@ mod tests {
     fn sphere_is_hit_when_ray_cast_hitting_directly() {
         let material: RenderableMaterial = RenderableMaterial::Lambertian(LambertianMaterial::new(RenderableTexture::SolidColor(SolidColor::from_values(0.0, 0.0, 0.0))));
         let sphere_a = Sphere::new(Point::new(-5.0, -5.0, 0.0), 2.0, material);
-        let r = Ray::new(Point::new(-5.0, -5.0, -5.0), Point::new(-5.0, -5.0, 0.0));
+        let r = Ray::new(Point::new(-5.0, -5.0, -5.0), Vec3::new(0.0, 0.0, 5.0));
         let (did_hit, _actual_hit_record) = sphere_a.hit(&r, Interval{min: 0.0, max:10.0});
         assert!(did_hit)
     }
+
+    #[test]
+    fn texture_coordinates_follow_the_direction_from_the_center() {
+        let material: RenderableMaterial = RenderableMaterial::Lambertian(LambertianMaterial::new(RenderableTexture::SolidColor(SolidColor::from_values(0.0, 0.0, 0.0))));
+        let sphere = Sphere::new(Point::new(-5.0, -5.0, 0.0), 2.0, material);
+        // hits the side facing -z
+        let (_, side) = sphere.hit(&Ray::new(Point::new(-5.0, -5.0, -5.0), Vec3::new(0.0, 0.0, 1.0)), Interval { min: 0.0, max: 10.0 });
+        assert!((side.u - 0.75).abs() < 1e-5 && (side.v - 0.5).abs() < 1e-5, "u {} v {}", side.u, side.v);
+        // hits the top
+        let (_, top) = sphere.hit(&Ray::new(Point::new(-5.0, 5.0, 0.0), Vec3::new(0.0, -1.0, 0.0)), Interval { min: 0.0, max: 20.0 });
+        assert!((top.v - 1.0).abs() < 1e-5, "v {}", top.v);
+    }
+
+    #[test]
+    fn keyframed_sphere_is_hit_where_its_motion_puts_it() {
+        let material: RenderableMaterial = RenderableMaterial::Lambertian(LambertianMaterial::new(RenderableTexture::SolidColor(SolidColor::from_values(0.0, 0.0, 0.0))));
+        // swings a quarter turn around the y axis, from +x over to -z
+        let motion = Motion {
+            keys: vec![
+                Keyframe { time: 0.0, translation: Vec3::zero(), rotation: Rotation::default() },
+                Keyframe { time: 1.0, translation: Vec3::zero(), rotation: Rotation { axis: Vec3::new(0.0, 1.0, 0.0), degrees: 90.0 } },
+            ],
+        };
+        let sphere = Sphere::new_keyframed(Point::new(5.0, 0.0, 0.0), 1.0, material, motion);
+        let interval = Interval { min: 0.0, max: 100.0 };
+        let down_at = |x: f32, z: f32, time: f32| Ray::new_with_time(Point::new(x, 10.0, z), Vec3::new(0.0, -1.0, 0.0), time);
+
+        let (did_hit, hit_record) = sphere.hit(&down_at(5.0, 0.0, 0.0), interval);
+        assert!(did_hit && (hit_record.point - Point::new(5.0, 1.0, 0.0)).len() < 1e-4);
+        assert!(!sphere.hit(&down_at(5.0, 0.0, 1.0), interval).0);
+
+        let (did_hit, hit_record) = sphere.hit(&down_at(0.0, -5.0, 1.0), interval);
+        assert!(did_hit);
+        assert!((hit_record.point - Point::new(0.0, 1.0, -5.0)).len() < 1e-4);
+        assert!((hit_record.normal - Vec3::new(0.0, 1.0, 0.0)).len() < 1e-4);
+        // textures stay put on the sphere
+        assert!((hit_record.texture_point - Point::new(5.0, 1.0, 0.0)).len() < 1e-4);
+        // halfway, the arc is outside of the straight line between the two ends
+        let half = 5.0 * f32::sqrt(0.5);
+        assert!(sphere.hit(&down_at(half, -half, 0.5), interval).0);
+        assert!(sphere.bbox.x_interval.max >= 6.0 && sphere.bbox.z_interval.min <= -6.0);
+    }
 }
 
 // impl fmt::Display for Sphere {
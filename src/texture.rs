@@ -1,6 +1,6 @@
 use serde::{Deserialize, Serialize};
 
-use crate::util::{random_between_0_1, Color, Point, Vec3};
+use crate::util::{Color, Point};
 use std::fmt::Display;
 
 #[derive(Debug, Serialize, Deserialize, Copy, Clone)]
@@ -60,7 +60,7 @@ impl PartialEq for SolidColor {
 }
 
 impl Texture for SolidColor {
-    fn value(&self, u: f32, v: f32, p: &Point) -> Color {
+    fn value(&self, _u: f32, _v: f32, _p: &Point) -> Color {
         self.color
     }
 }
@@ -80,7 +80,7 @@ impl SolidColor {
 pub struct CheckerTexture {
     pub odd: SolidColor,
     pub even: SolidColor,
-    inv_scale: f32,
+    pub(crate) inv_scale: f32,
 }
 
 impl Display for CheckerTexture {
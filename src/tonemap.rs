@@ -0,0 +1,186 @@
+use serde::{Deserialize, Serialize};
+
+use crate::util::{clamp, Color};
+
+/**
+ * Maps scene referred (unbounded, linear) radiance down into the [0, 1] range a display can show.
+ * Every operator outputs linear values, the sRGB transfer function is applied afterwards.
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, Default)]
+#[serde(tag = "type")] // will expect { type: "Reinhard", ... } in JSON format
+pub enum ToneMapOperator {
+    // values above 1.0 are simply cut off
+    #[default]
+    Clamp,
+    // extended Reinhard on luminance, the white point is the luminance that maps to pure white
+    Reinhard { white_point: f32 },
+    // Stephen Hill's fit of the ACES reference rendering transform + sRGB output transform
+    Aces,
+    // minimal AgX (Benjamin Wrensch's approximation), desaturates highlights instead of skewing their hue
+    Agx,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, Default)]
+#[serde(default)]
+pub struct ToneMapping {
+    pub operator: ToneMapOperator,
+    // exposure compensation in stops, every +1.0 doubles the brightness
+    pub exposure: f32,
+}
+
+impl ToneMapping {
+    // linear radiance in, sRGB encoded display values in [0, 1] out
+    pub fn apply(&self, radiance: Color) -> Color {
+        let exposed = radiance * f32::powf(2.0, self.exposure);
+        let mapped = match self.operator {
+            ToneMapOperator::Clamp => exposed,
+            ToneMapOperator::Reinhard { white_point } => reinhard(exposed, white_point),
+            ToneMapOperator::Aces => aces(exposed),
+            ToneMapOperator::Agx => agx(exposed),
+        };
+        Color::new(
+            srgb_oetf(mapped.x()),
+            srgb_oetf(mapped.y()),
+            srgb_oetf(mapped.z()),
+        )
+    }
+}
+
+// sRGB opto-electronic transfer function (linear -> encoded), replacing the old sqrt approximation
+pub fn srgb_oetf(linear: f32) -> f32 {
+    let linear = clamp(linear, 0.0, 1.0);
+    if linear <= 0.003_130_8 {
+        12.92 * linear
+    } else {
+        1.055 * f32::powf(linear, 1.0 / 2.4) - 0.055
+    }
+}
+
+pub fn luminance(color: Color) -> f32 {
+    0.2126 * color.x() + 0.7152 * color.y() + 0.0722 * color.z()
+}
+
+fn multiply(matrix: &[[f32; 3]; 3], color: Color) -> Color {
+    Color::new(
+        matrix[0][0] * color.x() + matrix[0][1] * color.y() + matrix[0][2] * color.z(),
+        matrix[1][0] * color.x() + matrix[1][1] * color.y() + matrix[1][2] * color.z(),
+        matrix[2][0] * color.x() + matrix[2][1] * color.y() + matrix[2][2] * color.z(),
+    )
+}
+
+fn map_channels(color: Color, f: impl Fn(f32) -> f32) -> Color {
+    Color::new(f(color.x()), f(color.y()), f(color.z()))
+}
+
+fn reinhard(color: Color, white_point: f32) -> Color {
+    let l_in = luminance(color);
+    if l_in <= 0.0 {
+        return Color::zero();
+    }
+    let l_out = l_in * (1.0 + l_in / (white_point * white_point)) / (1.0 + l_in);
+    color * (l_out / l_in)
+}
+
+const ACES_INPUT: [[f32; 3]; 3] = [
+    [0.59719, 0.35458, 0.04823],
+    [0.07600, 0.90834, 0.01566],
+    [0.02840, 0.13383, 0.83777],
+];
+
+const ACES_OUTPUT: [[f32; 3]; 3] = [
+    [1.60475, -0.53108, -0.07367],
+    [-0.10208, 1.10813, -0.00605],
+    [-0.00327, -0.07276, 1.07602],
+];
+
+fn aces(color: Color) -> Color {
+    let v = multiply(&ACES_INPUT, color);
+    let fitted = map_channels(v, |x| (x * (x + 0.024_578_6) - 0.000_090_537) / (x * (0.983_729 * x + 0.432_951) + 0.238_081));
+    map_channels(multiply(&ACES_OUTPUT, fitted), |x| clamp(x, 0.0, 1.0))
+}
+
+const AGX_INSET: [[f32; 3]; 3] = [
+    [0.842_479_06, 0.078_433_6, 0.079_223_745],
+    [0.042_328_242, 0.878_468_6, 0.079_166_13],
+    [0.042_375_655, 0.078_433_6, 0.879_143],
+];
+
+const AGX_OUTSET: [[f32; 3]; 3] = [
+    [1.196_879, -0.098_020_88, -0.099_029_74],
+    [-0.052_896_85, 1.151_903_1, -0.098_961_18],
+    [-0.052_971_635, -0.098_043_45, 1.151_073_7],
+];
+
+const AGX_MIN_EV: f32 = -12.473_93;
+const AGX_MAX_EV: f32 = 4.026_069;
+
+fn agx(color: Color) -> Color {
+    let v = multiply(&AGX_INSET, color);
+    // log2 encoding of the [min, max] EV range into [0, 1]
+    let v = map_channels(v, |x| {
+        clamp((f32::log2(f32::max(x, 1e-10)) - AGX_MIN_EV) / (AGX_MAX_EV - AGX_MIN_EV), 0.0, 1.0)
+    });
+    // polynomial approximation of the AgX sigmoid
+    let v = map_channels(v, |x| {
+        let x2 = x * x;
+        let x4 = x2 * x2;
+        15.5 * x4 * x2 - 40.14 * x4 * x + 31.96 * x4 - 6.868 * x2 * x + 0.4298 * x2 + 0.1191 * x - 0.00232
+    });
+    // back out of the AgX space, the curve above outputs display encoded values with a 2.2 gamma
+    map_channels(multiply(&AGX_OUTSET, v), |x| f32::powf(f32::max(x, 0.0), 2.2))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn approx_eq(a: f32, b: f32) -> bool {
+        f32::abs(a - b) < 1e-3
+    }
+
+    #[test]
+    fn srgb_oetf_matches_reference_values() {
+        assert_eq!(srgb_oetf(0.0), 0.0);
+        assert!(approx_eq(srgb_oetf(1.0), 1.0));
+        assert!(approx_eq(srgb_oetf(0.18), 0.4614));
+        assert!(approx_eq(srgb_oetf(0.002), 0.02584));
+    }
+
+    #[test]
+    fn exposure_doubles_per_stop() {
+        let tone_mapping = ToneMapping { operator: ToneMapOperator::Clamp, exposure: 1.0 };
+        let reference = ToneMapping::default().apply(Color::new(0.2, 0.2, 0.2));
+        assert_eq!(tone_mapping.apply(Color::new(0.1, 0.1, 0.1)), reference);
+    }
+
+    #[test]
+    fn reinhard_maps_white_point_to_white() {
+        let mapped = reinhard(Color::new(4.0, 4.0, 4.0), 4.0);
+        assert!(approx_eq(mapped.x(), 1.0));
+        let darker = reinhard(Color::new(1.0, 1.0, 1.0), 4.0);
+        assert!(darker.x() < 1.0);
+    }
+
+    #[test]
+    fn operators_keep_bright_values_displayable() {
+        for operator in [ToneMapOperator::Aces, ToneMapOperator::Agx, ToneMapOperator::Reinhard { white_point: 100.0 }] {
+            let tone_mapping = ToneMapping { operator, exposure: 0.0 };
+            let bright = tone_mapping.apply(Color::new(50.0, 20.0, 5.0));
+            let dim = tone_mapping.apply(Color::new(0.5, 0.2, 0.05));
+            for channel in 0..3 {
+                assert!((0.0..=1.0).contains(&bright[channel]));
+                assert!(dim[channel] <= bright[channel]);
+            }
+        }
+    }
+
+    #[test]
+    fn tone_mapping_deserializes_from_scene_json() {
+        let tone_mapping: ToneMapping =
+            serde_json::from_str(r#"{ "operator": { "type": "Reinhard", "white_point": 4.0 }, "exposure": -1.5 }"#).unwrap();
+        assert_eq!(tone_mapping.operator, ToneMapOperator::Reinhard { white_point: 4.0 });
+        assert_eq!(tone_mapping.exposure, -1.5);
+        let defaulted: ToneMapping = serde_json::from_str("{}").unwrap();
+        assert_eq!(defaulted, ToneMapping::default());
+    }
+}
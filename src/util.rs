@@ -5,13 +5,12 @@ use std::f32::consts::PI;
 use std::{fmt::Display, ops};
 
 pub fn degrees_to_radians(degrees: f32) -> f32 {
-    return (degrees * PI) / 180.0;
+    (degrees * PI) / 180.0
 }
 
 pub fn random_between_0_1() -> f32 {
     let mut rng = rand::thread_rng();
-    let random_float = rng.gen();
-    random_float
+    rng.gen()
 }
 
 pub fn random_in_range(min: f32, max: f32) -> f32 {
@@ -171,17 +170,21 @@ impl Vec3 {
     pub fn unit_vector(&self) -> Vec3 {
         *self / self.len()
     }
+
+    pub fn is_finite(&self) -> bool {
+        self.vec.iter().all(|c| c.is_finite())
+    }
 }
 
 impl ops::Index<usize> for Vec3 {
     type Output = f32;
     fn index(&self, i: usize) -> &f32 {
-        return self.get(i).unwrap();
+        self.get(i).unwrap()
     }
 }
 impl ops::IndexMut<usize> for Vec3 {
     fn index_mut(&mut self, i: usize) -> &mut f32 {
-        return self.get_mut(i).unwrap();
+        self.get_mut(i).unwrap()
     }
 }
 impl std::cmp::PartialEq for Vec3 {
@@ -306,8 +309,8 @@ impl Interval {
 
 impl PartialEq for Interval {
     fn eq(&self, other: &Self) -> bool {
-        let min_equal = f32::abs(self.min) - f32::abs(other.min) < f32::EPSILON;
-        let max_equal = f32::abs(self.max) - f32::abs(other.max) < f32::EPSILON;
+        let min_equal = f32::abs(self.min - other.min) < f32::EPSILON;
+        let max_equal = f32::abs(self.max - other.max) < f32::EPSILON;
         min_equal && max_equal
     }
 }
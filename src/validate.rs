@@ -0,0 +1,632 @@
+use std::fmt;
+
+use crate::aabb::AABB;
+use crate::adaptive::AdaptiveSampling;
+use crate::animation::Animation;
+use crate::aperture::ApertureShape;
+use crate::camera::{Camera, CameraDescription, Projection, Stereo};
+use crate::filter::Filter;
+use crate::material::RenderableMaterial;
+use crate::motion::Motion;
+use crate::renderable::Object;
+use crate::scene::Scene;
+use crate::sphere::Sphere;
+use crate::texture::{RenderableTexture, SolidColor};
+use crate::tonemap::{ToneMapOperator, ToneMapping};
+use crate::util::{Interval, Point, Vec3};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    // the scene can't be rendered, or would render garbage (NaNs, missing objects)
+    Error,
+    // renders, but probably not the way it was meant to
+    Warning,
+}
+
+/**
+ * One thing wrong with a scene. `path` points at the offending value the way it's laid out in the scene's JSON,
+ * e.g. `world.objects[3].material.ir`.
+ */
+#[derive(Debug, Clone, PartialEq)]
+pub struct Issue {
+    pub severity: Severity,
+    pub path: String,
+    pub message: String,
+}
+
+impl fmt::Display for Issue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.path, self.message)
+    }
+}
+
+impl Issue {
+    pub fn is_error(&self) -> bool {
+        self.severity == Severity::Error
+    }
+}
+
+/**
+ * Checks everything about a scene that parsing can't: ranges, NaNs, settings that contradict each other and
+ * values that were only ever sanitized by constructors (a scene file skips those). Every issue is reported, not
+ * just the first one, in the order they appear in the file.
+ */
+pub fn validate_scene(scene: &Scene) -> Vec<Issue> {
+    let mut validator = Validator { issues: vec![] };
+    validator.scene(scene);
+    validator.issues
+}
+
+struct Validator {
+    issues: Vec<Issue>,
+}
+
+impl Validator {
+    fn error(&mut self, path: &str, message: String) {
+        self.issues.push(Issue { severity: Severity::Error, path: path.to_string(), message });
+    }
+
+    fn warning(&mut self, path: &str, message: String) {
+        self.issues.push(Issue { severity: Severity::Warning, path: path.to_string(), message });
+    }
+
+    // true when it's fine, so callers can skip checks that would only repeat the problem
+    fn finite(&mut self, path: &str, value: f32) -> bool {
+        if !value.is_finite() {
+            self.error(path, format!("{} is not a finite number", value));
+        }
+        value.is_finite()
+    }
+
+    fn finite_vector(&mut self, path: &str, value: Vec3) -> bool {
+        if !value.is_finite() {
+            self.error(path, format!("{} has a component that is not a finite number", value));
+        }
+        value.is_finite()
+    }
+
+    fn positive(&mut self, path: &str, value: f32) {
+        if self.finite(path, value) && value <= 0.0 {
+            self.error(path, format!("{} has to be greater than 0", value));
+        }
+    }
+
+    fn scene(&mut self, scene: &Scene) {
+        let size_is_valid = scene.image_width > 0 && scene.image_height > 0;
+        if scene.image_width <= 0 {
+            self.error("image_width", format!("{} has to be at least 1", scene.image_width));
+        }
+        if scene.image_height <= 0 {
+            self.error("image_height", format!("{} has to be at least 1", scene.image_height));
+        }
+        if scene.samples_per_pixel <= 0 && scene.adaptive_sampling.is_none() {
+            self.error(
+                "samples_per_pixel",
+                format!("{} has to be at least 1 unless adaptive_sampling is set", scene.samples_per_pixel),
+            );
+        }
+        self.camera("camera", &scene.camera, if size_is_valid { Some(scene) } else { None });
+        self.world("world", scene);
+        self.tone_mapping("tone_mapping", &scene.tone_mapping);
+        if let Some(adaptive_sampling) = &scene.adaptive_sampling {
+            self.adaptive_sampling("adaptive_sampling", adaptive_sampling);
+        }
+        self.filter("filter", &scene.filter);
+        if let Some(animation) = &scene.animation {
+            self.animation("animation", animation, scene.camera.description());
+        }
+    }
+
+    fn camera(&mut self, path: &str, camera: &Camera, sized: Option<&Scene>) {
+        let description = camera.description();
+        let mut is_valid = self.finite_vector(&format!("{path}.look_from"), description.look_from);
+        is_valid &= self.finite_vector(&format!("{path}.look_at"), description.look_at);
+        is_valid &= self.finite_vector(&format!("{path}.vup"), description.vup);
+        let view = description.look_from - description.look_at;
+        if is_valid && view.len_squared() == 0.0 {
+            self.error(&format!("{path}.look_at"), "is the same point as look_from, there's no view direction".to_string());
+        } else if is_valid && Vec3::cross(description.vup, view).len_squared() == 0.0 {
+            self.error(&format!("{path}.vup"), format!("{} is parallel to the view direction", description.vup));
+        }
+        let aperture_path = format!("{path}.aperture");
+        let aperture_is_finite = self.finite(&aperture_path, description.aperture);
+        if aperture_is_finite && description.aperture < 0.0 {
+            self.error(&aperture_path, format!("{} can't be negative", description.aperture));
+        }
+        match description.projection {
+            Projection::Perspective => {
+                let vfov_path = format!("{path}.vfov");
+                if self.finite(&vfov_path, description.vfov) && !(description.vfov > 0.0 && description.vfov < 180.0) {
+                    self.error(&vfov_path, format!("{} has to be between 0 and 180 degrees", description.vfov));
+                }
+            }
+            Projection::Orthographic { view_width } => {
+                self.positive(&format!("{path}.projection.view_width"), view_width);
+            }
+            Projection::Equirectangular => match (sized, description.stereo) {
+                (Some(scene), None) if scene.image_width != 2 * scene.image_height => {
+                    self.warning(
+                        "image_height",
+                        format!("equirectangular images are twice as wide as they're high, this one is {:.3}:1", scene.aspect_ratio()),
+                    );
+                }
+                (Some(scene), Some(stereo)) => {
+                    let eye_aspect_ratio = stereo.layout.eye_aspect_ratio(scene.aspect_ratio());
+                    if f32::abs(eye_aspect_ratio / 2.0 - 1.0) > 0.01 {
+                        self.warning(
+                            "image_height",
+                            format!("equirectangular eyes are twice as wide as they're high, these are {:.3}:1", eye_aspect_ratio),
+                        );
+                    }
+                }
+                _ => {}
+            },
+            Projection::Fisheye { fov, .. } => {
+                let fov_path = format!("{path}.projection.fov");
+                if self.finite(&fov_path, fov) && !(fov > 0.0 && fov <= 360.0) {
+                    self.error(&fov_path, format!("{} has to be more than 0 and at most 360 degrees", fov));
+                }
+            }
+        }
+        if description.projection != Projection::Perspective && aperture_is_finite && description.aperture > 0.0 {
+            self.warning(&aperture_path, "is ignored, only perspective cameras have depth of field".to_string());
+        } else if description.exposure.is_some() && aperture_is_finite && description.aperture > 0.0 {
+            self.warning(&aperture_path, "is ignored, the exposure's f_number sets the aperture".to_string());
+        }
+        self.aperture_shape(&format!("{path}.aperture_shape"), description);
+        if let Some(exposure) = description.exposure {
+            let exposure_path = format!("{path}.exposure");
+            self.positive(&format!("{exposure_path}.f_number"), exposure.f_number);
+            self.positive(&format!("{exposure_path}.shutter_time"), exposure.shutter_time);
+            self.positive(&format!("{exposure_path}.iso"), exposure.iso);
+            self.positive(&format!("{exposure_path}.sensor_height"), exposure.sensor_height);
+        }
+        if let Some(focus_distance) = description.focus_distance {
+            self.positive(&format!("{path}.focus_distance"), focus_distance);
+        }
+        let shutter = description.shutter;
+        let shutter_path = format!("{path}.shutter");
+        let open_is_finite = self.finite(&format!("{shutter_path}.open"), shutter.open);
+        let close_is_finite = self.finite(&format!("{shutter_path}.close"), shutter.close);
+        if open_is_finite && close_is_finite && shutter.close < shutter.open {
+            self.error(&shutter_path, format!("closes at {} before it opens at {}", shutter.close, shutter.open));
+        }
+        if let Some(stereo) = description.stereo {
+            self.stereo(&format!("{path}.stereo"), description, &stereo);
+        }
+        let aspect_ratio_path = format!("{path}.aspect_ratio");
+        let aspect_ratio = camera.aspect_ratio();
+        if self.finite(&aspect_ratio_path, aspect_ratio) && aspect_ratio <= 0.0 {
+            self.error(&aspect_ratio_path, format!("{} has to be greater than 0", aspect_ratio));
+        } else if let Some(scene) = sized {
+            let view_aspect_ratio = description.view_aspect_ratio(scene.aspect_ratio());
+            if f32::abs(aspect_ratio / view_aspect_ratio - 1.0) > 0.01 {
+                let view = if description.stereo.is_some() { "each eye's half of the image" } else { "the image" };
+                self.warning(
+                    &aspect_ratio_path,
+                    format!(
+                        "the viewport is {:.3}:1 but {} is {:.3}:1, the render will be stretched",
+                        aspect_ratio, view, view_aspect_ratio
+                    ),
+                );
+            }
+        }
+    }
+
+    fn aperture_shape(&mut self, path: &str, description: &CameraDescription) {
+        match description.aperture_shape {
+            ApertureShape::Circle => return,
+            ApertureShape::Polygon { blades, rotation } => {
+                if blades < 3 {
+                    self.error(&format!("{path}.blades"), format!("{} is too few, it takes at least 3", blades));
+                }
+                self.finite(&format!("{path}.rotation"), rotation);
+            }
+            // read and checked while loading
+            ApertureShape::Image(_) => {}
+        }
+        if description.projection != Projection::Perspective {
+            self.warning(path, "is ignored, only perspective cameras have depth of field".to_string());
+        }
+    }
+
+    fn stereo(&mut self, path: &str, description: &CameraDescription, stereo: &Stereo) {
+        if let Projection::Orthographic { .. } = description.projection {
+            self.error(path, "needs a perspective, equirectangular or fisheye camera, parallel rays see no depth".to_string());
+        }
+        let distance_path = format!("{path}.interpupillary_distance");
+        if self.finite(&distance_path, stereo.interpupillary_distance) && stereo.interpupillary_distance < 0.0 {
+            self.error(&distance_path, format!("{} can't be negative", stereo.interpupillary_distance));
+        } else if stereo.interpupillary_distance == 0.0 {
+            self.warning(&distance_path, "is 0, both eyes will see the same image".to_string());
+        }
+        if let Some(convergence_distance) = stereo.convergence_distance {
+            let convergence_path = format!("{path}.convergence_distance");
+            self.positive(&convergence_path, convergence_distance);
+            if let Projection::Fisheye { .. } = description.projection {
+                self.warning(&convergence_path, "is ignored, fisheye eyes look straight ahead".to_string());
+            }
+        }
+    }
+
+    fn world(&mut self, path: &str, scene: &Scene) {
+        for (index, object) in scene.world.objects.iter().enumerate() {
+            let path = format!("{path}.objects[{index}]");
+            match object {
+                Object::Sphere(sphere) => self.sphere(&path, sphere),
+                Object::AABB(aabb) => {
+                    self.aabb(&path, aabb);
+                }
+            }
+        }
+    }
+
+    fn sphere(&mut self, path: &str, sphere: &Sphere) {
+        let center_is_finite = self.finite_vector(&format!("{path}.center"), sphere.center);
+        let radius_path = format!("{path}.r");
+        let radius_is_valid = self.finite(&radius_path, sphere.r) && sphere.r > 0.0;
+        if sphere.r.is_finite() && !radius_is_valid {
+            self.error(&radius_path, format!("radius {} has to be greater than 0", sphere.r));
+        }
+        let mut motion_is_valid = self.finite_vector(&format!("{path}.center_vec"), sphere.center_vec);
+        if let Some(motion) = &sphere.motion {
+            motion_is_valid &= self.motion(&format!("{path}.motion"), motion);
+            if sphere.is_moving {
+                self.warning(&format!("{path}.is_moving"), "is ignored, the sphere is moved by its keyframes".to_string());
+            }
+        }
+        let bbox_is_valid = self.aabb(&format!("{path}.bbox"), &sphere.bbox);
+        // the BVH only looks at the stored box, anything of the sphere outside of it is never hit
+        if center_is_finite && radius_is_valid && motion_is_valid && bbox_is_valid {
+            let radius_vec = Vec3::new(sphere.r, sphere.r, sphere.r);
+            let sphere_box = |center: Point| AABB::new_from_pts(center - radius_vec, center + radius_vec);
+            let needed = match &sphere.motion {
+                Some(motion) => motion.sphere_bounds(sphere.center, sphere.r),
+                None if sphere.is_moving => AABB::new_from_bbox(sphere_box(sphere.center), sphere_box(sphere.center + sphere.center_vec)),
+                None => sphere_box(sphere.center),
+            };
+            if !contains(&sphere.bbox, &needed) {
+                self.error(
+                    &format!("{path}.bbox"),
+                    "doesn't enclose the sphere everywhere it moves, the parts outside of it won't be rendered".to_string(),
+                );
+            }
+        }
+        self.material(&format!("{path}.material"), &sphere.material);
+    }
+
+    // true when every key is usable and they're in order
+    fn motion(&mut self, path: &str, motion: &Motion) -> bool {
+        if motion.keys.is_empty() {
+            self.error(&format!("{path}.keys"), "has to have at least one key".to_string());
+            return false;
+        }
+        let mut is_valid = true;
+        for (index, key) in motion.keys.iter().enumerate() {
+            let path = format!("{path}.keys[{index}]");
+            let time_path = format!("{path}.time");
+            is_valid &= self.finite(&time_path, key.time);
+            if index > 0 && key.time < motion.keys[index - 1].time {
+                self.error(&time_path, format!("{} is before the time of the key in front of it", key.time));
+                is_valid = false;
+            }
+            is_valid &= self.finite_vector(&format!("{path}.translation"), key.translation);
+            is_valid &= self.finite_vector(&format!("{path}.rotation.axis"), key.rotation.axis);
+            is_valid &= self.finite(&format!("{path}.rotation.degrees"), key.rotation.degrees);
+            if key.rotation.degrees != 0.0 && key.rotation.axis.len_squared() == 0.0 {
+                self.error(&format!("{path}.rotation.axis"), "can't be zero, there's nothing to rotate around".to_string());
+                is_valid = false;
+            }
+        }
+        is_valid
+    }
+
+    fn animation(&mut self, path: &str, animation: &Animation, camera: &CameraDescription) {
+        if animation.frames == 0 {
+            self.error(&format!("{path}.frames"), "has to be at least 1".to_string());
+        }
+        let frame_time_path = format!("{path}.frame_time");
+        if self.finite(&frame_time_path, animation.frame_time) && animation.frame_time < 0.0 {
+            self.error(&frame_time_path, format!("{} can't be negative", animation.frame_time));
+        }
+        let mut is_valid = true;
+        for (index, key) in animation.camera_keys.iter().enumerate() {
+            let path = format!("{path}.camera_keys[{index}]");
+            let frame_path = format!("{path}.frame");
+            is_valid &= self.finite(&frame_path, key.frame);
+            if index > 0 && key.frame <= animation.camera_keys[index - 1].frame {
+                self.error(&frame_path, format!("{} isn't after the frame of the key in front of it", key.frame));
+                is_valid = false;
+            } else if key.frame < 1.0 || key.frame > animation.frames as f32 {
+                self.warning(&frame_path, format!("{} is outside of the animation's frames 1 to {}", key.frame, animation.frames));
+            }
+            if let Some(look_from) = key.look_from {
+                is_valid &= self.finite_vector(&format!("{path}.look_from"), look_from);
+            }
+            if let Some(look_at) = key.look_at {
+                is_valid &= self.finite_vector(&format!("{path}.look_at"), look_at);
+            }
+            if let Some(vfov) = key.vfov {
+                let vfov_path = format!("{path}.vfov");
+                if self.finite(&vfov_path, vfov) && !(vfov > 0.0 && vfov < 180.0) {
+                    self.error(&vfov_path, format!("{} has to be between 0 and 180 degrees", vfov));
+                }
+            }
+            if let Some(focus_distance) = key.focus_distance {
+                self.positive(&format!("{path}.focus_distance"), focus_distance);
+            }
+        }
+        if !is_valid {
+            return;
+        }
+        // the keys can still take the camera somewhere it can't look from, only the first frame that does is reported
+        for frame in 1..=animation.frames {
+            let camera = animation.camera_at(camera, frame);
+            let view = camera.look_from - camera.look_at;
+            if view.len_squared() == 0.0 {
+                self.error(&format!("{path}.camera_keys"), format!("frame {} looks from and at the same point", frame));
+                return;
+            } else if Vec3::cross(camera.vup, view).len_squared() == 0.0 {
+                self.error(&format!("{path}.camera_keys"), format!("frame {} looks along vup", frame));
+                return;
+            }
+        }
+    }
+
+    // true when all three intervals are finite and not reversed
+    fn aabb(&mut self, path: &str, aabb: &AABB) -> bool {
+        let intervals = [("x_interval", aabb.x_interval), ("y_interval", aabb.y_interval), ("z_interval", aabb.z_interval)];
+        let mut is_valid = true;
+        for (name, interval) in intervals {
+            let path = format!("{path}.{name}");
+            if !interval.min.is_finite() || !interval.max.is_finite() {
+                self.error(&path, format!("[{}, {}] is not finite", interval.min, interval.max));
+                is_valid = false;
+            } else if interval.min > interval.max {
+                self.error(&path, format!("min {} is greater than max {}", interval.min, interval.max));
+                is_valid = false;
+            }
+        }
+        is_valid
+    }
+
+    fn material(&mut self, path: &str, material: &RenderableMaterial) {
+        match material {
+            RenderableMaterial::Lambertian(lambertian) => self.texture(&format!("{path}.albedo"), &lambertian.albedo),
+            RenderableMaterial::Metal(metal) => {
+                self.texture(&format!("{path}.albedo"), &metal.albedo);
+                let fuzziness_path = format!("{path}.fuzziness");
+                if self.finite(&fuzziness_path, metal.fuzziness) && !(0.0..1.0).contains(&metal.fuzziness) {
+                    // Metal::new would have quietly turned this into a perfect mirror
+                    self.warning(
+                        &fuzziness_path,
+                        format!("{} is outside of [0, 1), most rays will scatter into the surface", metal.fuzziness),
+                    );
+                }
+            }
+            RenderableMaterial::Dielectric(dielectric) => self.positive(&format!("{path}.ir"), dielectric.ir),
+        }
+    }
+
+    fn texture(&mut self, path: &str, texture: &RenderableTexture) {
+        match texture {
+            RenderableTexture::SolidColor(solid) => self.color(path, solid),
+            RenderableTexture::CheckerTexture(checker) => {
+                self.color(&format!("{path}.odd"), &checker.odd);
+                self.color(&format!("{path}.even"), &checker.even);
+                let inv_scale_path = format!("{path}.inv_scale");
+                // a scale of 0 turns into an infinite inv_scale
+                if !checker.inv_scale.is_finite() {
+                    self.error(&inv_scale_path, format!("{} is not finite, the checker scale can't be 0", checker.inv_scale));
+                } else if checker.inv_scale == 0.0 {
+                    self.warning(&inv_scale_path, "is 0, the whole checker will be one color".to_string());
+                }
+            }
+        }
+    }
+
+    fn color(&mut self, path: &str, solid: &SolidColor) {
+        let path = format!("{path}.color");
+        if !self.finite_vector(&path, solid.color) {
+            return;
+        }
+        let components = [solid.color.x(), solid.color.y(), solid.color.z()];
+        if components.iter().any(|c| *c < 0.0) {
+            self.error(&path, format!("{} has a negative component", solid.color));
+        } else if components.iter().any(|c| *c > 1.0) {
+            self.warning(&path, format!("{} reflects more light than it receives", solid.color));
+        }
+    }
+
+    fn tone_mapping(&mut self, path: &str, tone_mapping: &ToneMapping) {
+        self.finite(&format!("{path}.exposure"), tone_mapping.exposure);
+        if let ToneMapOperator::Reinhard { white_point } = tone_mapping.operator {
+            self.positive(&format!("{path}.operator.white_point"), white_point);
+        }
+    }
+
+    fn adaptive_sampling(&mut self, path: &str, adaptive_sampling: &AdaptiveSampling) {
+        let (min_samples, max_samples) = adaptive_sampling.sample_range();
+        if adaptive_sampling.min_samples != min_samples {
+            self.warning(
+                &format!("{path}.min_samples"),
+                format!("{} is raised to {}, the noise estimate needs 2 samples", adaptive_sampling.min_samples, min_samples),
+            );
+        }
+        if adaptive_sampling.max_samples != max_samples {
+            self.warning(
+                &format!("{path}.max_samples"),
+                format!("{} is below min_samples and is raised to {}", adaptive_sampling.max_samples, max_samples),
+            );
+        }
+        let threshold_path = format!("{path}.threshold");
+        if self.finite(&threshold_path, adaptive_sampling.threshold) && adaptive_sampling.threshold <= 0.0 {
+            self.warning(
+                &threshold_path,
+                format!("{} can never be reached, every pixel gets max_samples", adaptive_sampling.threshold),
+            );
+        }
+    }
+
+    fn filter(&mut self, path: &str, filter: &Filter) {
+        let radius_path = format!("{path}.radius");
+        self.positive(&radius_path, filter.radius());
+        match filter {
+            // narrower than half a pixel, samples between the boxes would be thrown away
+            Filter::Box { radius } if *radius > 0.0 && *radius < 0.5 => {
+                self.warning(&radius_path, format!("{} is less than 0.5, some samples won't reach any pixel", radius));
+            }
+            Filter::Gaussian { sigma, .. } => self.positive(&format!("{path}.sigma"), *sigma),
+            Filter::Mitchell { b, c, .. } => {
+                self.finite(&format!("{path}.b"), *b);
+                self.finite(&format!("{path}.c"), *c);
+            }
+            Filter::Lanczos { tau, .. } => self.positive(&format!("{path}.tau"), *tau),
+            _ => {}
+        }
+    }
+}
+
+// whether `inner` fits in `outer`, give or take rounding
+fn contains(outer: &AABB, inner: &AABB) -> bool {
+    let axes = [(outer.x_interval, inner.x_interval), (outer.y_interval, inner.y_interval), (outer.z_interval, inner.z_interval)];
+    axes.iter().all(|(outer, inner): &(Interval, Interval)| {
+        let tolerance = 1e-4 * f32::max(1.0, f32::max(f32::abs(inner.min), f32::abs(inner.max)));
+        outer.min <= inner.min + tolerance && inner.max - tolerance <= outer.max
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::material::{Dielectric, LambertianMaterial, Metal};
+    use crate::scene::default_scene;
+    use crate::texture::CheckerTexture;
+    use crate::util::Color;
+
+    fn paths(issues: &[Issue]) -> Vec<&str> {
+        issues.iter().map(|issue| issue.path.as_str()).collect()
+    }
+
+    #[test]
+    fn default_scene_is_valid() {
+        assert_eq!(validate_scene(&default_scene()), vec![]);
+    }
+
+    #[test]
+    fn every_problem_is_reported_with_its_path() {
+        let mut scene = default_scene();
+        scene.samples_per_pixel = 0;
+        scene.image_height = 300;
+        let checker = CheckerTexture::new_from_colors(0.0, Color::new(0.5, 0.5, 0.5), Color::new(f32::NAN, 0.0, 0.0));
+        let lambertian = RenderableMaterial::Lambertian(LambertianMaterial::new(RenderableTexture::CheckerTexture(checker)));
+        let mut metal = Metal::new(RenderableTexture::SolidColor(SolidColor::from_values(0.8, 0.8, 0.8)), None);
+        metal.fuzziness = 1.5;
+        scene.world.add(Object::Sphere(Sphere::new(Point::new(0.0, 0.0, 0.0), -1.0, lambertian)));
+        scene.world.add(Object::Sphere(Sphere::new(Point::new(0.0, 2.0, 0.0), 1.0, RenderableMaterial::Metal(metal))));
+        scene.world.add(Object::Sphere(Sphere::new(
+            Point::new(0.0, 4.0, 0.0),
+            1.0,
+            RenderableMaterial::Dielectric(Dielectric::new(Some(0.0))),
+        )));
+
+        let issues = validate_scene(&scene);
+        assert_eq!(
+            paths(&issues),
+            vec![
+                "samples_per_pixel",
+                "camera.aspect_ratio",
+                "world.objects[1].r",
+                "world.objects[1].material.albedo.even.color",
+                "world.objects[1].material.albedo.inv_scale",
+                "world.objects[2].material.fuzziness",
+                "world.objects[3].material.ir",
+            ]
+        );
+        let errors: Vec<bool> = issues.iter().map(Issue::is_error).collect();
+        assert_eq!(errors, vec![true, false, true, true, true, false, true]);
+    }
+
+    #[test]
+    fn keyframes_have_to_be_in_order() {
+        use crate::motion::{Keyframe, Motion, Rotation};
+
+        let mut scene = default_scene();
+        let key = |time: f32| Keyframe { time, translation: Vec3::zero(), rotation: Rotation::default() };
+        let motion = Motion { keys: vec![key(0.0), key(1.0), key(0.5)] };
+        let material = scene.world.objects[0].material().unwrap();
+        scene.world.add(Object::Sphere(Sphere::new_keyframed(Point::new(0.0, 0.0, 0.0), 1.0, material, motion)));
+        assert_eq!(paths(&validate_scene(&scene)), vec!["world.objects[1].motion.keys[2].time"]);
+    }
+
+    #[test]
+    fn stereo_needs_eyes_that_see_depth() {
+        use crate::camera::{Camera, Stereo, StereoLayout};
+
+        let mut scene = default_scene();
+        scene.image_width = 800;
+        let mut description = scene.camera.description().clone();
+        description.aspect_ratio = None;
+        description.stereo =
+            Some(Stereo { layout: StereoLayout::SideBySide, interpupillary_distance: 0.064, convergence_distance: None });
+        // each eye gets a square half of the image, like the camera had before
+        scene.camera = Camera::from_description(description.clone(), scene.aspect_ratio());
+        assert_eq!(validate_scene(&scene), vec![]);
+
+        description.projection = Projection::Orthographic { view_width: 4.0 };
+        description.stereo = Some(Stereo {
+            layout: StereoLayout::TopBottom,
+            interpupillary_distance: -0.1,
+            convergence_distance: Some(0.0),
+        });
+        scene.camera = Camera::from_description(description, scene.aspect_ratio());
+        let issues = validate_scene(&scene);
+        assert_eq!(
+            paths(&issues),
+            vec![
+                "camera.aperture",
+                "camera.stereo",
+                "camera.stereo.interpupillary_distance",
+                "camera.stereo.convergence_distance",
+            ]
+        );
+        assert!(issues[1..].iter().all(Issue::is_error));
+    }
+
+    #[test]
+    fn camera_keys_are_checked_for_every_frame() {
+        use crate::animation::{Animation, CameraKeyframe, Interpolation};
+
+        let mut scene = default_scene();
+        let look_at = scene.camera.description().look_at;
+        let key = |frame: f32, look_from: Point| CameraKeyframe {
+            frame,
+            look_from: Some(look_from),
+            look_at: None,
+            vfov: None,
+            focus_distance: None,
+        };
+        // the camera flies straight through look_at at frame 6
+        scene.animation = Some(Animation {
+            frames: 10,
+            frame_time: 1.0,
+            interpolation: Interpolation::Linear,
+            camera_keys: vec![key(1.0, look_at + Vec3::new(5.0, 0.0, 0.0)), key(11.0, look_at - Vec3::new(5.0, 0.0, 0.0))],
+        });
+        let issues = validate_scene(&scene);
+        assert_eq!(paths(&issues), vec!["animation.camera_keys[1].frame", "animation.camera_keys"]);
+        assert!(!issues[0].is_error() && issues[1].is_error());
+        assert!(issues[1].message.starts_with("frame 6 "));
+    }
+
+    #[test]
+    fn stale_bounding_boxes_are_errors() {
+        let mut scene = default_scene();
+        if let Object::Sphere(sphere) = &mut scene.world.objects[0] {
+            sphere.center = Point::new(10.0, 0.0, 0.0);
+        }
+        let issues = validate_scene(&scene);
+        assert_eq!(paths(&issues), vec!["world.objects[0].bbox"]);
+        assert!(issues[0].is_error());
+    }
+}
@@ -0,0 +1,58 @@
+use std::fs;
+use std::io::{BufRead, BufReader};
+use std::process::{Child, Command, Stdio};
+use std::thread;
+
+const RAYTRACER: &str = env!("CARGO_BIN_EXE_raytracer");
+
+// a `raytracer worker` process on a free local port, and the address it ended up listening on
+fn spawn_worker(threads: usize) -> (Child, String) {
+    let mut worker = Command::new(RAYTRACER)
+        .args(["worker", "--listen", "127.0.0.1:0", "--threads", &threads.to_string()])
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    let mut stderr = BufReader::new(worker.stderr.take().unwrap());
+    let mut line = String::new();
+    stderr.read_line(&mut line).unwrap();
+    // "worker listening on 127.0.0.1:<port> with <threads> threads"
+    let address = line.split_whitespace().nth(3).unwrap().to_string();
+    // keep reading, a worker writing into a full pipe would stop
+    thread::spawn(move || stderr.lines().for_each(drop));
+    (worker, address)
+}
+
+fn raytracer(args: &[&str]) {
+    let status = Command::new(RAYTRACER).args(args).stderr(Stdio::null()).status().unwrap();
+    assert!(status.success(), "raytracer {} failed", args.join(" "));
+}
+
+#[test]
+fn worker_processes_render_the_same_image_as_a_local_render() {
+    let directory = std::env::temp_dir().join(format!("raytracer-worker-processes-{}", std::process::id()));
+    fs::create_dir_all(&directory).unwrap();
+    let path = |name: &str| directory.join(name).to_str().unwrap().to_string();
+    let scene = path("scene.json");
+    raytracer(&["generate", "two-spheres-checker", "-o", &scene]);
+    // small enough for a debug build, but still several tiles across
+    let mut json: serde_json::Value = serde_json::from_str(&fs::read_to_string(&scene).unwrap()).unwrap();
+    json["image_width"] = 40.into();
+    json["image_height"] = 24.into();
+    json["samples_per_pixel"] = 2.into();
+    json["adaptive_sampling"] = serde_json::Value::Null;
+    fs::write(&scene, json.to_string()).unwrap();
+
+    let mut workers = [spawn_worker(2), spawn_worker(1)];
+    let addresses: Vec<&str> = workers.iter().map(|(_, address)| address.as_str()).collect();
+    let (local, distributed) = (path("local.pfm"), path("distributed.pfm"));
+    raytracer(&["render", &scene, "-o", &local, "--bvh", "--seed", "3"]);
+    raytracer(&["render", &scene, "-o", &distributed, "--bvh", "--seed", "3", "--workers", &addresses.join(",")]);
+    for (worker, _) in workers.iter_mut() {
+        worker.kill().unwrap();
+        worker.wait().unwrap();
+    }
+
+    // PFM is the raw floats, so this is the exact same radiance
+    assert_eq!(fs::read(&local).unwrap(), fs::read(&distributed).unwrap());
+    fs::remove_dir_all(&directory).unwrap();
+}